@@ -85,6 +85,111 @@ pub enum VaultError {
     
     #[error("Numerical Overflow")]
     NumericalOverflow,
+    
+    #[error("Vault Is Close-Only")]
+    VaultCloseOnly,
+    
+    #[error("Invalid Loss Limit")]
+    InvalidLossLimit,
+    
+    #[error("Leverage Limit Exceeded")]
+    LeverageExceeded,
+    
+    #[error("Invalid Max Leverage")]
+    InvalidMaxLeverage,
+    
+    #[error("Insufficient Delegate Allocation")]
+    InsufficientAllocation,
+    
+    #[error("Insufficient Locked Margin")]
+    InsufficientLockedMargin,
+    #[error("Insufficient Reserve Collateral")]
+    InsufficientReserve,
+    
+    #[error("Vault Notional Limit Exceeded")]
+    VaultNotionalLimitExceeded,
+    
+    #[error("Invalid Delegate Registry Account")]
+    InvalidRegistryAccount,
+    
+    #[error("Too Many Delegates")]
+    TooManyDelegates,
+    
+    #[error("Invalid Delegate Label")]
+    InvalidLabel,
+    
+    #[error("Invalid Max Delegates")]
+    InvalidMaxDelegates,
+    
+    #[error("Delegate Has Active Sub-Delegates")]
+    DelegateHasSubdelegates,
+    
+    #[error("Delegate Has Open Positions")]
+    DelegateHasOpenPositions,
+    #[error("Invalid Expiry Timestamp")]
+    InvalidExpiryTimestamp,
+    
+    #[error("Delegate Heartbeat Missed")]
+    HeartbeatMissed,
+    
+    #[error("Invalid Heartbeat Interval")]
+    InvalidHeartbeatInterval,
+    
+    #[error("Caller Program Not Allowed")]
+    ProgramNotAllowed,
+    
+    #[error("Too Many Allowed Programs")]
+    TooManyAllowedPrograms,
+    
+    #[error("Market Not Allowed")]
+    MarketNotAllowed,
+    
+    #[error("Invalid Ed25519 Signature")]
+    InvalidSignature,
+    
+    #[error("Intent Expired")]
+    IntentExpired,
+    
+    #[error("Invalid Intent Nonce")]
+    InvalidIntentNonce,
+    
+    #[error("Permit Expired")]
+    PermitExpired,
+    
+    #[error("Invalid Permit Nonce")]
+    InvalidPermitNonce,
+    
+    #[error("Instruction Not Allowed In Permit")]
+    InvalidPermitInstruction,
+    
+    #[error("Invalid Rent Payer")]
+    InvalidRentPayer,
+    #[error("Delegate Deposit Limit Exceeded")]
+    DepositLimitExceeded,
+    
+    #[error("Invalid Deposit Limit")]
+    InvalidDepositLimit,
+    
+    #[error("Withdrawal Request Already Pending")]
+    WithdrawalAlreadyPending,
+    
+    #[error("No Earmarked Funds To Withdraw")]
+    NoEarmarkedFunds,
+    
+    #[error("Withdrawal Amount Requires Timelock")]
+    WithdrawalTimelockRequired,
+    
+    #[error("Invalid Withdrawal Timelock")]
+    InvalidWithdrawalTimelock,
+    
+    #[error("Pending Withdrawal Already Exists")]
+    PendingWithdrawalExists,
+    
+    #[error("Withdrawal Timelock Not Expired")]
+    TimelockNotExpired,
+    
+    #[error("Owner Co-Signature Required")]
+    OwnerCosignRequired,
 }
 
 impl From<VaultError> for ProgramError {
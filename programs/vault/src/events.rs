@@ -0,0 +1,127 @@
+//! Vault Program Event Definitions
+//! 
+//! 结构化事件：borsh 编码后通过 sol_log_data 输出，供索引器解析
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{log::sol_log_data, pubkey::Pubkey};
+
+use crate::state::DELEGATE_LABEL_LEN;
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub enum VaultEvent {
+    /// Delegate 创建或更新（UpsertDelegate）
+    DelegateUpserted {
+        vault: Pubkey,
+        delegate: Pubkey,
+        permissions: u64,
+        label: [u8; DELEGATE_LABEL_LEN],
+        strategy_id: u64,
+    },
+    
+    /// Delegate 元数据更新（SetDelegateMetadata）
+    DelegateMetadataUpdated {
+        vault: Pubkey,
+        delegate: Pubkey,
+        label: [u8; DELEGATE_LABEL_LEN],
+        strategy_id: u64,
+    },
+    
+    /// Delegate 被 owner 撤销（RevokeDelegate）
+    DelegateRevoked {
+        vault: Pubkey,
+        delegate: Pubkey,
+        label: [u8; DELEGATE_LABEL_LEN],
+        strategy_id: u64,
+    },
+    
+    /// Delegate 自行撤销（SelfRevokeDelegate），通知 owner 该 API Key 已失效
+    DelegateSelfRevoked {
+        owner: Pubkey,
+        vault: Pubkey,
+        delegate: Pubkey,
+        label: [u8; DELEGATE_LABEL_LEN],
+        strategy_id: u64,
+    },
+    
+    /// 子 delegate 创建（CreateSubDelegate）
+    SubDelegateCreated {
+        vault: Pubkey,
+        parent: Pubkey,
+        delegate: Pubkey,
+        permissions: u64,
+        expiry_slot: u64,
+    },
+    
+    /// Delegate 密钥轮换（RotateDelegate）
+    DelegateRotated {
+        vault: Pubkey,
+        old_delegate: Pubkey,
+        new_delegate: Pubkey,
+    },
+    
+    /// 存款（Deposit / DepositFor），depositor 为实际出资的签名者
+    Deposited {
+        vault: Pubkey,
+        owner: Pubkey,
+        depositor: Pubkey,
+        amount: u64,
+    },
+    
+    /// Vault 间划转（TransferBetweenVaults），signer 为 owner 或 delegate
+    VaultTransfer {
+        from_vault: Pubkey,
+        to_vault: Pubkey,
+        signer: Pubkey,
+        amount: u64,
+    },
+    
+    /// 提款请求创建（RequestWithdrawal），earmarked 为立即预留的金额
+    WithdrawalRequested {
+        vault: Pubkey,
+        amount: u64,
+        earmarked: u64,
+    },
+    
+    /// 提款请求支付（ExecuteWithdrawal），remaining 为剩余未支付金额
+    WithdrawalExecuted {
+        vault: Pubkey,
+        amount: u64,
+        remaining: u64,
+    },
+    
+    /// 大额提款进入时间锁（ScheduleWithdrawal）
+    WithdrawalScheduled {
+        vault: Pubkey,
+        initiator: Pubkey,
+        amount: u64,
+        unlock_slot: u64,
+    },
+    
+    /// 时间锁到期后提款完成（ExecutePendingWithdrawal）
+    PendingWithdrawalExecuted {
+        vault: Pubkey,
+        amount: u64,
+    },
+    
+    /// Owner 在时间锁期间取消提款（CancelPendingWithdrawal）
+    PendingWithdrawalCancelled {
+        vault: Pubkey,
+        amount: u64,
+    },
+    
+    /// Delegate 调用方程序白名单更新（SetDelegatePrograms）
+    DelegateProgramsUpdated {
+        vault: Pubkey,
+        delegate: Pubkey,
+        programs: Vec<Pubkey>,
+    },
+}
+
+impl VaultEvent {
+    /// 输出事件日志
+    pub fn emit(&self) {
+        if let Ok(data) = self.try_to_vec() {
+            sol_log_data(&[&data]);
+        }
+    }
+}
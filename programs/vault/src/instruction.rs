@@ -3,6 +3,8 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::pubkey::Pubkey;
 
+use crate::state::DELEGATE_LABEL_LEN;
+
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub enum VaultInstruction {
     /// 初始化全局配置（仅一次，由管理员调用）
@@ -12,6 +14,7 @@ pub enum VaultInstruction {
     /// 1. `[signer, writable]` Admin - 管理员，支付租金
     /// 2. `[]` System Program
     /// 3. `[]` Rent Sysvar
+    /// 4. `[signer, writable, optional]` Payer - 代付租金（不提供时由 Admin 支付）
     InitializeGlobalConfig {
         usdc_mint: Pubkey,
     },
@@ -27,6 +30,7 @@ pub enum VaultInstruction {
     /// 5. `[]` System Program
     /// 6. `[]` Token Program
     /// 7. `[]` Rent Sysvar
+    /// 8. `[signer, writable, optional]` Payer - 代付租金（不提供时由 Owner 支付），记录在 UserVault.rent_payer
     CreateVault,
     
     /// 存款：用户钱包 → Vault
@@ -44,6 +48,10 @@ pub enum VaultInstruction {
     
     /// 提款：Vault → 用户钱包
     /// 
+    /// 只能提取 free_collateral，储备金需由 owner 先 ReleaseReserve。
+    /// 超过 withdrawal_timelock_threshold 的金额需改用 ScheduleWithdrawal。
+    /// delegate 提款超过其 cosign_threshold 时，owner 必须同时签名。
+    /// 
     /// Accounts:
     /// 0. `[writable]` UserVault PDA
     /// 1. `[signer]` Signer - Owner 或有 PERM_WITHDRAW 权限的 delegate
@@ -52,56 +60,100 @@ pub enum VaultInstruction {
     /// 4. `[]` GlobalConfig PDA
     /// 5. `[]` Token Program
     /// 6. `[writable, optional]` DelegateAccount PDA - 如果 signer 是 delegate
+    /// 7. `[optional]` Parent DelegateAccount PDA - 如果 signer 是子 delegate
+    /// 8. `[optional]` Instructions Sysvar - 仅 WithdrawWithIntent 需要（没有父 delegate 时可直接放在 7）
+    /// 9. `[signer, optional]` Owner - 超过 cosign_threshold 时附加在最后
     Withdraw {
         amount: u64,
     },
     
     /// 添加/更新 API Key（Delegate）
     /// 
+    /// max_leverage: 最大杠杆倍数（notional / margin），0 表示不限制
+    /// label: 标签（UTF-8，末尾 0 填充，不能含控制字符），写入 DelegateAccount 并记录到 DelegateRegistry
+    /// not_before_slot: 生效 slot（0 表示立即生效），必须早于 expiry_slot
+    /// expiry_unix_ts: 过期 Unix 时间戳（秒，0 表示不使用）。设置后 expiry_slot 可传 0
+    ///   表示不按 slot 过期；两者都设置时同时生效
+    /// heartbeat_interval_secs: 心跳间隔（秒，0 表示不要求心跳）。超过间隔未调用
+    ///   Heartbeat 的 delegate 不能 LockMargin，但仍可平仓
+    /// allowed_markets: 允许开仓的市场位图（第 i 位对应 market_id = i，0 表示不限制）
+    /// 
+    /// 对子 delegate 的 key 调用时将其转为 owner 直接授权（需先平掉全部仓位）。
+    /// 
     /// Accounts:
     /// 0. `[writable]` DelegateAccount PDA - 将被创建或更新
     /// 1. `[writable]` UserVault PDA
     /// 2. `[signer, writable]` Owner - 用户主钱包，支付租金（如果创建）
     /// 3. `[]` GlobalConfig PDA
     /// 4. `[]` System Program
+    /// 5. `[writable]` DelegateRegistry PDA - 不存在时创建，新增记录时 realloc
+    /// 6. `[signer, writable, optional]` Payer - 代付租金（不提供时由 Owner 支付），
+    ///    新建时记录在 DelegateAccount.rent_payer
     UpsertDelegate {
         delegate_pubkey: Pubkey,
         permissions: u64,
         max_notional: u64,
         expiry_slot: u64,
+        max_leverage: u64,
+        label: [u8; DELEGATE_LABEL_LEN],
+        not_before_slot: u64,
+        expiry_unix_ts: i64,
+        heartbeat_interval_secs: u64,
+        allowed_markets: u128,
     },
     
     /// 撤销 API Key（Delegate）
     /// 
+    /// 同时作废该 delegate 创建的所有子 delegate。
+    /// 如需一次性撤销全部 API Key，使用 RevokeAllDelegates。
+    /// 
     /// Accounts:
     /// 0. `[writable]` DelegateAccount PDA
     /// 1. `[writable]` UserVault PDA
     /// 2. `[signer]` Owner - 用户主钱包
     /// 3. `[]` GlobalConfig PDA
+    /// 4. `[writable]` DelegateRegistry PDA
     RevokeDelegate {
         delegate_pubkey: Pubkey,
     },
     
     /// 锁定保证金（由业务程序 CPI 调用）
     /// 
+    /// Owner 从未分配的 free_collateral 中锁定；delegate 只能从自己的分配额度中锁定。
+    /// 子 delegate 锁定时，父 delegate 当前的 PERM_TRADE、杠杆、名义敞口、调用方程序与市场白名单和心跳同样适用。
+    /// market_id: 业务程序定义的市场编号，delegate 设置了 allowed_markets 时必须在白名单内。
+    /// 
     /// Accounts:
     /// 0. `[writable]` UserVault PDA
     /// 1. `[signer]` Signer - Owner 或有 PERM_TRADE 权限的 delegate
     /// 2. `[writable, optional]` DelegateAccount PDA - 如果 signer 是 delegate
     /// 3. `[]` GlobalConfig PDA
     /// 4. `[]` Clock Sysvar
+    /// 5. `[writable, optional]` Parent DelegateAccount PDA - 如果 signer 是子 delegate
+    /// 6. `[optional]` Instructions Sysvar - delegate 设置了 allowed_programs 或使用
+    ///    LockMarginWithIntent 时必须提供（没有父 delegate 时可直接放在 5）
     LockMargin {
         required_margin: u64,
         required_notional: u64,
+        market_id: u16,
     },
     
     /// 解锁保证金并更新 PnL（由业务程序 CPI 调用）
     /// 
+    /// 保证金和 PnL 结算到锁定它的一方：传入 DelegateAccount 时结算到该 delegate
+    /// 的分配额度，否则结算到 owner 的 free_collateral。
+    /// Owner 可以传入 DelegateAccount 代为平仓/清算 delegate 的仓位。
+    /// 亏损超过可用额度的部分由 vault 的 free_collateral 吸收，不会导致平仓失败。
+    /// 
     /// Accounts:
     /// 0. `[writable]` UserVault PDA
     /// 1. `[signer]` Signer - Owner 或有 PERM_TRADE 权限的 delegate
-    /// 2. `[writable, optional]` DelegateAccount PDA - 如果 signer 是 delegate
+    /// 2. `[writable, optional]` DelegateAccount PDA - 仓位由 delegate 开立时必须提供
+    ///    （owner 结算自己的仓位时传入 Program ID 占位）
     /// 3. `[]` GlobalConfig PDA
+    /// 4. `[writable, optional]` Parent DelegateAccount PDA - 如果 signer 是子 delegate
+    /// 5. `[optional]` Instructions Sysvar - delegate 设置了 allowed_programs 时必须提供
+    ///    （没有父 delegate 时可直接放在 4）
     UnlockMarginAndUpdatePnl {
         unlocked_margin: u64,
         pnl_delta: i64,          // 正数为盈利，负数为亏损
@@ -137,5 +189,457 @@ pub enum VaultInstruction {
     /// 0. `[writable]` UserVault PDA
     /// 1. `[signer]` Owner
     UnfreezeVault,
+    
+    /// 迁移旧版本账户（任何人可调用）
+    /// 
+    /// 将旧布局的 UserVault 或 DelegateAccount realloc 到当前大小并写入当前 version。
+    /// 新增字段占用原 reserved 区域或追加在其后，按默认值初始化；已是当前版本时不做修改。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault 或 DelegateAccount PDA
+    /// 1. `[signer, writable]` Payer - 补足扩容所需租金
+    /// 2. `[]` System Program
+    MigrateAccount,
+    
+    /// 设置 vault 级别亏损熔断（仅 owner 可调用）
+    /// 
+    /// 窗口内 delegate 累计净亏损超过 loss_limit 后，vault 进入只平仓状态，
+    /// delegate 的 LockMargin 将被拒绝，直到 owner 调用 ResumeTrading。
+    /// loss_limit 为 0 表示关闭该功能。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    SetLossLimit {
+        loss_limit: u64,
+        window_slots: u64,
+    },
+    
+    /// 恢复交易：解除亏损熔断（仅 owner 可调用）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    ResumeTrading,
+    
+    /// 设置全局最大杠杆倍数（仅 admin 可调用，0 表示不限制）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` GlobalConfig PDA
+    /// 1. `[signer]` Current Admin
+    SetMaxLeverage {
+        max_leverage: u64,
+    },
+    
+    /// 从 free_collateral 中分配保证金给 delegate（仅 owner 可调用）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[writable]` UserVault PDA
+    /// 2. `[signer]` Owner
+    AllocateCollateral {
+        delegate_pubkey: Pubkey,
+        amount: u64,
+    },
+    
+    /// 收回 delegate 未锁定的分配额度到 free_collateral（仅 owner 可调用）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[writable]` UserVault PDA
+    /// 2. `[signer]` Owner
+    ReclaimCollateral {
+        delegate_pubkey: Pubkey,
+        amount: u64,
+    },
+    
+    /// 将 free_collateral 转入 owner 专用储备金（仅 owner 可调用）
+    /// 
+    /// 储备金不参与 delegate 的 LockMargin / Withdraw。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    MoveToReserve {
+        amount: u64,
+    },
+    
+    /// 将储备金释放回 free_collateral（仅 owner 可调用）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    ReleaseReserve {
+        amount: u64,
+    },
+    
+    /// 设置 vault 总名义敞口上限（仅 owner 可调用，0 表示不限制）
+    /// 
+    /// 对 owner 和所有 delegate 的 LockMargin 生效。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    SetMaxTotalNotional {
+        max_total_notional: u64,
+    },
+    
+    /// 一次性撤销所有 API Key（仅 owner 可调用）
+    /// 
+    /// 递增 vault 的 delegate 代数，之前创建/更新的所有 delegate 立即失效，
+    /// 需要通过 UpsertDelegate 重新授权。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    /// 2. `[writable]` DelegateRegistry PDA - 同步将所有记录标记为已撤销（尚未创建时传入派生地址）
+    RevokeAllDelegates,
+    
+    /// 设置每个 vault 最多有效 delegate 数量（仅 admin 可调用）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` GlobalConfig PDA
+    /// 1. `[signer]` Current Admin
+    SetMaxDelegatesPerVault {
+        max_delegates: u64,
+    },
+    
+    /// 设置 API Key 元数据（仅 owner 可调用）
+    /// 
+    /// label: 标签（UTF-8，末尾 0 填充，不能含控制字符）
+    /// strategy_id: 策略标识
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Owner
+    /// 3. `[writable]` DelegateRegistry PDA
+    SetDelegateMetadata {
+        delegate_pubkey: Pubkey,
+        label: [u8; DELEGATE_LABEL_LEN],
+        strategy_id: u64,
+    },
+    
+    /// 轮换 API Key（仅 owner 可调用）
+    /// 
+    /// 将旧 delegate 的权限、限额、used_notional、分配额度和元数据原子地迁移到
+    /// 新 delegate 派生的 PDA，并关闭旧账户（租金退回旧账户的 rent_payer）。
+    /// 子 delegate 指向旧账户，因此旧 delegate 还有未撤销的子 delegate 时拒绝轮换。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Old DelegateAccount PDA - 将被关闭
+    /// 1. `[writable]` New DelegateAccount PDA - 将被创建
+    /// 2. `[]` UserVault PDA
+    /// 3. `[signer, writable]` Owner - 支付新账户租金
+    /// 4. `[]` System Program
+    /// 5. `[writable]` DelegateRegistry PDA
+    /// 6. `[writable, optional]` Rent Payer - 旧账户的 rent_payer（不是 owner 时必须提供）
+    RotateDelegate {
+        old_delegate_pubkey: Pubkey,
+        new_delegate_pubkey: Pubkey,
+    },
+    
+    /// API Key 自行撤销（由 delegate 签名）
+    /// 
+    /// 发现密钥泄露时 delegate 可立即作废自己的授权，owner 之后可通过
+    /// UpsertDelegate 重新授权。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Delegate - API Key
+    /// 3. `[writable]` DelegateRegistry PDA
+    SelfRevokeDelegate,
+    
+    /// 创建子 API Key（会话密钥，由有 PERM_SUBDELEGATE 权限的 delegate 签名）
+    /// 
+    /// 子 delegate 的权限、max_notional、max_leverage 和 expiry_slot 必须是父
+    /// delegate 的子集；其敞口和保证金计入父 delegate。父 delegate 被撤销后
+    /// 所有子 delegate 随之失效。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Child DelegateAccount PDA - 将被创建
+    /// 1. `[]` Parent DelegateAccount PDA
+    /// 2. `[writable]` UserVault PDA
+    /// 3. `[signer, writable]` Parent Delegate - 支付租金
+    /// 4. `[]` GlobalConfig PDA
+    /// 5. `[]` System Program
+    /// 6. `[writable]` DelegateRegistry PDA
+    CreateSubDelegate {
+        child_pubkey: Pubkey,
+        permissions: u64,
+        max_notional: u64,
+        expiry_slot: u64,
+        max_leverage: u64,
+        label: [u8; DELEGATE_LABEL_LEN],
+    },
+    
+    /// 暂停 API Key（仅 owner 可调用，保留全部配置）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Owner
+    /// 3. `[writable]` DelegateRegistry PDA
+    PauseDelegate {
+        delegate_pubkey: Pubkey,
+    },
+    
+    /// 恢复已暂停的 API Key（仅 owner 可调用）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Owner
+    /// 3. `[writable]` DelegateRegistry PDA
+    ResumeDelegate {
+        delegate_pubkey: Pubkey,
+    },
+    
+    /// API Key 心跳（由 delegate 签名）
+    /// 
+    /// 设置了 heartbeat_interval_secs 的 delegate 需定期调用，作为策略进程的
+    /// 存活信号（dead-man switch）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[signer]` Delegate - API Key
+    Heartbeat,
+    
+    /// 设置 API Key 可调用的业务程序白名单（仅 owner 可调用）
+    /// 
+    /// programs: 最多 MAX_ALLOWED_PROGRAMS 个程序 ID，传空列表表示不限制。
+    /// 通过 instructions sysvar 识别顶层调用程序。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Owner
+    SetDelegatePrograms {
+        delegate_pubkey: Pubkey,
+        programs: Vec<Pubkey>,
+    },
+    
+    /// 锁定保证金（delegate 链下签名授权，由 relayer 提交）
+    /// 
+    /// 与 LockMargin 相同，但 1 号账户为 delegate 公钥且无需签名。delegate 对
+    /// DelegateIntent（action = INTENT_ACTION_LOCK_MARGIN）的 ed25519 签名须由同一
+    /// 交易中此前的 Ed25519 程序指令验证；nonce 必须大于 DelegateAccount.nonce。
+    /// 消息中的 delegate_epoch 和 delegate_serial 取自当前 DelegateAccount。
+    /// 
+    /// Accounts: 同 LockMargin，Instructions Sysvar 必须提供
+    LockMarginWithIntent {
+        required_margin: u64,
+        required_notional: u64,
+        market_id: u16,
+        nonce: u64,
+        expiry_unix_ts: i64,
+    },
+    
+    /// 提款（delegate 链下签名授权，由 relayer 提交）
+    /// 
+    /// 与 Withdraw 相同，但 1 号账户为 delegate 公钥且无需签名，签名消息为
+    /// DelegateIntent（action = INTENT_ACTION_WITHDRAW）。
+    /// 
+    /// Accounts: 同 Withdraw，DelegateAccount 和 Instructions Sysvar 必须提供
+    WithdrawWithIntent {
+        amount: u64,
+        nonce: u64,
+        expiry_unix_ts: i64,
+    },
+    
+    /// 执行 owner 链下签名许可（由 relayer 提交并支付租金）
+    /// 
+    /// instruction_data: 被授权的 UpsertDelegate 或 RevokeDelegate 指令数据
+    /// nonce: 必须等于 UserVault.permit_nonce，执行后递增
+    /// deadline: 许可截止时间（Unix 时间戳，秒）
+    /// 
+    /// owner 对 OwnerPermit 消息的 ed25519 签名须由同一交易中此前的 Ed25519 程序
+    /// 指令验证。
+    /// 
+    /// Accounts:
+    /// 0..n. 被授权指令的账户（Owner 无需签名）
+    /// n. `[signer, writable]` Payer - relayer，支付租金
+    /// n+1. `[]` Instructions Sysvar
+    ExecuteOwnerPermit {
+        instruction_data: Vec<u8>,
+        nonce: u64,
+        deadline: i64,
+    },
+    
+    /// 代存款：任意签名者 → 指定 owner 的 Vault
+    /// 
+    /// 可由第三方钱包直接调用或由其他程序 CPI 调用，冻结的 Vault 拒绝存款。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Depositor - 出资方
+    /// 2. `[writable]` Depositor USDC Account - 出资方的 USDC 账户
+    /// 3. `[writable]` Vault USDC Account - Vault 的 USDC 账户
+    /// 4. `[]` GlobalConfig PDA
+    /// 5. `[]` Token Program
+    DepositFor {
+        owner: Pubkey,
+        amount: u64,
+    },
+    
+    /// 设置 delegate 代存款上限（仅 owner 可调用）
+    /// 
+    /// deposit_limit: 每个窗口内的存款上限，0 表示禁止
+    /// window_slots: 窗口长度（slots）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Owner
+    SetDelegateDepositLimit {
+        delegate_pubkey: Pubkey,
+        deposit_limit: u64,
+        window_slots: u64,
+    },
+    
+    /// Delegate 代存款：owner 钱包 → Vault（由有 PERM_DEPOSIT 权限的 delegate 签名）
+    /// 
+    /// owner 需先对自己的 USDC 账户执行 SPL approve，授权给 deposit authority PDA
+    /// （seeds: ["deposit-authority", owner]）。每个窗口内的存款总额受
+    /// DelegateAccount.deposit_limit 限制。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Delegate - API Key
+    /// 2. `[writable]` DelegateAccount PDA
+    /// 3. `[writable]` Owner USDC Account - 已授权给 deposit authority PDA
+    /// 4. `[writable]` Vault USDC Account
+    /// 5. `[]` Deposit Authority PDA
+    /// 6. `[]` Token Program
+    /// 7. `[optional]` Parent DelegateAccount PDA - 如果 signer 是子 delegate
+    DelegateDeposit {
+        amount: u64,
+    },
+    
+    /// Vault 间划转：源 Vault → 目标 Vault（不经过用户钱包）
+    /// 
+    /// 从源 vault 的 free_collateral 扣除并记入目标 vault 的 free_collateral，
+    /// 两侧余额原子更新。仅源 owner 或有 PERM_TRANSFER 权限的 delegate 可调用。
+    /// delegate 划转超过其（或父 delegate 的）cosign_threshold 时，owner 必须同时签名。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` Source UserVault PDA
+    /// 1. `[writable]` Source Vault USDC Account
+    /// 2. `[writable]` Destination UserVault PDA
+    /// 3. `[writable]` Destination Vault USDC Account
+    /// 4. `[signer]` Signer - 源 Owner 或有 PERM_TRANSFER 权限的 delegate
+    /// 5. `[]` Token Program
+    /// 6. `[optional]` DelegateAccount PDA - 如果 signer 是 delegate
+    /// 7. `[optional]` Parent DelegateAccount PDA - 如果 signer 是子 delegate
+    /// 8. `[signer, optional]` Owner - 超过 cosign_threshold 时附加在最后
+    TransferBetweenVaults {
+        amount: u64,
+    },
+    
+    /// 请求提款（仅 owner 可调用，free_collateral 不足时使用）
+    /// 
+    /// 创建提款请求 PDA 并立即从 free_collateral 预留可用部分；之后每次
+    /// UnlockMarginAndUpdatePnl 释放的资金，以及存款、ReclaimCollateral 等增加
+    /// free_collateral 的操作都会继续预留，delegate 无法再次锁定。
+    /// 请求金额不能超过 free_collateral + locked_collateral；delegate 分配额度中未锁定的
+    /// 部分不计入，需 owner 先 ReclaimCollateral。
+    /// 每个 vault 同时只能有一个未完成的请求，可通过 CancelWithdrawalRequest 取消。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[writable]` WithdrawalRequest PDA - 将被创建
+    /// 2. `[signer, writable]` Owner - 支付租金
+    /// 3. `[]` System Program
+    RequestWithdrawal {
+        amount: u64,
+    },
+    
+    /// 执行提款请求（任何人可调用）
+    /// 
+    /// 将已预留的资金支付到 owner 的 USDC 账户，请求全部完成后关闭 PDA，
+    /// 租金退回 owner。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[writable]` WithdrawalRequest PDA
+    /// 2. `[writable]` Owner USDC Account - 必须是 owner 的
+    /// 3. `[writable]` Vault USDC Account
+    /// 4. `[]` Token Program
+    /// 5. `[writable]` Owner - 接收关闭 PDA 退回的租金
+    ExecuteWithdrawal,
+    
+    /// 取消提款请求（仅 owner 可调用）
+    /// 
+    /// 已预留但尚未支付的资金退回 free_collateral，关闭 WithdrawalRequest PDA，
+    /// 租金退回 owner。之后可以重新 RequestWithdrawal。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[writable]` WithdrawalRequest PDA
+    /// 2. `[signer, writable]` Owner - 接收关闭 PDA 退回的租金
+    CancelWithdrawalRequest,
+    /// 设置大额提款时间锁（仅 owner 可调用）
+    /// 
+    /// 超过 threshold 的提款和 TransferBetweenVaults（包括 delegate 发起的）必须通过
+    /// ScheduleWithdrawal 等待 delay_slots 后才能执行。threshold 为 0 表示关闭该功能。
+    /// 收紧设置立即生效；关闭、提高阈值或缩短延迟要等待当前 delay_slots 后才生效，
+    /// 期间再次调用收紧的设置即可取消。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[signer]` Owner
+    SetWithdrawalTimelock {
+        threshold: u64,
+        delay_slots: u64,
+    },
+    
+    /// 发起时间锁提款：从 free_collateral 中冻结资金并创建 PendingWithdrawal PDA
+    /// 
+    /// 每个 vault 同时只能有一个时间锁提款。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[writable]` PendingWithdrawal PDA - 将被创建
+    /// 2. `[signer, writable]` Signer - Owner 或有 PERM_WITHDRAW 权限的 delegate，支付租金
+    /// 3. `[]` System Program
+    /// 4. `[optional]` DelegateAccount PDA - 如果 signer 是 delegate
+    /// 5. `[optional]` Parent DelegateAccount PDA - 如果 signer 是子 delegate
+    /// 6. `[signer, optional]` Owner - 超过 delegate 的 cosign_threshold 时附加在最后
+    ScheduleWithdrawal {
+        amount: u64,
+    },
+    
+    /// 执行时间锁提款（任何人可调用，需时间锁到期）
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[writable]` PendingWithdrawal PDA
+    /// 2. `[writable]` Owner USDC Account - 必须是 owner 的
+    /// 3. `[writable]` Vault USDC Account
+    /// 4. `[]` Token Program
+    /// 5. `[writable]` Initiator - 接收关闭 PDA 退回的租金
+    ExecutePendingWithdrawal,
+    
+    /// 取消时间锁提款（仅 owner 可调用），资金退回 free_collateral
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` UserVault PDA
+    /// 1. `[writable]` PendingWithdrawal PDA
+    /// 2. `[signer]` Owner
+    /// 3. `[writable]` Initiator - 接收关闭 PDA 退回的租金
+    CancelPendingWithdrawal,
+    
+    /// 设置 delegate 提款 co-sign 阈值（仅 owner 可调用）
+    /// 
+    /// delegate 提款或 Vault 间划转金额超过 threshold 时，owner 必须同时签名；0 表示不要求。
+    /// 
+    /// Accounts:
+    /// 0. `[writable]` DelegateAccount PDA
+    /// 1. `[]` UserVault PDA
+    /// 2. `[signer]` Owner
+    SetDelegateCosignThreshold {
+        delegate_pubkey: Pubkey,
+        threshold: u64,
+    },
 }
-
@@ -21,6 +21,7 @@ use solana_program::{
 };
 
 pub mod error;
+pub mod events;
 pub mod instruction;
 pub mod processor;
 pub mod state;
@@ -44,9 +45,13 @@ pub fn process_instruction(
 
 // 导出公共类型
 pub use error::VaultError;
+pub use events::VaultEvent;
 pub use instruction::VaultInstruction;
 pub use state::{
-    DelegateAccount, GlobalConfig, UserVault, 
-    PERM_CLOSE_ONLY, PERM_TRADE, PERM_VIEW_ONLY, PERM_WITHDRAW,
+    DelegateAccount, DelegateIntent, DelegateRegistry, DelegateRegistryEntry, GlobalConfig,
+    OwnerPermit, PendingWithdrawal, UserVault, WithdrawalRequest, DELEGATE_LABEL_LEN,
+    INTENT_ACTION_LOCK_MARGIN, INTENT_ACTION_WITHDRAW, MAX_ALLOWED_PROGRAMS, PERM_CLOSE_ONLY,
+    PERM_DEPOSIT, PERM_SUBDELEGATE, PERM_TRADE, PERM_TRANSFER, PERM_VIEW_ONLY, PERM_WITHDRAW,
+    REGISTRY_STATUS_ACTIVE, REGISTRY_STATUS_PAUSED, REGISTRY_STATUS_REVOKED,
 };
 
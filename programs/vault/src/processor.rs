@@ -5,22 +5,27 @@ use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
     msg,
-    program::{invoke, invoke_signed},
+    program::invoke_signed,
     program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
-    sysvar::{clock::Clock, Sysvar},
+    sysvar::{self, clock::Clock, Sysvar},
     system_program,
 };
 use spl_token::state::Account as TokenAccount;
 
 use crate::{
     error::VaultError,
+    events::VaultEvent,
     instruction::VaultInstruction,
     state::{
-        DelegateAccount, GlobalConfig, UserVault, PERM_TRADE, PERM_WITHDRAW,
+        within_leverage, DelegateAccount, DelegateIntent, DelegateRegistry, DelegateRegistryEntry,
+        GlobalConfig, OwnerPermit, PendingWithdrawal, UserVault, WithdrawalRequest,
+        DELEGATE_LABEL_LEN, INTENT_ACTION_LOCK_MARGIN, INTENT_ACTION_WITHDRAW, MAX_ALLOWED_PROGRAMS,
+        PERM_DEPOSIT, PERM_SUBDELEGATE, PERM_TRADE, PERM_TRANSFER, PERM_WITHDRAW,
+        REGISTRY_STATUS_ACTIVE, REGISTRY_STATUS_PAUSED, REGISTRY_STATUS_REVOKED,
     },
     utils::*,
 };
@@ -45,13 +50,19 @@ pub fn process_instruction(
             process_deposit(program_id, accounts, amount)
         }
         VaultInstruction::Withdraw { amount } => {
-            process_withdraw(program_id, accounts, amount)
+            process_withdraw(program_id, accounts, amount, None)
         }
         VaultInstruction::UpsertDelegate {
             delegate_pubkey,
             permissions,
             max_notional,
             expiry_slot,
+            max_leverage,
+            label,
+            not_before_slot,
+            expiry_unix_ts,
+            heartbeat_interval_secs,
+            allowed_markets,
         } => {
             process_upsert_delegate(
                 program_id,
@@ -60,16 +71,31 @@ pub fn process_instruction(
                 permissions,
                 max_notional,
                 expiry_slot,
+                max_leverage,
+                label,
+                not_before_slot,
+                expiry_unix_ts,
+                heartbeat_interval_secs,
+                allowed_markets,
+                None,
             )
         }
         VaultInstruction::RevokeDelegate { delegate_pubkey } => {
-            process_revoke_delegate(program_id, accounts, delegate_pubkey)
+            process_revoke_delegate(program_id, accounts, delegate_pubkey, false)
         }
         VaultInstruction::LockMargin {
             required_margin,
             required_notional,
+            market_id,
         } => {
-            process_lock_margin(program_id, accounts, required_margin, required_notional)
+            process_lock_margin(
+                program_id,
+                accounts,
+                required_margin,
+                required_notional,
+                market_id,
+                None,
+            )
         }
         VaultInstruction::UnlockMarginAndUpdatePnl {
             unlocked_margin,
@@ -96,6 +122,182 @@ pub fn process_instruction(
         VaultInstruction::UnfreezeVault => {
             process_unfreeze_vault(program_id, accounts)
         }
+        VaultInstruction::MigrateAccount => {
+            process_migrate_account(program_id, accounts)
+        }
+        VaultInstruction::SetLossLimit {
+            loss_limit,
+            window_slots,
+        } => {
+            process_set_loss_limit(program_id, accounts, loss_limit, window_slots)
+        }
+        VaultInstruction::ResumeTrading => {
+            process_resume_trading(program_id, accounts)
+        }
+        VaultInstruction::SetMaxLeverage { max_leverage } => {
+            process_set_max_leverage(program_id, accounts, max_leverage)
+        }
+        VaultInstruction::AllocateCollateral {
+            delegate_pubkey,
+            amount,
+        } => {
+            process_allocate_collateral(program_id, accounts, delegate_pubkey, amount)
+        }
+        VaultInstruction::ReclaimCollateral {
+            delegate_pubkey,
+            amount,
+        } => {
+            process_reclaim_collateral(program_id, accounts, delegate_pubkey, amount)
+        }
+        VaultInstruction::MoveToReserve { amount } => {
+            process_move_to_reserve(program_id, accounts, amount)
+        }
+        VaultInstruction::ReleaseReserve { amount } => {
+            process_release_reserve(program_id, accounts, amount)
+        }
+        VaultInstruction::SetMaxTotalNotional { max_total_notional } => {
+            process_set_max_total_notional(program_id, accounts, max_total_notional)
+        }
+        VaultInstruction::RevokeAllDelegates => {
+            process_revoke_all_delegates(program_id, accounts)
+        }
+        VaultInstruction::SetMaxDelegatesPerVault { max_delegates } => {
+            process_set_max_delegates_per_vault(program_id, accounts, max_delegates)
+        }
+        VaultInstruction::SetDelegateMetadata {
+            delegate_pubkey,
+            label,
+            strategy_id,
+        } => {
+            process_set_delegate_metadata(program_id, accounts, delegate_pubkey, label, strategy_id)
+        }
+        VaultInstruction::RotateDelegate {
+            old_delegate_pubkey,
+            new_delegate_pubkey,
+        } => {
+            process_rotate_delegate(program_id, accounts, old_delegate_pubkey, new_delegate_pubkey)
+        }
+        VaultInstruction::SelfRevokeDelegate => {
+            process_self_revoke_delegate(program_id, accounts)
+        }
+        VaultInstruction::CreateSubDelegate {
+            child_pubkey,
+            permissions,
+            max_notional,
+            expiry_slot,
+            max_leverage,
+            label,
+        } => {
+            process_create_subdelegate(
+                program_id,
+                accounts,
+                child_pubkey,
+                permissions,
+                max_notional,
+                expiry_slot,
+                max_leverage,
+                label,
+            )
+        }
+        VaultInstruction::PauseDelegate { delegate_pubkey } => {
+            process_set_delegate_paused(program_id, accounts, delegate_pubkey, true)
+        }
+        VaultInstruction::ResumeDelegate { delegate_pubkey } => {
+            process_set_delegate_paused(program_id, accounts, delegate_pubkey, false)
+        }
+        VaultInstruction::Heartbeat => {
+            process_heartbeat(program_id, accounts)
+        }
+        VaultInstruction::SetDelegatePrograms { delegate_pubkey, programs } => {
+            process_set_delegate_programs(program_id, accounts, delegate_pubkey, programs)
+        }
+        VaultInstruction::LockMarginWithIntent {
+            required_margin,
+            required_notional,
+            market_id,
+            nonce,
+            expiry_unix_ts,
+        } => {
+            process_lock_margin(
+                program_id,
+                accounts,
+                required_margin,
+                required_notional,
+                market_id,
+                Some(IntentAuth { nonce, expiry_unix_ts }),
+            )
+        }
+        VaultInstruction::WithdrawWithIntent {
+            amount,
+            nonce,
+            expiry_unix_ts,
+        } => {
+            process_withdraw(
+                program_id,
+                accounts,
+                amount,
+                Some(IntentAuth { nonce, expiry_unix_ts }),
+            )
+        }
+        VaultInstruction::ExecuteOwnerPermit {
+            instruction_data,
+            nonce,
+            deadline,
+        } => {
+            process_execute_owner_permit(program_id, accounts, instruction_data, nonce, deadline)
+        }
+        VaultInstruction::DepositFor { owner, amount } => {
+            process_deposit_for(program_id, accounts, owner, amount)
+        }
+        VaultInstruction::SetDelegateDepositLimit {
+            delegate_pubkey,
+            deposit_limit,
+            window_slots,
+        } => {
+            process_set_delegate_deposit_limit(
+                program_id,
+                accounts,
+                delegate_pubkey,
+                deposit_limit,
+                window_slots,
+            )
+        }
+        VaultInstruction::DelegateDeposit { amount } => {
+            process_delegate_deposit(program_id, accounts, amount)
+        }
+        VaultInstruction::TransferBetweenVaults { amount } => {
+            process_transfer_between_vaults(program_id, accounts, amount)
+        }
+        VaultInstruction::RequestWithdrawal { amount } => {
+            process_request_withdrawal(program_id, accounts, amount)
+        }
+        VaultInstruction::ExecuteWithdrawal => {
+            process_execute_withdrawal(program_id, accounts)
+        }
+        VaultInstruction::CancelWithdrawalRequest => {
+            process_cancel_withdrawal_request(program_id, accounts)
+        }
+        VaultInstruction::SetWithdrawalTimelock {
+            threshold,
+            delay_slots,
+        } => {
+            process_set_withdrawal_timelock(program_id, accounts, threshold, delay_slots)
+        }
+        VaultInstruction::ScheduleWithdrawal { amount } => {
+            process_schedule_withdrawal(program_id, accounts, amount)
+        }
+        VaultInstruction::ExecutePendingWithdrawal => {
+            process_execute_pending_withdrawal(program_id, accounts)
+        }
+        VaultInstruction::CancelPendingWithdrawal => {
+            process_cancel_pending_withdrawal(program_id, accounts)
+        }
+        VaultInstruction::SetDelegateCosignThreshold {
+            delegate_pubkey,
+            threshold,
+        } => {
+            process_set_delegate_cosign_threshold(program_id, accounts, delegate_pubkey, threshold)
+        }
     }
 }
 
@@ -111,6 +313,7 @@ fn process_initialize_global_config(
     let admin_info = next_account_info(account_info_iter)?;
     let system_program_info = next_account_info(account_info_iter)?;
     let rent_sysvar_info = next_account_info(account_info_iter)?;
+    let payer_info = resolve_payer(account_info_iter.next(), admin_info)?; // Optional
     
     // 验证
     require_signer(admin_info)?;
@@ -135,7 +338,7 @@ fn process_initialize_global_config(
     
     // 创建账户
     create_pda_account(
-        admin_info,
+        payer_info,
         global_config_info,
         system_program_info,
         program_id,
@@ -167,6 +370,7 @@ fn process_create_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> Progra
     let system_program_info = next_account_info(account_info_iter)?;
     let token_program_info = next_account_info(account_info_iter)?;
     let rent_sysvar_info = next_account_info(account_info_iter)?;
+    let payer_info = resolve_payer(account_info_iter.next(), owner_info)?; // Optional
     
     // 验证
     require_signer(owner_info)?;
@@ -193,7 +397,7 @@ fn process_create_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> Progra
     
     // 创建 UserVault 账户
     create_pda_account(
-        owner_info,
+        payer_info,
         vault_info,
         system_program_info,
         program_id,
@@ -210,7 +414,7 @@ fn process_create_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> Progra
     // 创建 Token Account (owner = Token Program, authority = vault-usdc PDA)
     let token_account_space = TokenAccount::LEN;
     let create_account_ix = system_instruction::create_account(
-        owner_info.key,
+        payer_info.key,
         vault_usdc_info.key,
         rent.minimum_balance(token_account_space),
         token_account_space as u64,
@@ -219,7 +423,7 @@ fn process_create_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> Progra
     
     invoke_signed(
         &create_account_ix,
-        &[owner_info.clone(), vault_usdc_info.clone(), system_program_info.clone()],
+        &[payer_info.clone(), vault_usdc_info.clone(), system_program_info.clone()],
         &[usdc_seeds_with_bump],
     )?;
     
@@ -243,16 +447,21 @@ fn process_create_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> Progra
     )?;
     
     // 初始化 UserVault 数据
-    let user_vault = UserVault::new(*owner_info.key, *vault_usdc_info.key, vault_bump, usdc_bump);
+    let mut user_vault = UserVault::new(*owner_info.key, *vault_usdc_info.key, vault_bump, usdc_bump);
+    user_vault.rent_payer = *payer_info.key;
     user_vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
     
     msg!("Vault created for owner: {}", owner_info.key);
+    msg!("Rent payer: {}", payer_info.key);
     msg!("Vault PDA: {}", vault_info.key);
     msg!("Vault USDC: {}", vault_usdc_info.key);
     
     Ok(())
 }
 
+/// 单次存款上限：1B USDC (防止误操作)
+const MAX_DEPOSIT: u64 = 1_000_000_000_000_000; // 1B USDC (e6 format)
+
 /// 存款
 fn process_deposit(
     program_id: &Pubkey,
@@ -278,8 +487,6 @@ fn process_deposit(
         return Err(VaultError::InvalidAmount.into());
     }
     
-    // 单次存款上限：1B USDC (防止误操作)
-    const MAX_DEPOSIT: u64 = 1_000_000_000_000_000; // 1B USDC (e6 format)
     if amount > MAX_DEPOSIT {
         msg!("Deposit amount too large: {}", amount);
         return Err(VaultError::InvalidAmount.into());
@@ -310,6 +517,7 @@ fn process_deposit(
     // 更新余额
     vault.total_deposit = safe_add(vault.total_deposit, amount)?;
     vault.free_collateral = safe_add(vault.free_collateral, amount)?;
+    vault.earmark_free_collateral()?;
     vault.update_timestamp();
     
     vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
@@ -320,6 +528,257 @@ fn process_deposit(
     msg!("Deposited {} USDC to vault", amount);
     msg!("New free collateral: {}", vault.free_collateral);
     
+    VaultEvent::Deposited {
+        vault: *vault_info.key,
+        owner: vault.owner,
+        depositor: *owner_info.key,
+        amount,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 代存款：任意签名者向指定 owner 的 Vault 存款
+///
+/// 用于跨链桥、发薪、交易所提现等由第三方钱包或程序（CPI）入金的场景，
+/// 资金记入 owner 的 free_collateral
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Depositor
+/// 2. `[writable]` Depositor USDC Account
+/// 3. `[writable]` Vault USDC Account
+/// 4. `[]` GlobalConfig PDA
+/// 5. `[]` Token Program
+fn process_deposit_for(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    owner: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let depositor_info = next_account_info(account_info_iter)?;
+    let depositor_usdc_info = next_account_info(account_info_iter)?;
+    let vault_usdc_info = next_account_info(account_info_iter)?;
+    let _global_config_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(depositor_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 参数边界检查
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    if amount > MAX_DEPOSIT {
+        msg!("Deposit amount too large: {}", amount);
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner 和 vault USDC 账户
+    if vault.owner != owner {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if vault.usdc_vault != *vault_usdc_info.key {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 转账：depositor → vault
+    token_transfer(
+        token_program_info,
+        depositor_usdc_info,
+        vault_usdc_info,
+        depositor_info,
+        amount,
+    )?;
+    
+    // 更新余额
+    vault.total_deposit = safe_add(vault.total_deposit, amount)?;
+    vault.free_collateral = safe_add(vault.free_collateral, amount)?;
+    vault.earmark_free_collateral()?;
+    vault.update_timestamp();
+    
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    // 验证余额一致性
+    verify_vault_balance_integrity(&vault, vault_usdc_info)?;
+    
+    msg!("Deposited {} USDC to vault of {}", amount, owner);
+    msg!("Depositor: {}", depositor_info.key);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    VaultEvent::Deposited {
+        vault: *vault_info.key,
+        owner,
+        depositor: *depositor_info.key,
+        amount,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// Delegate 代存款
+///
+/// 通过 owner 对 deposit authority PDA 的 SPL 授权，从 owner 的 USDC 账户
+/// 转入 Vault，用于自动补充保证金
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Delegate
+/// 2. `[writable]` DelegateAccount PDA
+/// 3. `[writable]` Owner USDC Account
+/// 4. `[writable]` Vault USDC Account
+/// 5. `[]` Deposit Authority PDA
+/// 6. `[]` Token Program
+/// 7. `[optional]` Parent DelegateAccount PDA
+fn process_delegate_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    let delegate_info = next_account_info(account_info_iter)?;
+    let owner_usdc_info = next_account_info(account_info_iter)?;
+    let vault_usdc_info = next_account_info(account_info_iter)?;
+    let deposit_authority_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let parent_info = account_info_iter.next(); // Optional
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(vault_info)?;
+    require_writable(delegate_info)?;
+    require_owner(vault_info, program_id)?;
+    require_owner(delegate_info, program_id)?;
+    
+    if amount == 0 || amount > MAX_DEPOSIT {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    if vault.usdc_vault != *vault_usdc_info.key {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证 delegate
+    if delegate.delegate != *signer_info.key {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    if delegate.owner != vault.owner || delegate.vault != *vault_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 检查权限
+    let clock = Clock::get()?;
+    if !delegate.is_valid(clock.slot, clock.unix_timestamp, vault.delegate_epoch) {
+        return Err(VaultError::DelegateExpired.into());
+    }
+    
+    if !delegate.has_permission(PERM_DEPOSIT) {
+        return Err(VaultError::PermissionDenied.into());
+    }
+    
+    // 子 delegate 需要父 delegate 同样有效
+    if delegate.is_subdelegate() {
+        let parent_account_info = parent_info.ok_or(VaultError::InvalidDelegate)?;
+        let parent = load_parent_delegate(
+            program_id,
+            parent_account_info,
+            &delegate,
+            &vault,
+            clock.slot,
+            clock.unix_timestamp,
+        )?;
+        
+        if !parent.has_permission(PERM_DEPOSIT) {
+            return Err(VaultError::PermissionDenied.into());
+        }
+    }
+    
+    // 检查窗口上限
+    delegate.record_deposit(amount, clock.slot)?;
+    
+    // 资金来源必须是 owner 的 USDC 账户
+    let owner_usdc = TokenAccount::unpack(&owner_usdc_info.data.borrow())?;
+    if owner_usdc.owner != vault.owner {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 验证 deposit authority PDA
+    let authority_seeds = &[b"deposit-authority".as_ref(), vault.owner.as_ref()];
+    let authority_bump = verify_pda(deposit_authority_info.key, program_id, authority_seeds)?;
+    let authority_seeds_with_bump = &[
+        b"deposit-authority".as_ref(),
+        vault.owner.as_ref(),
+        &[authority_bump],
+    ];
+    
+    // 转账：owner → vault（deposit authority PDA 作为 SPL delegate 签名）
+    token_transfer_signed(
+        token_program_info,
+        owner_usdc_info,
+        vault_usdc_info,
+        deposit_authority_info,
+        amount,
+        authority_seeds_with_bump,
+    )?;
+    
+    delegate.update_timestamp();
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    // 更新余额
+    vault.total_deposit = safe_add(vault.total_deposit, amount)?;
+    vault.free_collateral = safe_add(vault.free_collateral, amount)?;
+    vault.earmark_free_collateral()?;
+    vault.update_timestamp();
+    
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    // 验证余额一致性
+    verify_vault_balance_integrity(&vault, vault_usdc_info)?;
+    
+    msg!("Delegate deposited {} USDC to vault", amount);
+    msg!("Delegate: {}", signer_info.key);
+    msg!("Window used: {} / {}", delegate.deposit_window_used, delegate.deposit_limit);
+    
+    VaultEvent::Deposited {
+        vault: *vault_info.key,
+        owner: vault.owner,
+        depositor: *signer_info.key,
+        amount,
+    }
+    .emit();
+    
     Ok(())
 }
 
@@ -328,6 +787,7 @@ fn process_withdraw(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     amount: u64,
+    intent: Option<IntentAuth>,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
@@ -338,9 +798,12 @@ fn process_withdraw(
     let _global_config_info = next_account_info(account_info_iter)?;
     let token_program_info = next_account_info(account_info_iter)?;
     let delegate_info = account_info_iter.next(); // Optional
+    let (parent_info, instructions_sysvar_info) = split_optional_accounts(account_info_iter);
     
-    // 验证
-    require_signer(signer_info)?;
+    // 验证（链下签名授权时 signer 无需签名交易）
+    if intent.is_none() {
+        require_signer(signer_info)?;
+    }
     require_writable(vault_info)?;
     require_owner(vault_info, program_id)?;
     
@@ -356,32 +819,103 @@ fn process_withdraw(
         return Err(VaultError::VaultFrozen.into());
     }
     
-    // 权限验证
-    let is_owner = *signer_info.key == vault.owner;
+    // 大额提款必须走时间锁
+    vault.apply_pending_withdrawal_timelock(Clock::get()?.slot);
+    if vault.requires_withdrawal_timelock(amount) {
+        msg!("Amount exceeds timelock threshold: {}", vault.withdrawal_timelock_threshold);
+        return Err(VaultError::WithdrawalTimelockRequired.into());
+    }
+    
+    // 权限验证（链下签名授权只适用于 delegate）
+    let is_owner = intent.is_none() && *signer_info.key == vault.owner;
     
     if !is_owner {
         // 如果不是 owner，必须是有 WITHDRAW 权限的 delegate
         let delegate_account_info = delegate_info.ok_or(VaultError::InvalidDelegate)?;
-        let delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
+        require_owner(delegate_account_info, program_id)?;
+        
+        let mut delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
         
         // 验证 delegate
         if delegate.delegate != *signer_info.key {
             return Err(VaultError::InvalidDelegate.into());
         }
         
-        if delegate.owner != vault.owner {
+        if delegate.owner != vault.owner || delegate.vault != *vault_info.key {
             return Err(VaultError::InvalidOwner.into());
         }
         
+        let delegate_seeds = &[
+            b"delegate".as_ref(),
+            vault.owner.as_ref(),
+            delegate.delegate.as_ref(),
+        ];
+        verify_pda(delegate_account_info.key, program_id, delegate_seeds)?;
+        
         // 检查权限
-        let current_slot = Clock::get()?.slot;
-        if !delegate.is_valid(current_slot) {
+        let clock = Clock::get()?;
+        let current_slot = clock.slot;
+        if !delegate.is_valid(current_slot, clock.unix_timestamp, vault.delegate_epoch) {
             return Err(VaultError::DelegateExpired.into());
         }
         
         if !delegate.has_permission(PERM_WITHDRAW) {
             return Err(VaultError::PermissionDenied.into());
         }
+        
+        // 超过 co-sign 阈值的提款需要 owner 同时签名
+        check_owner_cosign(&delegate, &vault, accounts, amount)?;
+        
+        // delegate 提款的目标必须是 owner 的 USDC 账户
+        let owner_usdc = TokenAccount::unpack(&owner_usdc_info.data.borrow())?;
+        if owner_usdc.owner != vault.owner {
+            return Err(VaultError::InvalidTokenAccount.into());
+        }
+        
+        // 链下签名授权：验签并推进 nonce
+        if let Some(auth) = intent {
+            require_writable(delegate_account_info)?;
+            
+            let message = DelegateIntent {
+                program_id: *program_id,
+                vault: *vault_info.key,
+                delegate: delegate.delegate,
+                delegate_epoch: delegate.delegate_epoch,
+                delegate_serial: delegate.serial,
+                action: INTENT_ACTION_WITHDRAW,
+                amount,
+                notional: 0,
+                market_id: 0,
+                nonce: auth.nonce,
+                expiry_unix_ts: auth.expiry_unix_ts,
+            };
+            verify_delegate_intent(
+                &mut delegate,
+                &message,
+                instructions_sysvar_info,
+                clock.unix_timestamp,
+            )?;
+            delegate.serialize(&mut &mut delegate_account_info.data.borrow_mut()[..])?;
+        }
+        
+        // 子 delegate 需要父 delegate 同样有效
+        if delegate.is_subdelegate() {
+            let parent_account_info = parent_info.ok_or(VaultError::InvalidDelegate)?;
+            let parent = load_parent_delegate(
+                program_id,
+                parent_account_info,
+                &delegate,
+                &vault,
+                current_slot,
+                clock.unix_timestamp,
+            )?;
+            
+            if !parent.has_permission(PERM_WITHDRAW) {
+                return Err(VaultError::PermissionDenied.into());
+            }
+            
+            check_owner_cosign(&parent, &vault, accounts, amount)?;
+        }
     }
     
     // 检查余额
@@ -422,157 +956,3192 @@ fn process_withdraw(
     Ok(())
 }
 
+/// Vault 间划转
+///
+/// 使用源 vault-usdc PDA 签名转账，并原子更新两个 UserVault 的余额
+///
+/// # 账户
+/// 0. `[writable]` Source UserVault PDA
+/// 1. `[writable]` Source Vault USDC Account
+/// 2. `[writable]` Destination UserVault PDA
+/// 3. `[writable]` Destination Vault USDC Account
+/// 4. `[signer]` Signer
+/// 5. `[]` Token Program
+/// 6. `[optional]` DelegateAccount PDA
+/// 7. `[optional]` Parent DelegateAccount PDA
+/// 8. `[signer, optional]` Owner（超过 cosign_threshold 时）
+fn process_transfer_between_vaults(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let from_vault_info = next_account_info(account_info_iter)?;
+    let from_usdc_info = next_account_info(account_info_iter)?;
+    let to_vault_info = next_account_info(account_info_iter)?;
+    let to_usdc_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let delegate_info = account_info_iter.next(); // Optional
+    let parent_info = account_info_iter.next(); // Optional
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(from_vault_info)?;
+    require_writable(to_vault_info)?;
+    require_owner(from_vault_info, program_id)?;
+    require_owner(to_vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    if from_vault_info.key == to_vault_info.key {
+        msg!("Source and destination vaults must differ");
+        return Err(VaultError::InvalidVaultAccount.into());
+    }
+    
+    // 读取两个 vault
+    let mut from_vault = UserVault::try_from_slice(&from_vault_info.data.borrow())?;
+    let mut to_vault = UserVault::try_from_slice(&to_vault_info.data.borrow())?;
+    
+    if from_vault.usdc_vault != *from_usdc_info.key || to_vault.usdc_vault != *to_usdc_info.key {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 检查是否冻结（任一侧冻结都拒绝）
+    if from_vault.is_frozen() || to_vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 权限验证
+    let is_owner = *signer_info.key == from_vault.owner;
+    
+    if !is_owner {
+        // 如果不是 owner，必须是有 TRANSFER 权限的 delegate
+        let delegate_account_info = delegate_info.ok_or(VaultError::InvalidDelegate)?;
+        require_owner(delegate_account_info, program_id)?;
+        
+        let delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
+        
+        // 验证 delegate
+        if delegate.delegate != *signer_info.key {
+            return Err(VaultError::InvalidDelegate.into());
+        }
+        
+        if delegate.owner != from_vault.owner || delegate.vault != *from_vault_info.key {
+            return Err(VaultError::InvalidOwner.into());
+        }
+        
+        // 检查权限
+        let clock = Clock::get()?;
+        if !delegate.is_valid(clock.slot, clock.unix_timestamp, from_vault.delegate_epoch) {
+            return Err(VaultError::DelegateExpired.into());
+        }
+        
+        if !delegate.has_permission(PERM_TRANSFER) {
+            return Err(VaultError::PermissionDenied.into());
+        }
+        
+        // 超过 co-sign 阈值的划转需要 owner 同时签名
+        check_owner_cosign(&delegate, &from_vault, accounts, amount)?;
+        
+        // 子 delegate 需要父 delegate 同样有效
+        if delegate.is_subdelegate() {
+            let parent_account_info = parent_info.ok_or(VaultError::InvalidDelegate)?;
+            let parent = load_parent_delegate(
+                program_id,
+                parent_account_info,
+                &delegate,
+                &from_vault,
+                clock.slot,
+                clock.unix_timestamp,
+            )?;
+            
+            if !parent.has_permission(PERM_TRANSFER) {
+                return Err(VaultError::PermissionDenied.into());
+            }
+            
+            check_owner_cosign(&parent, &from_vault, accounts, amount)?;
+        }
+    }
+    
+    // 大额划转同样必须走时间锁（目标可以是任意 vault）
+    from_vault.apply_pending_withdrawal_timelock(Clock::get()?.slot);
+    if from_vault.requires_withdrawal_timelock(amount) {
+        msg!("Amount exceeds timelock threshold: {}", from_vault.withdrawal_timelock_threshold);
+        return Err(VaultError::WithdrawalTimelockRequired.into());
+    }
+    
+    // 检查余额
+    if from_vault.free_collateral < amount {
+        return Err(VaultError::InsufficientFreeCollateral.into());
+    }
+    
+    // 转账：源 vault-usdc → 目标 vault-usdc（源 vault-usdc PDA 签名）
+    let usdc_seeds_with_bump = &[
+        b"vault-usdc".as_ref(),
+        from_vault.owner.as_ref(),
+        &[from_vault.usdc_bump],
+    ];
+    
+    token_transfer_signed(
+        token_program_info,
+        from_usdc_info,
+        to_usdc_info,
+        from_usdc_info, // authority 是源 vault-usdc PDA 本身
+        amount,
+        usdc_seeds_with_bump,
+    )?;
+    
+    // 更新两侧余额
+    from_vault.free_collateral = safe_sub(from_vault.free_collateral, amount)?;
+    from_vault.total_withdrawn = safe_add(from_vault.total_withdrawn, amount)?;
+    from_vault.update_timestamp();
+    
+    to_vault.free_collateral = safe_add(to_vault.free_collateral, amount)?;
+    to_vault.earmark_free_collateral()?;
+    to_vault.total_deposit = safe_add(to_vault.total_deposit, amount)?;
+    to_vault.update_timestamp();
+    
+    from_vault.serialize(&mut &mut from_vault_info.data.borrow_mut()[..])?;
+    to_vault.serialize(&mut &mut to_vault_info.data.borrow_mut()[..])?;
+    
+    // 验证余额一致性
+    verify_vault_balance_integrity(&from_vault, from_usdc_info)?;
+    verify_vault_balance_integrity(&to_vault, to_usdc_info)?;
+    
+    msg!("Transferred {} USDC between vaults", amount);
+    msg!("From: {}", from_vault_info.key);
+    msg!("To: {}", to_vault_info.key);
+    
+    VaultEvent::VaultTransfer {
+        from_vault: *from_vault_info.key,
+        to_vault: *to_vault_info.key,
+        signer: *signer_info.key,
+        amount,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 请求提款
+///
+/// free_collateral 不足时创建提款请求，立即预留可用部分，
+/// 其余在后续 UnlockMarginAndUpdatePnl 释放资金时预留
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[writable]` WithdrawalRequest PDA
+/// 2. `[signer, writable]` Owner
+/// 3. `[]` System Program
+fn process_request_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let request_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_writable(request_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 大额提款必须走时间锁
+    vault.apply_pending_withdrawal_timelock(Clock::get()?.slot);
+    if vault.requires_withdrawal_timelock(amount) {
+        msg!("Amount exceeds timelock threshold: {}", vault.withdrawal_timelock_threshold);
+        return Err(VaultError::WithdrawalTimelockRequired.into());
+    }
+    
+    // 请求金额不能超过可预留的资金总额：free_collateral 加上解锁后会回到
+    // free_collateral 或被预留的 locked_collateral；delegate 未锁定的分配额度需先 ReclaimCollateral
+    let available = safe_add(vault.free_collateral, vault.locked_collateral)?;
+    if amount > available {
+        msg!("Requestable amount: {}", available);
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 派生 WithdrawalRequest PDA
+    let request_seeds = &[b"withdrawal".as_ref(), owner_info.key.as_ref()];
+    let request_bump = verify_pda(request_info.key, program_id, request_seeds)?;
+    let request_seeds_with_bump = &[
+        b"withdrawal".as_ref(),
+        owner_info.key.as_ref(),
+        &[request_bump],
+    ];
+    
+    // 同一时间只允许一个未完成的请求
+    if request_info.data_len() > 0 {
+        return Err(VaultError::WithdrawalAlreadyPending.into());
+    }
+    
+    let rent = Rent::get()?;
+    create_pda_account(
+        owner_info,
+        request_info,
+        system_program_info,
+        program_id,
+        &rent,
+        WithdrawalRequest::SIZE,
+        request_seeds_with_bump,
+    )?;
+    
+    let request = WithdrawalRequest::new(*owner_info.key, *vault_info.key, amount, request_bump);
+    request.serialize(&mut &mut request_info.data.borrow_mut()[..])?;
+    
+    // 立即从 free_collateral 预留
+    vault.withdrawal_requested = amount;
+    let earmarked = vault.earmark_free_collateral()?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("Withdrawal requested: {}", amount);
+    msg!("Earmarked: {}", earmarked);
+    
+    VaultEvent::WithdrawalRequested {
+        vault: *vault_info.key,
+        amount,
+        earmarked,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 执行提款请求
+///
+/// 任何人可调用，将已预留资金支付到 owner 的 USDC 账户，
+/// 请求全部完成后关闭 PDA 并退回租金
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[writable]` WithdrawalRequest PDA
+/// 2. `[writable]` Owner USDC Account
+/// 3. `[writable]` Vault USDC Account
+/// 4. `[]` Token Program
+/// 5. `[writable]` Owner
+fn process_execute_withdrawal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let request_info = next_account_info(account_info_iter)?;
+    let owner_usdc_info = next_account_info(account_info_iter)?;
+    let vault_usdc_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_writable(vault_info)?;
+    require_writable(request_info)?;
+    require_owner(vault_info, program_id)?;
+    require_owner(request_info, program_id)?;
+    
+    // 读取 vault 和请求
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    let mut request = WithdrawalRequest::try_from_slice(&request_info.data.borrow())?;
+    
+    if request.vault != *vault_info.key || request.owner != vault.owner {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    if vault.usdc_vault != *vault_usdc_info.key {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 目标必须是 owner 的 USDC 账户
+    let owner_usdc = TokenAccount::unpack(&owner_usdc_info.data.borrow())?;
+    if owner_usdc.owner != vault.owner {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    let amount = vault.earmarked_collateral;
+    if amount == 0 {
+        return Err(VaultError::NoEarmarkedFunds.into());
+    }
+    
+    // 转账：vault → owner
+    let usdc_seeds_with_bump = &[
+        b"vault-usdc".as_ref(),
+        vault.owner.as_ref(),
+        &[vault.usdc_bump],
+    ];
+    
+    token_transfer_signed(
+        token_program_info,
+        vault_usdc_info,
+        owner_usdc_info,
+        vault_usdc_info, // authority 是 vault-usdc PDA 本身
+        amount,
+        usdc_seeds_with_bump,
+    )?;
+    
+    // 更新余额
+    vault.earmarked_collateral = 0;
+    vault.total_withdrawn = safe_add(vault.total_withdrawn, amount)?;
+    request.paid = safe_add(request.paid, amount)?;
+    let remaining = request.remaining();
+    
+    if remaining == 0 {
+        // 请求完成：清除剩余预留并关闭 PDA
+        if *owner_info.key != vault.owner {
+            return Err(VaultError::InvalidOwner.into());
+        }
+        require_writable(owner_info)?;
+        
+        vault.withdrawal_requested = 0;
+        close_pda_account(request_info, owner_info)?;
+        msg!("Withdrawal request completed");
+    } else {
+        request.serialize(&mut &mut request_info.data.borrow_mut()[..])?;
+    }
+    
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    // 验证余额一致性
+    verify_vault_balance_integrity(&vault, vault_usdc_info)?;
+    
+    msg!("Executed withdrawal: {}", amount);
+    msg!("Remaining: {}", remaining);
+    
+    VaultEvent::WithdrawalExecuted {
+        vault: *vault_info.key,
+        amount,
+        remaining,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 取消提款请求
+///
+/// 已预留但尚未支付的资金退回 free_collateral，关闭 PDA 并退回租金
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[writable]` WithdrawalRequest PDA
+/// 2. `[signer, writable]` Owner
+fn process_cancel_withdrawal_request(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let request_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_writable(request_info)?;
+    require_writable(owner_info)?;
+    require_owner(vault_info, program_id)?;
+    require_owner(request_info, program_id)?;
+    
+    // 读取 vault 和请求
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    let request = WithdrawalRequest::try_from_slice(&request_info.data.borrow())?;
+    
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if request.vault != *vault_info.key || request.owner != vault.owner {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 预留资金退回 free_collateral
+    let released = vault.earmarked_collateral;
+    vault.free_collateral = safe_add(vault.free_collateral, released)?;
+    vault.earmarked_collateral = 0;
+    vault.withdrawal_requested = 0;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    close_pda_account(request_info, owner_info)?;
+    
+    msg!("Withdrawal request cancelled");
+    msg!("Released earmarked funds: {}", released);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    Ok(())
+}
+
+/// 发起时间锁提款
+///
+/// 资金从 free_collateral 转入 timelocked_collateral，
+/// 等待 withdrawal_timelock_slots 后才能执行，期间 owner 可取消
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[writable]` PendingWithdrawal PDA
+/// 2. `[signer, writable]` Signer
+/// 3. `[]` System Program
+/// 4. `[optional]` DelegateAccount PDA
+/// 5. `[optional]` Parent DelegateAccount PDA
+fn process_schedule_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let pending_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let delegate_info = account_info_iter.next(); // Optional
+    let parent_info = account_info_iter.next(); // Optional
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(vault_info)?;
+    require_writable(pending_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    let clock = Clock::get()?;
+    
+    // 权限验证
+    let is_owner = *signer_info.key == vault.owner;
+    
+    if !is_owner {
+        // 如果不是 owner，必须是有 WITHDRAW 权限的 delegate
+        let delegate_account_info = delegate_info.ok_or(VaultError::InvalidDelegate)?;
+        require_owner(delegate_account_info, program_id)?;
+        
+        let delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
+        
+        // 验证 delegate
+        if delegate.delegate != *signer_info.key {
+            return Err(VaultError::InvalidDelegate.into());
+        }
+        
+        if delegate.owner != vault.owner || delegate.vault != *vault_info.key {
+            return Err(VaultError::InvalidOwner.into());
+        }
+        
+        // 检查权限
+        if !delegate.is_valid(clock.slot, clock.unix_timestamp, vault.delegate_epoch) {
+            return Err(VaultError::DelegateExpired.into());
+        }
+        
+        if !delegate.has_permission(PERM_WITHDRAW) {
+            return Err(VaultError::PermissionDenied.into());
+        }
+        
+        // 超过 co-sign 阈值的提款需要 owner 同时签名
+        check_owner_cosign(&delegate, &vault, accounts, amount)?;
+        
+        // 子 delegate 需要父 delegate 同样有效
+        if delegate.is_subdelegate() {
+            let parent_account_info = parent_info.ok_or(VaultError::InvalidDelegate)?;
+            let parent = load_parent_delegate(
+                program_id,
+                parent_account_info,
+                &delegate,
+                &vault,
+                clock.slot,
+                clock.unix_timestamp,
+            )?;
+            
+            if !parent.has_permission(PERM_WITHDRAW) {
+                return Err(VaultError::PermissionDenied.into());
+            }
+            
+            check_owner_cosign(&parent, &vault, accounts, amount)?;
+        }
+    }
+    
+    // 检查余额
+    if vault.free_collateral < amount {
+        return Err(VaultError::InsufficientFreeCollateral.into());
+    }
+    
+    // 派生 PendingWithdrawal PDA
+    let pending_seeds = &[b"pending-withdrawal".as_ref(), vault.owner.as_ref()];
+    let pending_bump = verify_pda(pending_info.key, program_id, pending_seeds)?;
+    let pending_seeds_with_bump = &[
+        b"pending-withdrawal".as_ref(),
+        vault.owner.as_ref(),
+        &[pending_bump],
+    ];
+    
+    // 同一时间只允许一个时间锁提款
+    if pending_info.data_len() > 0 {
+        return Err(VaultError::PendingWithdrawalExists.into());
+    }
+    
+    let rent = Rent::get()?;
+    create_pda_account(
+        signer_info,
+        pending_info,
+        system_program_info,
+        program_id,
+        &rent,
+        PendingWithdrawal::SIZE,
+        pending_seeds_with_bump,
+    )?;
+    
+    vault.apply_pending_withdrawal_timelock(clock.slot);
+    let unlock_slot = safe_add(clock.slot, vault.withdrawal_timelock_slots)?;
+    let pending = PendingWithdrawal::new(
+        vault.owner,
+        *vault_info.key,
+        *signer_info.key,
+        amount,
+        unlock_slot,
+        pending_bump,
+    );
+    pending.serialize(&mut &mut pending_info.data.borrow_mut()[..])?;
+    
+    // 冻结资金，delegate 无法再使用
+    vault.free_collateral = safe_sub(vault.free_collateral, amount)?;
+    vault.timelocked_collateral = safe_add(vault.timelocked_collateral, amount)?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("Withdrawal scheduled: {}", amount);
+    msg!("Unlock slot: {}", unlock_slot);
+    
+    VaultEvent::WithdrawalScheduled {
+        vault: *vault_info.key,
+        initiator: *signer_info.key,
+        amount,
+        unlock_slot,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 校验 delegate 提款的 owner co-sign 要求
+///
+/// 金额超过 cosign_threshold 时，owner 必须作为 signer 出现在账户列表中
+fn check_owner_cosign(
+    delegate: &DelegateAccount,
+    vault: &UserVault,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    if !delegate.requires_owner_cosign(amount) {
+        return Ok(());
+    }
+    
+    let owner_signed = accounts
+        .iter()
+        .any(|info| info.is_signer && *info.key == vault.owner);
+    
+    if !owner_signed {
+        msg!("Owner co-signature required above {}", delegate.cosign_threshold);
+        return Err(VaultError::OwnerCosignRequired.into());
+    }
+    
+    Ok(())
+}
+
+/// 读取并校验 PendingWithdrawal，同时校验租金退回账户
+fn load_pending_withdrawal(
+    program_id: &Pubkey,
+    pending_info: &AccountInfo,
+    initiator_info: &AccountInfo,
+    vault_key: &Pubkey,
+) -> Result<PendingWithdrawal, ProgramError> {
+    require_writable(pending_info)?;
+    require_writable(initiator_info)?;
+    require_owner(pending_info, program_id)?;
+    
+    let pending = PendingWithdrawal::try_from_slice(&pending_info.data.borrow())?;
+    
+    if pending.vault != *vault_key {
+        return Err(VaultError::InvalidVaultAccount.into());
+    }
+    
+    if pending.initiator != *initiator_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    Ok(pending)
+}
+
+/// 执行时间锁提款
+///
+/// 任何人可在时间锁到期后调用，资金支付到 owner 的 USDC 账户
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[writable]` PendingWithdrawal PDA
+/// 2. `[writable]` Owner USDC Account
+/// 3. `[writable]` Vault USDC Account
+/// 4. `[]` Token Program
+/// 5. `[writable]` Initiator
+fn process_execute_pending_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let pending_info = next_account_info(account_info_iter)?;
+    let owner_usdc_info = next_account_info(account_info_iter)?;
+    let vault_usdc_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let initiator_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    let pending = load_pending_withdrawal(program_id, pending_info, initiator_info, vault_info.key)?;
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 检查时间锁
+    let current_slot = Clock::get()?.slot;
+    if !pending.is_unlocked(current_slot) {
+        msg!("Withdrawal unlocks at slot {}", pending.unlock_slot);
+        return Err(VaultError::TimelockNotExpired.into());
+    }
+    
+    if vault.usdc_vault != *vault_usdc_info.key {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 目标必须是 owner 的 USDC 账户
+    let owner_usdc = TokenAccount::unpack(&owner_usdc_info.data.borrow())?;
+    if owner_usdc.owner != vault.owner {
+        return Err(VaultError::InvalidTokenAccount.into());
+    }
+    
+    // 转账：vault → owner
+    let usdc_seeds_with_bump = &[
+        b"vault-usdc".as_ref(),
+        vault.owner.as_ref(),
+        &[vault.usdc_bump],
+    ];
+    
+    token_transfer_signed(
+        token_program_info,
+        vault_usdc_info,
+        owner_usdc_info,
+        vault_usdc_info, // authority 是 vault-usdc PDA 本身
+        pending.amount,
+        usdc_seeds_with_bump,
+    )?;
+    
+    // 更新余额
+    vault.timelocked_collateral = safe_sub(vault.timelocked_collateral, pending.amount)?;
+    vault.total_withdrawn = safe_add(vault.total_withdrawn, pending.amount)?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    close_pda_account(pending_info, initiator_info)?;
+    
+    // 验证余额一致性
+    verify_vault_balance_integrity(&vault, vault_usdc_info)?;
+    
+    msg!("Executed pending withdrawal: {}", pending.amount);
+    
+    VaultEvent::PendingWithdrawalExecuted {
+        vault: *vault_info.key,
+        amount: pending.amount,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 取消时间锁提款
+///
+/// Owner 在时间锁期间取消（冻结状态下同样允许），资金退回 free_collateral
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[writable]` PendingWithdrawal PDA
+/// 2. `[signer]` Owner
+/// 3. `[writable]` Initiator
+fn process_cancel_pending_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let pending_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let initiator_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    let pending = load_pending_withdrawal(program_id, pending_info, initiator_info, vault_info.key)?;
+    
+    // 资金退回 free_collateral
+    vault.timelocked_collateral = safe_sub(vault.timelocked_collateral, pending.amount)?;
+    vault.free_collateral = safe_add(vault.free_collateral, pending.amount)?;
+    vault.earmark_free_collateral()?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    close_pda_account(pending_info, initiator_info)?;
+    
+    msg!("Cancelled pending withdrawal: {}", pending.amount);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    VaultEvent::PendingWithdrawalCancelled {
+        vault: *vault_info.key,
+        amount: pending.amount,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 从 delegate 分配额度中为提款请求预留资金，返回本次预留金额
+fn earmark_from_allocation(
+    vault: &mut UserVault,
+    holder: &mut DelegateAccount,
+) -> Result<u64, ProgramError> {
+    let amount = vault.withdrawal_requested.min(holder.allocated_collateral);
+    holder.allocated_collateral -= amount;
+    vault.delegated_collateral = safe_sub(vault.delegated_collateral, amount)?;
+    vault.withdrawal_requested -= amount;
+    vault.earmarked_collateral = safe_add(vault.earmarked_collateral, amount)?;
+    Ok(amount)
+}
+
+/// 最大名义敞口上限：1B USDC
+const MAX_NOTIONAL_LIMIT: u64 = 1_000_000_000_000_000;
+
+/// 最大杠杆倍数上限
+const MAX_LEVERAGE_LIMIT: u64 = 1_000;
+
+/// 每天的 slot 数（假设 2s/slot），按天计算的 slot 上限都由此推导
+const SLOTS_PER_DAY: u64 = 24 * 60 * 60 / 2;
+
 /// 添加/更新 Delegate
-fn process_upsert_delegate(
+///
+/// permit_payer 为 Some 时 owner 已通过链下签名许可授权，由 payer 支付租金
+#[allow(clippy::too_many_arguments)]
+fn process_upsert_delegate<'a, 'b>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'b>],
+    delegate_pubkey: Pubkey,
+    permissions: u64,
+    max_notional: u64,
+    expiry_slot: u64,
+    max_leverage: u64,
+    label: [u8; DELEGATE_LABEL_LEN],
+    not_before_slot: u64,
+    expiry_unix_ts: i64,
+    heartbeat_interval_secs: u64,
+    allowed_markets: u128,
+    permit_payer: Option<&'a AccountInfo<'b>>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let global_config_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    let payer_info = match permit_payer {
+        Some(payer_info) => payer_info,
+        None => {
+            require_signer(owner_info)?;
+            resolve_payer(account_info_iter.next(), owner_info)? // Optional
+        }
+    };
+    require_writable(delegate_info)?;
+    require_writable(vault_info)?;
+    require_writable(registry_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    let global_config = load_global_config(global_config_info, program_id)?;
+    
+    validate_label(&label)?;
+    
+    // 参数边界检查
+    if permissions == 0 {
+        msg!("Permissions cannot be empty");
+        return Err(VaultError::InvalidPermissions.into());
+    }
+    
+    if max_notional == 0 {
+        msg!("Max notional must be greater than 0");
+        return Err(VaultError::InvalidMaxNotional.into());
+    }
+    
+    if max_notional > MAX_NOTIONAL_LIMIT {
+        msg!("Max notional too large: {}", max_notional);
+        return Err(VaultError::InvalidMaxNotional.into());
+    }
+    
+    if max_leverage > MAX_LEVERAGE_LIMIT {
+        msg!("Max leverage too large: {}", max_leverage);
+        return Err(VaultError::InvalidMaxLeverage.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 验证过期时间（expiry_slot 和 expiry_unix_ts 至少设置一个）
+    let clock = Clock::get()?;
+    let current_slot = clock.slot;
+    
+    // 限制最大有效期（1 年）
+    const MAX_EXPIRY_DURATION: u64 = 365 * SLOTS_PER_DAY; // 约 1 年的 slots
+    const MAX_EXPIRY_SECONDS: i64 = 365 * 24 * 60 * 60;
+    
+    let expiry_slot = if expiry_slot == 0 && expiry_unix_ts != 0 {
+        // 仅按时间戳过期
+        u64::MAX
+    } else {
+        if expiry_slot <= current_slot {
+            return Err(VaultError::InvalidExpirySlot.into());
+        }
+        
+        if expiry_slot > current_slot + MAX_EXPIRY_DURATION {
+            msg!("Expiry too far in future. Max: 1 year");
+            return Err(VaultError::InvalidExpirySlot.into());
+        }
+        
+        expiry_slot
+    };
+    
+    if expiry_unix_ts != 0 {
+        if expiry_unix_ts <= clock.unix_timestamp {
+            return Err(VaultError::InvalidExpiryTimestamp.into());
+        }
+        
+        if expiry_unix_ts > clock.unix_timestamp + MAX_EXPIRY_SECONDS {
+            msg!("Expiry too far in future. Max: 1 year");
+            return Err(VaultError::InvalidExpiryTimestamp.into());
+        }
+    }
+    
+    if heartbeat_interval_secs > MAX_EXPIRY_SECONDS as u64 {
+        msg!("Heartbeat interval too large: {}", heartbeat_interval_secs);
+        return Err(VaultError::InvalidHeartbeatInterval.into());
+    }
+    
+    // 生效时间必须早于过期时间
+    if not_before_slot >= expiry_slot {
+        msg!("Not-before slot must be earlier than expiry slot");
+        return Err(VaultError::InvalidExpirySlot.into());
+    }
+    
+    // 派生 DelegateAccount PDA
+    let delegate_seeds = &[
+        b"delegate".as_ref(),
+        owner_info.key.as_ref(),
+        delegate_pubkey.as_ref(),
+    ];
+    let delegate_bump = verify_pda(delegate_info.key, program_id, delegate_seeds)?;
+    let delegate_seeds_with_bump = &[
+        b"delegate".as_ref(),
+        owner_info.key.as_ref(),
+        delegate_pubkey.as_ref(),
+        &[delegate_bump],
+    ];
+    
+    let rent = Rent::get()?;
+    
+    // 检查账户是否存在
+    let is_new = delegate_info.data_len() == 0;
+    
+    let delegate = if is_new {
+        // 创建新账户
+        create_pda_account(
+            payer_info,
+            delegate_info,
+            system_program_info,
+            program_id,
+            &rent,
+            DelegateAccount::SIZE,
+            delegate_seeds_with_bump,
+        )?;
+        
+        // 初始化 delegate
+        let mut delegate = DelegateAccount::new(
+            *owner_info.key,
+            *vault_info.key,
+            delegate_pubkey,
+            permissions,
+            max_notional,
+            expiry_slot,
+            max_leverage,
+            vault.delegate_epoch,
+            label,
+            delegate_bump,
+        );
+        delegate.rent_payer = *payer_info.key;
+        delegate.not_before_slot = not_before_slot;
+        delegate.expiry_unix_ts = expiry_unix_ts;
+        delegate.heartbeat_interval_secs = heartbeat_interval_secs;
+        delegate.allowed_markets = allowed_markets;
+        delegate.serial = vault.next_delegate_serial()?;
+        delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+        
+        vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+        
+        msg!("Delegate created: {}", delegate_pubkey);
+        delegate
+    } else {
+        // 更新现有 delegate
+        let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+        
+        // 验证
+        if delegate.owner != *owner_info.key {
+            return Err(VaultError::InvalidOwner.into());
+        }
+        
+        // owner 直接授权的 key 不再挂在父 delegate 之下；
+        // 子 delegate 的仓位占用的是父 delegate 的额度，需先平仓
+        if delegate.is_subdelegate() {
+            if delegate.used_notional > 0 {
+                msg!("Sub-delegate has open notional: {}", delegate.used_notional);
+                return Err(VaultError::DelegateHasOpenPositions.into());
+            }
+            delegate.parent = Pubkey::default();
+            delegate.parent_subdelegate_epoch = 0;
+        }
+        
+        // 更新字段
+        delegate.permissions = permissions;
+        delegate.max_notional = max_notional;
+        delegate.expiry_slot = expiry_slot;
+        delegate.max_leverage = max_leverage;
+        delegate.delegate_epoch = vault.delegate_epoch;
+        delegate.label = label;
+        delegate.not_before_slot = not_before_slot;
+        delegate.expiry_unix_ts = expiry_unix_ts;
+        delegate.heartbeat_interval_secs = heartbeat_interval_secs;
+        delegate.last_heartbeat_at = clock.unix_timestamp;
+        delegate.allowed_markets = allowed_markets;
+        delegate.is_active = true;
+        delegate.update_timestamp();
+        
+        delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+        
+        msg!("Delegate updated: {}", delegate_pubkey);
+        delegate
+    };
+    
+    // 同步 registry
+    register_delegate(
+        program_id,
+        registry_info,
+        vault_info,
+        owner_info.key,
+        payer_info,
+        system_program_info,
+        &rent,
+        DelegateRegistryEntry::new(
+            delegate_pubkey,
+            label,
+            expiry_slot,
+            expiry_unix_ts,
+            Pubkey::default(),
+        ),
+        global_config.max_delegates(),
+    )?;
+    
+    msg!("Permissions: {:064b}", permissions);
+    msg!("Max notional: {}", max_notional);
+    msg!("Expiry slot: {}", expiry_slot);
+    msg!("Expiry unix ts: {}", expiry_unix_ts);
+    msg!("Heartbeat interval: {}s", heartbeat_interval_secs);
+    msg!("Allowed markets: {:#034x}", allowed_markets);
+    msg!("Not before slot: {}", not_before_slot);
+    msg!("Max leverage: {}", max_leverage);
+    
+    VaultEvent::DelegateUpserted {
+        vault: *vault_info.key,
+        delegate: delegate_pubkey,
+        permissions,
+        label: delegate.label,
+        strategy_id: delegate.strategy_id,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 读取并验证 DelegateRegistry 账户
+fn load_registry(
+    registry_info: &AccountInfo,
+    program_id: &Pubkey,
+    owner: &Pubkey,
+) -> Result<DelegateRegistry, ProgramError> {
+    require_owner(registry_info, program_id)?;
+    
+    let registry = DelegateRegistry::try_from_slice(&registry_info.data.borrow())?;
+    if registry.discriminator != DelegateRegistry::DISCRIMINATOR {
+        return Err(VaultError::InvalidRegistryAccount.into());
+    }
+    
+    if registry.owner != *owner {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    Ok(registry)
+}
+
+/// 在 registry 中登记 delegate
+///
+/// registry 不存在时创建；已有记录则更新标签、过期时间和状态；
+/// 新 delegate 优先复用已失效（撤销、过期或父 delegate 失效）的槽位，否则 realloc 追加
+#[allow(clippy::too_many_arguments)]
+fn register_delegate<'a>(
+    program_id: &Pubkey,
+    registry_info: &AccountInfo<'a>,
+    vault_info: &AccountInfo<'a>,
+    owner: &Pubkey,
+    payer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent: &Rent,
+    mut entry: DelegateRegistryEntry,
+    max_delegates: u64,
+) -> ProgramResult {
+    // 派生 DelegateRegistry PDA
+    let registry_seeds = &[b"registry".as_ref(), owner.as_ref()];
+    let registry_bump = verify_pda(registry_info.key, program_id, registry_seeds)?;
+    
+    let is_new = registry_info.data_len() == 0;
+    let mut registry = if is_new {
+        DelegateRegistry::new(*owner, *vault_info.key, registry_bump)
+    } else {
+        load_registry(registry_info, program_id, owner)?
+    };
+    
+    let clock = Clock::get()?;
+    let current_slot = clock.slot;
+    let current_ts = clock.unix_timestamp;
+    let active_count = registry.active_count(current_slot, current_ts) as u64;
+    
+    match registry.find(&entry.delegate) {
+        Some(index) => {
+            let status = registry.entries[index].status;
+            let is_live = registry.is_live(index, current_slot, current_ts);
+            if !is_live && active_count >= max_delegates {
+                msg!("Too many delegates. Max: {}", max_delegates);
+                return Err(VaultError::TooManyDelegates.into());
+            }
+            // 更新配置不会解除暂停
+            if status == REGISTRY_STATUS_PAUSED {
+                entry.status = REGISTRY_STATUS_PAUSED;
+            }
+            registry.entries[index] = entry;
+        }
+        None => {
+            if active_count >= max_delegates {
+                msg!("Too many delegates. Max: {}", max_delegates);
+                return Err(VaultError::TooManyDelegates.into());
+            }
+            
+            let free_slot = (0..registry.entries.len())
+                .find(|&index| !registry.is_live(index, current_slot, current_ts));
+            match free_slot {
+                Some(index) => registry.entries[index] = entry,
+                None => registry.entries.push(entry),
+            }
+        }
+    }
+    
+    let space = DelegateRegistry::space(registry.entries.len());
+    if is_new {
+        let registry_seeds_with_bump = &[
+            b"registry".as_ref(),
+            owner.as_ref(),
+            &[registry_bump],
+        ];
+        create_pda_account(
+            payer_info,
+            registry_info,
+            system_program_info,
+            program_id,
+            rent,
+            space,
+            registry_seeds_with_bump,
+        )?;
+    } else if registry_info.data_len() < space {
+        realloc_pda_account(payer_info, registry_info, system_program_info, rent, space)?;
+    }
+    
+    registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    
+    msg!(
+        "Registry delegates: {} active / {} slots",
+        registry.active_count(current_slot, current_ts),
+        registry.entries.len()
+    );
+    
+    Ok(())
+}
+
+/// 读取并验证子 delegate 的父 delegate
+///
+/// 父 delegate 失效、被撤销或失去 PERM_SUBDELEGATE 时，子 delegate 一并失效
+fn load_parent_delegate(
+    program_id: &Pubkey,
+    parent_info: &AccountInfo,
+    child: &DelegateAccount,
+    vault: &UserVault,
+    current_slot: u64,
+    current_ts: i64,
+) -> Result<DelegateAccount, ProgramError> {
+    if *parent_info.key != child.parent {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    require_owner(parent_info, program_id)?;
+    
+    let parent = DelegateAccount::try_from_slice(&parent_info.data.borrow())?;
+    
+    if parent.owner != vault.owner {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if !parent.is_valid(current_slot, current_ts, vault.delegate_epoch)
+        || parent.subdelegate_epoch != child.parent_subdelegate_epoch
+    {
+        return Err(VaultError::DelegateExpired.into());
+    }
+    
+    if !parent.has_permission(PERM_SUBDELEGATE) {
+        return Err(VaultError::PermissionDenied.into());
+    }
+    
+    Ok(parent)
+}
+
+/// Delegate 心跳
+///
+/// 刷新 last_heartbeat_at，只做最少的校验以保持低开销
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[signer]` Delegate - API Key
+fn process_heartbeat(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(delegate_info)?;
+    require_owner(delegate_info, program_id)?;
+    
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    if delegate.delegate != *signer_info.key {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    if !delegate.is_active {
+        return Err(VaultError::DelegateNotActive.into());
+    }
+    
+    delegate.last_heartbeat_at = Clock::get()?.unix_timestamp;
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    Ok(())
+}
+
+/// 创建子 Delegate（会话密钥）
+///
+/// 由拥有 PERM_SUBDELEGATE 的 delegate 签名，子 delegate 的权限、限额和有效期
+/// 必须是父 delegate 的子集
+#[allow(clippy::too_many_arguments)]
+fn process_create_subdelegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    child_pubkey: Pubkey,
+    permissions: u64,
+    max_notional: u64,
+    expiry_slot: u64,
+    max_leverage: u64,
+    label: [u8; DELEGATE_LABEL_LEN],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let child_info = next_account_info(account_info_iter)?;
+    let parent_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    let global_config_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(child_info)?;
+    require_writable(vault_info)?;
+    require_writable(registry_info)?;
+    require_owner(parent_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    let global_config = load_global_config(global_config_info, program_id)?;
+    
+    validate_label(&label)?;
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 读取父 delegate
+    let parent = DelegateAccount::try_from_slice(&parent_info.data.borrow())?;
+    
+    // 验证父 delegate
+    if parent.delegate != *signer_info.key {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    if parent.owner != vault.owner || parent.vault != *vault_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 子 delegate 不能继续创建子 delegate
+    if parent.is_subdelegate() {
+        msg!("Sub-delegates cannot create sub-delegates");
+        return Err(VaultError::PermissionDenied.into());
+    }
+    
+    let clock = Clock::get()?;
+    let current_slot = clock.slot;
+    if !parent.is_valid(current_slot, clock.unix_timestamp, vault.delegate_epoch) {
+        return Err(VaultError::DelegateExpired.into());
+    }
+    
+    if !parent.has_permission(PERM_SUBDELEGATE) {
+        return Err(VaultError::PermissionDenied.into());
+    }
+    
+    // 权限必须是父 delegate 的子集（且不能再授予 PERM_SUBDELEGATE）
+    if permissions == 0
+        || permissions & !parent.permissions != 0
+        || permissions & PERM_SUBDELEGATE != 0
+    {
+        msg!("Child permissions must be a subset of parent permissions");
+        return Err(VaultError::InvalidPermissions.into());
+    }
+    
+    if max_notional == 0 || max_notional > parent.max_notional {
+        msg!("Child max notional must be in 1..={}", parent.max_notional);
+        return Err(VaultError::InvalidMaxNotional.into());
+    }
+    
+    if expiry_slot <= current_slot || expiry_slot > parent.expiry_slot {
+        msg!("Child expiry must be in ({}, {}]", current_slot, parent.expiry_slot);
+        return Err(VaultError::InvalidExpirySlot.into());
+    }
+    
+    if parent.max_leverage != 0 && (max_leverage == 0 || max_leverage > parent.max_leverage) {
+        msg!("Child max leverage must be in 1..={}", parent.max_leverage);
+        return Err(VaultError::InvalidMaxLeverage.into());
+    }
+    
+    if max_leverage > MAX_LEVERAGE_LIMIT {
+        msg!("Max leverage too large: {}", max_leverage);
+        return Err(VaultError::InvalidMaxLeverage.into());
+    }
+    
+    // 派生子 DelegateAccount PDA（与普通 delegate 相同的 seeds）
+    let child_seeds = &[
+        b"delegate".as_ref(),
+        vault.owner.as_ref(),
+        child_pubkey.as_ref(),
+    ];
+    let child_bump = verify_pda(child_info.key, program_id, child_seeds)?;
+    let child_seeds_with_bump = &[
+        b"delegate".as_ref(),
+        vault.owner.as_ref(),
+        child_pubkey.as_ref(),
+        &[child_bump],
+    ];
+    
+    if child_info.data_len() > 0 {
+        return Err(VaultError::AccountAlreadyExists.into());
+    }
+    
+    let rent = Rent::get()?;
+    
+    // 创建账户（父 delegate 支付租金）
+    create_pda_account(
+        signer_info,
+        child_info,
+        system_program_info,
+        program_id,
+        &rent,
+        DelegateAccount::SIZE,
+        child_seeds_with_bump,
+    )?;
+    
+    let mut child = DelegateAccount::new(
+        vault.owner,
+        *vault_info.key,
+        child_pubkey,
+        permissions,
+        max_notional,
+        expiry_slot,
+        max_leverage,
+        vault.delegate_epoch,
+        label,
+        child_bump,
+    );
+    child.parent = *parent_info.key;
+    child.parent_subdelegate_epoch = parent.subdelegate_epoch;
+    // 子 delegate 不能晚于父 delegate 的时间戳过期
+    child.expiry_unix_ts = parent.expiry_unix_ts;
+    child.allowed_programs = parent.allowed_programs;
+    child.allowed_markets = parent.allowed_markets;
+    child.cosign_threshold = parent.cosign_threshold;
+    child.serial = vault.next_delegate_serial()?;
+    child.rent_payer = *signer_info.key;
+    child.serialize(&mut &mut child_info.data.borrow_mut()[..])?;
+    
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    // 同步 registry
+    register_delegate(
+        program_id,
+        registry_info,
+        vault_info,
+        &vault.owner,
+        signer_info,
+        system_program_info,
+        &rent,
+        DelegateRegistryEntry::new(
+            child_pubkey,
+            label,
+            expiry_slot,
+            parent.expiry_unix_ts,
+            parent.delegate,
+        ),
+        global_config.max_delegates(),
+    )?;
+    
+    msg!("Sub-delegate created: {}", child_pubkey);
+    msg!("Parent delegate: {}", parent.delegate);
+    msg!("Permissions: {:064b}", permissions);
+    msg!("Max notional: {}", max_notional);
+    msg!("Expiry slot: {}", expiry_slot);
+    
+    VaultEvent::SubDelegateCreated {
+        vault: *vault_info.key,
+        parent: parent.delegate,
+        delegate: child_pubkey,
+        permissions,
+        expiry_slot,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 撤销 Delegate
+///
+/// permit_verified 为 true 时 owner 已通过链下签名许可授权
+fn process_revoke_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate_pubkey: Pubkey,
+    permit_verified: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let _global_config_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    if !permit_verified {
+        require_signer(owner_info)?;
+    }
+    require_writable(delegate_info)?;
+    require_writable(registry_info)?;
+    require_owner(delegate_info, program_id)?;
+    
+    // 读取 vault
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证
+    if delegate.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 撤销（同时作废其所有子 delegate）
+    delegate.is_active = false;
+    delegate.subdelegate_epoch = safe_add(delegate.subdelegate_epoch, 1)?;
+    delegate.update_timestamp();
+    
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    // 同步 registry
+    let mut registry = load_registry(registry_info, program_id, owner_info.key)?;
+    if !registry.set_status(&delegate_pubkey, REGISTRY_STATUS_REVOKED) {
+        msg!("Delegate not found in registry: {}", delegate_pubkey);
+    }
+    let revoked_children = registry.revoke_subdelegates(&delegate_pubkey);
+    if revoked_children > 0 {
+        msg!("Sub-delegates revoked: {}", revoked_children);
+    }
+    registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    
+    msg!("Delegate revoked: {}", delegate_pubkey);
+    
+    VaultEvent::DelegateRevoked {
+        vault: *vault_info.key,
+        delegate: delegate_pubkey,
+        label: delegate.label,
+        strategy_id: delegate.strategy_id,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 执行 Owner 链下签名许可
+///
+/// 验证 owner 对 OwnerPermit 的签名、截止时间和 vault 的 permit_nonce 后，
+/// 代 owner 执行其中的 UpsertDelegate 或 RevokeDelegate，relayer 支付租金
+///
+/// # 账户
+/// 0..n. 被授权指令的账户（Owner 无需签名）
+/// n. `[signer, writable]` Payer
+/// n+1. `[]` Instructions Sysvar
+fn process_execute_owner_permit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: Vec<u8>,
+    nonce: u64,
+    deadline: i64,
+) -> ProgramResult {
+    if accounts.len() < 2 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    
+    let (inner_accounts, permit_accounts) = accounts.split_at(accounts.len() - 2);
+    let payer_info = &permit_accounts[0];
+    let instructions_sysvar_info = &permit_accounts[1];
+    
+    // UpsertDelegate 和 RevokeDelegate 的 1 号账户都是 UserVault
+    let vault_info = inner_accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    
+    // 验证
+    require_signer(payer_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 检查截止时间
+    let clock = Clock::get()?;
+    if clock.unix_timestamp > deadline {
+        msg!("Permit expired at {}", deadline);
+        return Err(VaultError::PermitExpired.into());
+    }
+    
+    // 检查 nonce
+    if nonce != vault.permit_nonce {
+        msg!("Permit nonce mismatch. Expected: {}", vault.permit_nonce);
+        return Err(VaultError::InvalidPermitNonce.into());
+    }
+    
+    // 验证 owner 签名
+    let permit = OwnerPermit {
+        program_id: *program_id,
+        vault: *vault_info.key,
+        nonce,
+        deadline,
+        instruction_data,
+    };
+    let message = permit.try_to_vec().map_err(|_| VaultError::SerializationError)?;
+    verify_ed25519_signature(instructions_sysvar_info, &vault.owner, &message)?;
+    
+    // 消耗 nonce
+    vault.permit_nonce = safe_add(vault.permit_nonce, 1)?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("Owner permit accepted, nonce: {}", nonce);
+    msg!("Payer: {}", payer_info.key);
+    
+    let instruction = VaultInstruction::try_from_slice(&permit.instruction_data)
+        .map_err(|_| VaultError::DeserializationError)?;
+    
+    match instruction {
+        VaultInstruction::UpsertDelegate {
+            delegate_pubkey,
+            permissions,
+            max_notional,
+            expiry_slot,
+            max_leverage,
+            label,
+            not_before_slot,
+            expiry_unix_ts,
+            heartbeat_interval_secs,
+            allowed_markets,
+        } => {
+            process_upsert_delegate(
+                program_id,
+                inner_accounts,
+                delegate_pubkey,
+                permissions,
+                max_notional,
+                expiry_slot,
+                max_leverage,
+                label,
+                not_before_slot,
+                expiry_unix_ts,
+                heartbeat_interval_secs,
+                allowed_markets,
+                Some(payer_info),
+            )
+        }
+        VaultInstruction::RevokeDelegate { delegate_pubkey } => {
+            process_revoke_delegate(program_id, inner_accounts, delegate_pubkey, true)
+        }
+        _ => {
+            msg!("Only UpsertDelegate and RevokeDelegate can be permitted");
+            Err(VaultError::InvalidPermitInstruction.into())
+        }
+    }
+}
+
+/// 暂停 / 恢复 Delegate
+///
+/// Owner 临时停用 API Key 而不丢失其配置，恢复后立即可用
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[]` UserVault PDA
+/// 2. `[signer]` Owner
+/// 3. `[writable]` DelegateRegistry PDA
+fn process_set_delegate_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate_pubkey: Pubkey,
+    paused: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(delegate_info)?;
+    require_writable(registry_info)?;
+    require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 读取 vault
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证
+    if delegate.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    if delegate.is_paused == paused {
+        msg!("Delegate is already {}", if paused { "paused" } else { "active" });
+        return Ok(());
+    }
+    
+    delegate.is_paused = paused;
+    delegate.update_timestamp();
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    // 同步 registry（已撤销的记录保持不变）
+    let mut registry = load_registry(registry_info, program_id, owner_info.key)?;
+    if let Some(index) = registry.find(&delegate_pubkey) {
+        if registry.entries[index].status != REGISTRY_STATUS_REVOKED {
+            registry.entries[index].status = if paused {
+                REGISTRY_STATUS_PAUSED
+            } else {
+                REGISTRY_STATUS_ACTIVE
+            };
+        }
+    }
+    registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    
+    if paused {
+        msg!("⏸️  Delegate paused: {}", delegate_pubkey);
+    } else {
+        msg!("▶️  Delegate resumed: {}", delegate_pubkey);
+    }
+    
+    Ok(())
+}
+
+/// Delegate 自行撤销
+///
+/// 由 delegate 签名作废自己的授权，无需 owner 参与
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[]` UserVault PDA
+/// 2. `[signer]` Delegate
+/// 3. `[writable]` DelegateRegistry PDA
+fn process_self_revoke_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(delegate_info)?;
+    require_writable(registry_info)?;
+    require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 读取 vault
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证 delegate
+    if delegate.delegate != *signer_info.key {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    if delegate.owner != vault.owner || delegate.vault != *vault_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 撤销（同时作废其所有子 delegate）
+    delegate.is_active = false;
+    delegate.subdelegate_epoch = safe_add(delegate.subdelegate_epoch, 1)?;
+    delegate.update_timestamp();
+    
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    // 同步 registry
+    let mut registry = load_registry(registry_info, program_id, &vault.owner)?;
+    if !registry.set_status(signer_info.key, REGISTRY_STATUS_REVOKED) {
+        msg!("Delegate not found in registry: {}", signer_info.key);
+    }
+    let revoked_children = registry.revoke_subdelegates(signer_info.key);
+    if revoked_children > 0 {
+        msg!("Sub-delegates revoked: {}", revoked_children);
+    }
+    registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    
+    msg!("⚠️  Delegate self-revoked: {}", signer_info.key);
+    msg!("Owner: {}", vault.owner);
+    
+    VaultEvent::DelegateSelfRevoked {
+        owner: vault.owner,
+        vault: *vault_info.key,
+        delegate: *signer_info.key,
+        label: delegate.label,
+        strategy_id: delegate.strategy_id,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 设置 Delegate 元数据
+///
+/// Owner 更新 delegate 的标签和策略标识，并同步 registry 中的标签
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[]` UserVault PDA
+/// 2. `[signer]` Owner
+/// 3. `[writable]` DelegateRegistry PDA
+fn process_set_delegate_metadata(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate_pubkey: Pubkey,
+    label: [u8; DELEGATE_LABEL_LEN],
+    strategy_id: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(delegate_info)?;
+    require_writable(registry_info)?;
+    require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    validate_label(&label)?;
+    
+    // 读取 vault
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证
+    if delegate.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 更新元数据
+    delegate.label = label;
+    delegate.strategy_id = strategy_id;
+    delegate.update_timestamp();
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    // 同步 registry
+    let mut registry = load_registry(registry_info, program_id, owner_info.key)?;
+    match registry.find(&delegate_pubkey) {
+        Some(index) => registry.entries[index].label = label,
+        None => msg!("Delegate not found in registry: {}", delegate_pubkey),
+    }
+    registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    
+    msg!("Delegate metadata updated: {}", delegate_pubkey);
+    msg!("Strategy ID: {}", strategy_id);
+    
+    VaultEvent::DelegateMetadataUpdated {
+        vault: *vault_info.key,
+        delegate: delegate_pubkey,
+        label,
+        strategy_id,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 设置 Delegate 调用方程序白名单
+///
+/// 设置后该 delegate 只能通过白名单内的业务程序 LockMargin/UnlockMarginAndUpdatePnl
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[]` UserVault PDA
+/// 2. `[signer]` Owner
+fn process_set_delegate_programs(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate_pubkey: Pubkey,
+    programs: Vec<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(delegate_info)?;
+    require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    if programs.len() > MAX_ALLOWED_PROGRAMS {
+        msg!("Too many allowed programs. Max: {}", MAX_ALLOWED_PROGRAMS);
+        return Err(VaultError::TooManyAllowedPrograms.into());
+    }
+    
+    if programs.iter().any(|program| *program == Pubkey::default()) {
+        msg!("Allowed program cannot be the default pubkey");
+        return Err(ProgramError::InvalidArgument);
+    }
+    
+    // 读取 vault
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证
+    if delegate.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 更新白名单
+    delegate.allowed_programs = [Pubkey::default(); MAX_ALLOWED_PROGRAMS];
+    for (slot, program) in delegate.allowed_programs.iter_mut().zip(programs.iter()) {
+        *slot = *program;
+    }
+    delegate.update_timestamp();
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    msg!("Delegate programs updated: {}", delegate_pubkey);
+    for program in &programs {
+        msg!("Allowed program: {}", program);
+    }
+    
+    VaultEvent::DelegateProgramsUpdated {
+        vault: *vault_info.key,
+        delegate: delegate_pubkey,
+        programs,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 轮换 Delegate
+///
+/// 将旧 delegate 的全部状态迁移到新 delegate 的 PDA，并关闭旧账户。
+/// 旧 delegate 还有未撤销的子 delegate 时拒绝轮换
+///
+/// # 账户
+/// 0. `[writable]` Old DelegateAccount PDA
+/// 1. `[writable]` New DelegateAccount PDA
+/// 2. `[]` UserVault PDA
+/// 3. `[signer, writable]` Owner
+/// 4. `[]` System Program
+/// 5. `[writable]` DelegateRegistry PDA
+fn process_rotate_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    old_delegate_pubkey: Pubkey,
+    new_delegate_pubkey: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let old_delegate_info = next_account_info(account_info_iter)?;
+    let new_delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    let rent_payer_info = account_info_iter.next(); // Optional
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(old_delegate_info)?;
+    require_writable(new_delegate_info)?;
+    require_writable(registry_info)?;
+    require_owner(old_delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    if old_delegate_pubkey == new_delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 读取 vault
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 读取旧 delegate
+    let old_delegate = DelegateAccount::try_from_slice(&old_delegate_info.data.borrow())?;
+    
+    // 验证
+    if old_delegate.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if old_delegate.delegate != old_delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 子 delegate 的 parent 指向旧 PDA，轮换后将无法再锁定或结算，
+    // 因此必须先撤销全部子 delegate
+    let mut registry = load_registry(registry_info, program_id, owner_info.key)?;
+    if registry.has_subdelegates(&old_delegate_pubkey) {
+        msg!("Revoke sub-delegates before rotating: {}", old_delegate_pubkey);
+        return Err(VaultError::DelegateHasSubdelegates.into());
+    }
+    
+    // 派生新 DelegateAccount PDA
+    let new_delegate_seeds = &[
+        b"delegate".as_ref(),
+        owner_info.key.as_ref(),
+        new_delegate_pubkey.as_ref(),
+    ];
+    let new_delegate_bump = verify_pda(new_delegate_info.key, program_id, new_delegate_seeds)?;
+    let new_delegate_seeds_with_bump = &[
+        b"delegate".as_ref(),
+        owner_info.key.as_ref(),
+        new_delegate_pubkey.as_ref(),
+        &[new_delegate_bump],
+    ];
+    
+    if new_delegate_info.data_len() > 0 {
+        return Err(VaultError::AccountAlreadyExists.into());
+    }
+    
+    let rent = Rent::get()?;
+    create_pda_account(
+        owner_info,
+        new_delegate_info,
+        system_program_info,
+        program_id,
+        &rent,
+        DelegateAccount::SIZE,
+        new_delegate_seeds_with_bump,
+    )?;
+    
+    // 迁移全部状态（权限、限额、used_notional、分配额度、元数据、历史）
+    let mut new_delegate = old_delegate.clone();
+    new_delegate.delegate = new_delegate_pubkey;
+    new_delegate.bump = new_delegate_bump;
+    // serial 随状态迁移：新 key 从未持有过该序号，旧 key 的授权也无法由新 key 签名
+    new_delegate.rent_payer = *owner_info.key;
+    new_delegate.update_timestamp();
+    new_delegate.serialize(&mut &mut new_delegate_info.data.borrow_mut()[..])?;
+    
+    // 关闭旧账户（租金退回原 payer）
+    let refund_info = if old_delegate.rent_payer == *owner_info.key {
+        owner_info
+    } else {
+        let rent_payer_info = rent_payer_info.ok_or_else(|| {
+            msg!("Rent payer account required: {}", old_delegate.rent_payer);
+            VaultError::InvalidRentPayer
+        })?;
+        if *rent_payer_info.key != old_delegate.rent_payer {
+            msg!("Rent payer mismatch. Expected: {}", old_delegate.rent_payer);
+            return Err(VaultError::InvalidRentPayer.into());
+        }
+        require_writable(rent_payer_info)?;
+        rent_payer_info
+    };
+    close_pda_account(old_delegate_info, refund_info)?;
+    
+    // 同步 registry
+    match registry.find(&old_delegate_pubkey) {
+        Some(index) => registry.entries[index].delegate = new_delegate_pubkey,
+        None => msg!("Delegate not found in registry: {}", old_delegate_pubkey),
+    }
+    registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    
+    msg!("🔄 Delegate rotated");
+    msg!("Old delegate: {}", old_delegate_pubkey);
+    msg!("New delegate: {}", new_delegate_pubkey);
+    msg!("Used notional: {}", new_delegate.used_notional);
+    
+    VaultEvent::DelegateRotated {
+        vault: *vault_info.key,
+        old_delegate: old_delegate_pubkey,
+        new_delegate: new_delegate_pubkey,
+    }
+    .emit();
+    
+    Ok(())
+}
+
+/// 撤销所有 Delegate
+///
+/// 递增 vault 的 delegate 代数，所有旧代数的 delegate 立即失效
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+/// 2. `[writable]` DelegateRegistry PDA
+fn process_revoke_all_delegates(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_writable(registry_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 同步 registry（尚未授权过 delegate 时 registry 不存在）
+    let registry_seeds = &[b"registry".as_ref(), owner_info.key.as_ref()];
+    verify_pda(registry_info.key, program_id, registry_seeds)?;
+    
+    if registry_info.data_len() > 0 {
+        let mut registry = load_registry(registry_info, program_id, owner_info.key)?;
+        for entry in registry.entries.iter_mut() {
+            entry.status = REGISTRY_STATUS_REVOKED;
+        }
+        registry.serialize(&mut &mut registry_info.data.borrow_mut()[..])?;
+    }
+    
+    // 递增代数
+    vault.delegate_epoch = safe_add(vault.delegate_epoch, 1)?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("⚠️  All delegates revoked");
+    msg!("New delegate epoch: {}", vault.delegate_epoch);
+    
+    Ok(())
+}
+
+/// Delegate 链下签名授权参数
+#[derive(Clone, Copy)]
+struct IntentAuth {
+    nonce: u64,
+    expiry_unix_ts: i64,
+}
+
+/// 校验 delegate 的链下签名授权，通过后推进 delegate.nonce
+///
+/// nonce 必须严格递增，旧授权无法重放；消息绑定 delegate_epoch 和 serial，
+/// 重新创建的同一 key 无法重放旧账户的授权
+fn verify_delegate_intent(
+    delegate: &mut DelegateAccount,
+    message: &DelegateIntent,
+    instructions_sysvar_info: Option<&AccountInfo>,
+    current_ts: i64,
+) -> ProgramResult {
+    if current_ts > message.expiry_unix_ts {
+        msg!("Intent expired at {}", message.expiry_unix_ts);
+        return Err(VaultError::IntentExpired.into());
+    }
+    
+    if message.nonce <= delegate.nonce {
+        msg!("Intent nonce must be greater than {}", delegate.nonce);
+        return Err(VaultError::InvalidIntentNonce.into());
+    }
+    
+    let instructions_sysvar_info = instructions_sysvar_info.ok_or_else(|| {
+        msg!("Instructions sysvar required for signed intent");
+        ProgramError::NotEnoughAccountKeys
+    })?;
+    
+    let data = message.try_to_vec().map_err(|_| VaultError::SerializationError)?;
+    verify_ed25519_signature(instructions_sysvar_info, &delegate.delegate, &data)?;
+    
+    delegate.nonce = message.nonce;
+    
+    msg!("Signed intent accepted, nonce: {}", message.nonce);
+    Ok(())
+}
+
+/// 拆分 Lock/Unlock/Withdraw 末尾的可选账户：父 DelegateAccount 和 instructions sysvar
+///
+/// 没有父 delegate 时 instructions sysvar 可直接放在父 delegate 的位置
+fn split_optional_accounts<'a, 'b>(
+    account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+) -> (Option<&'a AccountInfo<'b>>, Option<&'a AccountInfo<'b>>) {
+    let parent_info = account_info_iter.next();
+    
+    match parent_info {
+        Some(info) if sysvar::instructions::check_id(info.key) => (None, Some(info)),
+        _ => (parent_info, account_info_iter.next()),
+    }
+}
+
+/// 校验 delegate 的调用方程序白名单
+fn check_program_allowlist(
+    delegate: &DelegateAccount,
+    instructions_sysvar_info: Option<&AccountInfo>,
+) -> ProgramResult {
+    if !delegate.has_program_allowlist() {
+        return Ok(());
+    }
+    
+    let instructions_sysvar_info = instructions_sysvar_info.ok_or_else(|| {
+        msg!("Instructions sysvar required for program-scoped delegate");
+        ProgramError::NotEnoughAccountKeys
+    })?;
+    
+    let caller_program = get_invoking_program(instructions_sysvar_info)?;
+    if !delegate.is_program_allowed(&caller_program) {
+        msg!("Caller program not allowed: {}", caller_program);
+        return Err(VaultError::ProgramNotAllowed.into());
+    }
+    
+    Ok(())
+}
+
+/// 锁定保证金（CPI调用）
+fn process_lock_margin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    required_margin: u64,
+    required_notional: u64,
+    market_id: u16,
+    intent: Option<IntentAuth>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    let delegate_info = account_info_iter.next(); // Optional
+    let global_config_info = next_account_info(account_info_iter)?;
+    let _clock_sysvar_info = next_account_info(account_info_iter)?;
+    let (parent_info, instructions_sysvar_info) = split_optional_accounts(account_info_iter);
+    
+    // 验证
+    if intent.is_none() {
+        require_signer(signer_info)?;
+    }
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    let global_config = load_global_config(global_config_info, program_id)?;
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 检查是否冻结
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 检查全局杠杆上限
+    if !within_leverage(required_margin, required_notional, global_config.max_leverage) {
+        msg!("Leverage exceeds global max: {}x", global_config.max_leverage);
+        return Err(VaultError::LeverageExceeded.into());
+    }
+    
+    // 检查 vault 总名义敞口上限
+    if !vault.can_use_notional(required_notional) {
+        msg!("Vault total notional: {} / {}", vault.total_notional, vault.max_total_notional);
+        return Err(VaultError::VaultNotionalLimitExceeded.into());
+    }
+    
+    // 权限验证
+    let is_owner = intent.is_none() && *signer_info.key == vault.owner;
+    
+    if !is_owner {
+        // 如果不是 owner，必须是有 TRADE 权限的 delegate
+        let delegate_account_info = delegate_info.ok_or(VaultError::InvalidDelegate)?;
+        require_writable(delegate_account_info)?;
+        require_owner(delegate_account_info, program_id)?;
+        
+        let mut delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
+        
+        // 验证 delegate
+        if delegate.delegate != *signer_info.key {
+            return Err(VaultError::InvalidDelegate.into());
+        }
+        
+        if delegate.owner != vault.owner || delegate.vault != *vault_info.key {
+            return Err(VaultError::InvalidOwner.into());
+        }
+        
+        // 亏损熔断期间 delegate 只能平仓
+        if vault.is_close_only() {
+            msg!("Vault is close-only, delegate cannot lock margin");
+            return Err(VaultError::VaultCloseOnly.into());
+        }
+        
+        // 检查权限
+        let clock = Clock::get()?;
+        let current_slot = clock.slot;
+        if !delegate.is_valid(current_slot, clock.unix_timestamp, vault.delegate_epoch) {
+            return Err(VaultError::DelegateExpired.into());
+        }
+        
+        // 心跳超时的 delegate 不能开新仓
+        if !delegate.is_alive(clock.unix_timestamp) {
+            msg!("Delegate heartbeat missed, last: {}", delegate.last_heartbeat_at);
+            return Err(VaultError::HeartbeatMissed.into());
+        }
+        
+        if !delegate.has_permission(PERM_TRADE) {
+            return Err(VaultError::PermissionDenied.into());
+        }
+        
+        // 检查调用方程序白名单
+        check_program_allowlist(&delegate, instructions_sysvar_info)?;
+        
+        // 检查市场白名单
+        if !delegate.is_market_allowed(market_id) {
+            msg!("Market not allowed for delegate: {}", market_id);
+            return Err(VaultError::MarketNotAllowed.into());
+        }
+        
+        // 链下签名授权：验签并推进 nonce
+        if let Some(auth) = intent {
+            let message = DelegateIntent {
+                program_id: *program_id,
+                vault: *vault_info.key,
+                delegate: delegate.delegate,
+                delegate_epoch: delegate.delegate_epoch,
+                delegate_serial: delegate.serial,
+                action: INTENT_ACTION_LOCK_MARGIN,
+                amount: required_margin,
+                notional: required_notional,
+                market_id,
+                nonce: auth.nonce,
+                expiry_unix_ts: auth.expiry_unix_ts,
+            };
+            verify_delegate_intent(
+                &mut delegate,
+                &message,
+                instructions_sysvar_info,
+                clock.unix_timestamp,
+            )?;
+        }
+        
+        // 检查 notional 限额
+        if !delegate.can_use_notional(required_notional) {
+            return Err(VaultError::NotionalLimitExceeded.into());
+        }
+        
+        // 检查杠杆上限
+        if !delegate.within_leverage(required_margin, required_notional) {
+            msg!("Leverage exceeds delegate max: {}x", delegate.max_leverage);
+            return Err(VaultError::LeverageExceeded.into());
+        }
+        
+        if delegate.is_subdelegate() {
+            // 子 delegate 的敞口计入父 delegate，保证金从父 delegate 的分配额度中扣除
+            let parent_account_info = parent_info.ok_or(VaultError::InvalidDelegate)?;
+            require_writable(parent_account_info)?;
+            
+            let mut parent = load_parent_delegate(
+                program_id,
+                parent_account_info,
+                &delegate,
+                &vault,
+                current_slot,
+                clock.unix_timestamp,
+            )?;
+            
+            // 父 delegate 心跳超时时，其子 delegate 同样不能开新仓
+            if !parent.is_alive(clock.unix_timestamp) {
+                msg!("Parent delegate heartbeat missed, last: {}", parent.last_heartbeat_at);
+                return Err(VaultError::HeartbeatMissed.into());
+            }
+            
+            // 子 delegate 不能超出父 delegate 当前的交易权限、杠杆上限和名义敞口
+            if !parent.has_permission(PERM_TRADE) {
+                return Err(VaultError::PermissionDenied.into());
+            }
+            
+            if !parent.within_leverage(required_margin, required_notional) {
+                msg!("Leverage exceeds parent delegate max: {}x", parent.max_leverage);
+                return Err(VaultError::LeverageExceeded.into());
+            }
+            check_program_allowlist(&parent, instructions_sysvar_info)?;
+            
+            if !parent.is_market_allowed(market_id) {
+                msg!("Market not allowed for parent delegate: {}", market_id);
+                return Err(VaultError::MarketNotAllowed.into());
+            }
+            
+            if !parent.can_use_notional(required_notional) {
+                return Err(VaultError::NotionalLimitExceeded.into());
+            }
+            
+            parent.used_notional = safe_add(parent.used_notional, required_notional)?;
+            parent.lock_allocation(required_margin)?;
+            parent.update_timestamp();
+            parent.serialize(&mut &mut parent_account_info.data.borrow_mut()[..])?;
+        } else {
+            // delegate 只能使用自己的分配额度
+            delegate.lock_allocation(required_margin)?;
+        }
+        
+        // 更新 delegate 的 used_notional
+        delegate.used_notional = safe_add(delegate.used_notional, required_notional)?;
+        delegate.update_timestamp();
+        delegate.serialize(&mut &mut delegate_account_info.data.borrow_mut()[..])?;
+        
+        vault.delegated_collateral = safe_sub(vault.delegated_collateral, required_margin)?;
+        vault.delegate_locked_collateral = safe_add(vault.delegate_locked_collateral, required_margin)?;
+    } else {
+        // 检查保证金充足
+        if vault.free_collateral < required_margin {
+            return Err(VaultError::InsufficientFreeCollateral.into());
+        }
+        
+        vault.free_collateral = safe_sub(vault.free_collateral, required_margin)?;
+    }
+    
+    // 锁定保证金
+    vault.locked_collateral = safe_add(vault.locked_collateral, required_margin)?;
+    vault.total_notional = safe_add(vault.total_notional, required_notional)?;
+    vault.update_timestamp();
+    
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    // Note: 不需要验证余额一致性，因为 lock 不改变总余额，只是内部转移
+    
+    msg!("Locked margin: {}", required_margin);
+    msg!("Locked notional: {}", required_notional);
+    msg!("Market: {}", market_id);
+    msg!("New free collateral: {}", vault.free_collateral);
+    msg!("New locked collateral: {}", vault.locked_collateral);
+    msg!("New total notional: {}", vault.total_notional);
+    
+    Ok(())
+}
+
+/// 解锁保证金并更新 PnL（CPI调用）
+///
+/// 结算对象由 DelegateAccount 决定，而不是由 signer 决定：
+/// 传入 DelegateAccount 时结算到其分配额度（owner 也可代为结算），否则结算到 owner
+fn process_unlock_margin_and_update_pnl(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    unlocked_margin: u64,
+    pnl_delta: i64,
+    notional_delta: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let signer_info = next_account_info(account_info_iter)?;
+    // Optional：owner 结算自己的仓位时以 Program ID 占位
+    let delegate_info = account_info_iter
+        .next()
+        .filter(|info| info.key != program_id);
+    let _global_config_info = next_account_info(account_info_iter)?;
+    let (parent_info, instructions_sysvar_info) = split_optional_accounts(account_info_iter);
+    
+    // 验证
+    require_signer(signer_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 权限验证
+    let is_owner = *signer_info.key == vault.owner;
+    
+    if let Some(delegate_account_info) = delegate_info {
+        // 仓位由 delegate 开立：结算到该 delegate 的分配额度
+        require_writable(delegate_account_info)?;
+        require_owner(delegate_account_info, program_id)?;
+        
+        let mut delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
+        
+        if delegate.owner != vault.owner || delegate.vault != *vault_info.key {
+            return Err(VaultError::InvalidOwner.into());
+        }
+        
+        let clock = Clock::get()?;
+        let current_slot = clock.slot;
+        
+        // owner 可随时代为平仓（包括已撤销或过期的 delegate），delegate 本身需有效
+        if !is_owner {
+            if delegate.delegate != *signer_info.key {
+                return Err(VaultError::InvalidDelegate.into());
+            }
+            
+            if !delegate.is_valid(current_slot, clock.unix_timestamp, vault.delegate_epoch) {
+                return Err(VaultError::DelegateExpired.into());
+            }
+            
+            if !delegate.has_permission(PERM_TRADE) {
+                return Err(VaultError::PermissionDenied.into());
+            }
+            
+            // 检查调用方程序白名单
+            check_program_allowlist(&delegate, instructions_sysvar_info)?;
+        }
+        
+        // 更新 delegate 的 used_notional
+        delegate.apply_notional_delta(notional_delta)?;
+        
+        if delegate.is_subdelegate() {
+            // 子 delegate 的敞口、保证金和 PnL 同步到父 delegate
+            let parent_account_info = parent_info.ok_or(VaultError::InvalidDelegate)?;
+            require_writable(parent_account_info)?;
+            
+            let mut parent = if is_owner {
+                load_parent_for_settlement(program_id, parent_account_info, &delegate, &vault)?
+            } else {
+                let parent = load_parent_delegate(
+                    program_id,
+                    parent_account_info,
+                    &delegate,
+                    &vault,
+                    current_slot,
+                    clock.unix_timestamp,
+                )?;
+                check_program_allowlist(&parent, instructions_sysvar_info)?;
+                parent
+            };
+            
+            parent.apply_notional_delta(notional_delta)?;
+            settle_delegate_unlock(&mut vault, &mut parent, unlocked_margin, pnl_delta)?;
+            parent.update_timestamp();
+            parent.serialize(&mut &mut parent_account_info.data.borrow_mut()[..])?;
+        } else {
+            // 解锁的保证金和 PnL 回到该 delegate 的分配额度
+            settle_delegate_unlock(&mut vault, &mut delegate, unlocked_margin, pnl_delta)?;
+        }
+        
+        delegate.update_timestamp();
+        delegate.serialize(&mut &mut delegate_account_info.data.borrow_mut()[..])?;
+        
+        // 统计 delegate 窗口内净亏损，超限则进入只平仓状态
+        if vault.record_delegate_pnl(pnl_delta, current_slot) {
+            msg!("⚠️  Loss limit exceeded - vault is now close-only");
+            msg!("Window PnL: {}", vault.loss_window_pnl);
+        }
+    } else {
+        // 仓位由 owner 开立：只有 owner 可以结算
+        if !is_owner {
+            return Err(VaultError::InvalidDelegate.into());
+        }
+        
+        if unlocked_margin > vault.owner_locked_collateral() {
+            msg!("Owner locked margin: {}", vault.owner_locked_collateral());
+            return Err(VaultError::InsufficientLockedMargin.into());
+        }
+        
+        // 解锁保证金
+        vault.locked_collateral = safe_sub(vault.locked_collateral, unlocked_margin)?;
+        vault.free_collateral = safe_add(vault.free_collateral, unlocked_margin)?;
+        
+        // 应用 PnL
+        let unabsorbed = vault.absorb_pnl(pnl_delta)?;
+        if unabsorbed > 0 {
+            msg!("⚠️  Unabsorbed loss: {}", unabsorbed);
+        }
+    }
+    
+    // 有待处理的提款请求时，释放的资金优先预留
+    vault.earmark_free_collateral()?;
+    
+    // 更新 vault 总名义敞口
+    vault.apply_notional_delta(notional_delta)?;
+    
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    // Note: PnL 会改变总余额，但这是正常的（盈利增加，亏损减少）
+    // 这里的验证会失败，因为实际的 USDC 转账由业务程序处理
+    // 所以 unlock 时不验证余额一致性
+    
+    msg!("Unlocked margin: {}", unlocked_margin);
+    msg!("PnL delta: {}", pnl_delta);
+    msg!("Notional delta: {}", notional_delta);
+    msg!("New free collateral: {}", vault.free_collateral);
+    msg!("New locked collateral: {}", vault.locked_collateral);
+    msg!("New total notional: {}", vault.total_notional);
+    
+    Ok(())
+}
+
+/// 读取子 delegate 的父 delegate 用于 owner 代为结算
+///
+/// 只校验账户关系，不要求父 delegate 仍然有效
+fn load_parent_for_settlement(
+    program_id: &Pubkey,
+    parent_info: &AccountInfo,
+    child: &DelegateAccount,
+    vault: &UserVault,
+) -> Result<DelegateAccount, ProgramError> {
+    if *parent_info.key != child.parent {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    require_owner(parent_info, program_id)?;
+    
+    let parent = DelegateAccount::try_from_slice(&parent_info.data.borrow())?;
+    
+    if parent.owner != vault.owner {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    Ok(parent)
+}
+
+/// 将一次解锁结算到 delegate 的分配额度
+///
+/// 只结算该 delegate 实际锁定的部分；超出的保证金（引入分配额度之前锁定的仓位）
+/// 按 owner 仓位结算回 free_collateral，PnL 按保证金比例拆分。
+/// 超出分配额度的亏损由 free_collateral 吸收，额度不足不会导致解锁失败。
+fn settle_delegate_unlock(
+    vault: &mut UserVault,
+    holder: &mut DelegateAccount,
+    margin: u64,
+    pnl_delta: i64,
+) -> ProgramResult {
+    let holder_margin = margin.min(holder.allocated_locked);
+    let owner_margin = margin - holder_margin;
+    
+    if owner_margin > vault.owner_locked_collateral() {
+        msg!("Delegate locked margin: {}", holder.allocated_locked);
+        return Err(VaultError::InsufficientLockedMargin.into());
+    }
+    
+    let holder_pnl = if owner_margin == 0 {
+        pnl_delta
+    } else {
+        (pnl_delta as i128 * holder_margin as i128 / margin as i128) as i64
+    };
+    let owner_pnl = pnl_delta - holder_pnl;
+    
+    // delegate 部分：保证金和 PnL 回到分配额度
+    let allocated_before = holder.allocated_collateral;
+    let shortfall = holder.unlock_allocation(holder_margin, holder_pnl)?;
+    
+    vault.locked_collateral = safe_sub(vault.locked_collateral, holder_margin)?;
+    vault.delegate_locked_collateral = safe_sub(vault.delegate_locked_collateral, holder_margin)?;
+    vault.delegated_collateral = safe_sub(vault.delegated_collateral, allocated_before)?;
+    vault.delegated_collateral = safe_add(vault.delegated_collateral, holder.allocated_collateral)?;
+    
+    // owner 部分：保证金回到 free_collateral，超出分配额度的亏损一并由 free_collateral 吸收
+    vault.locked_collateral = safe_sub(vault.locked_collateral, owner_margin)?;
+    vault.free_collateral = safe_add(vault.free_collateral, owner_margin)?;
+    
+    let shortfall = i64::try_from(shortfall).map_err(|_| VaultError::ArithmeticOverflow)?;
+    let unabsorbed = vault.absorb_pnl(owner_pnl.saturating_sub(shortfall))?;
+    if unabsorbed > 0 {
+        msg!("⚠️  Unabsorbed loss: {}", unabsorbed);
+    }
+    
+    // 有待处理的提款请求时，释放的额度优先预留，不能再被锁定
+    earmark_from_allocation(vault, holder)?;
+    
+    Ok(())
+}
+
+/// 分配保证金给 Delegate
+///
+/// Owner 从 free_collateral 中划出额度，delegate 的 LockMargin 只能使用该额度
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[writable]` UserVault PDA
+/// 2. `[signer]` Owner
+fn process_allocate_collateral(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate_pubkey: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(delegate_info)?;
+    require_writable(vault_info)?;
+    require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证
+    if delegate.owner != *owner_info.key || delegate.vault != *vault_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 检查余额
+    if vault.free_collateral < amount {
+        return Err(VaultError::InsufficientFreeCollateral.into());
+    }
+    
+    // 划转额度
+    vault.free_collateral = safe_sub(vault.free_collateral, amount)?;
+    vault.delegated_collateral = safe_add(vault.delegated_collateral, amount)?;
+    vault.update_timestamp();
+    
+    delegate.allocated_collateral = safe_add(delegate.allocated_collateral, amount)?;
+    delegate.update_timestamp();
+    
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    msg!("Allocated {} USDC to delegate: {}", amount, delegate_pubkey);
+    msg!("Delegate allocation: {}", delegate.allocated_collateral);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    Ok(())
+}
+
+/// 收回 Delegate 的分配额度
+///
+/// Owner 将 delegate 未锁定的额度收回到 free_collateral（已撤销的 delegate 也可收回）
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[writable]` UserVault PDA
+/// 2. `[signer]` Owner
+fn process_reclaim_collateral(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate_pubkey: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let delegate_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(delegate_info)?;
+    require_writable(vault_info)?;
+    require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
+    
+    // 验证
+    if delegate.owner != *owner_info.key || delegate.vault != *vault_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
+    
+    // 只能收回未锁定的额度
+    if delegate.allocated_collateral < amount {
+        return Err(VaultError::InsufficientAllocation.into());
+    }
+    
+    // 划转额度
+    delegate.allocated_collateral = safe_sub(delegate.allocated_collateral, amount)?;
+    delegate.update_timestamp();
+    
+    vault.delegated_collateral = safe_sub(vault.delegated_collateral, amount)?;
+    vault.free_collateral = safe_add(vault.free_collateral, amount)?;
+    vault.earmark_free_collateral()?;
+    vault.update_timestamp();
+    
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    msg!("Reclaimed {} USDC from delegate: {}", amount, delegate_pubkey);
+    msg!("Delegate allocation: {}", delegate.allocated_collateral);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    Ok(())
+}
+
+/// 转入储备金
+///
+/// Owner 将 free_collateral 转入储备金，delegate 无法锁定或提取储备金
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+fn process_move_to_reserve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 检查余额
+    if vault.free_collateral < amount {
+        return Err(VaultError::InsufficientFreeCollateral.into());
+    }
+    
+    vault.free_collateral = safe_sub(vault.free_collateral, amount)?;
+    vault.reserve_collateral = safe_add(vault.reserve_collateral, amount)?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("Moved {} USDC to reserve", amount);
+    msg!("New reserve collateral: {}", vault.reserve_collateral);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    Ok(())
+}
+
+/// 释放储备金
+///
+/// Owner 将储备金释放回 free_collateral
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+fn process_release_reserve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    if amount == 0 {
+        return Err(VaultError::InvalidAmount.into());
+    }
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
+    
+    if vault.is_frozen() {
+        return Err(VaultError::VaultFrozen.into());
+    }
+    
+    // 检查储备金
+    if vault.reserve_collateral < amount {
+        return Err(VaultError::InsufficientReserve.into());
+    }
+    
+    vault.reserve_collateral = safe_sub(vault.reserve_collateral, amount)?;
+    vault.free_collateral = safe_add(vault.free_collateral, amount)?;
+    vault.earmark_free_collateral()?;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("Released {} USDC from reserve", amount);
+    msg!("New reserve collateral: {}", vault.reserve_collateral);
+    msg!("New free collateral: {}", vault.free_collateral);
+    
+    Ok(())
+}
+
+/// 转移 Admin 权限
+///
+/// 将 GlobalConfig 的 admin 转移给新地址
+///
+/// # 账户
+/// 0. `[writable]` GlobalConfig PDA
+/// 1. `[signer]` Current Admin
+fn process_transfer_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let global_config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(admin_info)?;
+    require_writable(global_config_info)?;
+    require_owner(global_config_info, program_id)?;
+    
+    // 读取 global config
+    let mut global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
+    
+    // 验证当前 admin
+    if global_config.admin != *admin_info.key {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    
+    // 不允许转移给默认地址（使用 RenounceAdmin）
+    if new_admin == Pubkey::default() {
+        msg!("Cannot transfer to default pubkey. Use RenounceAdmin instead.");
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    
+    // 转移 admin
+    let old_admin = global_config.admin;
+    global_config.admin = new_admin;
+    global_config.serialize(&mut &mut global_config_info.data.borrow_mut()[..])?;
+    
+    msg!("✅ Admin transferred");
+    msg!("Old admin: {}", old_admin);
+    msg!("New admin: {}", new_admin);
+    
+    Ok(())
+}
+
+/// 放弃 Admin 权限（实现完全非托管）
+///
+/// 将 admin 设为 Pubkey::default()，之后无人可修改 GlobalConfig
+///
+/// # 账户
+/// 0. `[writable]` GlobalConfig PDA
+/// 1. `[signer]` Current Admin
+fn process_renounce_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let global_config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(admin_info)?;
+    require_writable(global_config_info)?;
+    require_owner(global_config_info, program_id)?;
+    
+    // 读取 global config
+    let mut global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
+    
+    // 验证当前 admin
+    if global_config.admin != *admin_info.key {
+        return Err(VaultError::InvalidAuthority.into());
+    }
+    
+    // 放弃 admin
+    let old_admin = global_config.admin;
+    global_config.admin = Pubkey::default();
+    global_config.serialize(&mut &mut global_config_info.data.borrow_mut()[..])?;
+    
+    msg!("⚠️  Admin renounced - Program is now fully non-custodial!");
+    msg!("Previous admin: {}", old_admin);
+    msg!("New admin: {} (none)", Pubkey::default());
+    
+    Ok(())
+}
+
+/// 设置全局最大杠杆
+///
+/// Admin 设置协议级别的杠杆上限，对所有 LockMargin 生效
+///
+/// # 账户
+/// 0. `[writable]` GlobalConfig PDA
+/// 1. `[signer]` Current Admin
+fn process_set_max_leverage(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    delegate_pubkey: Pubkey,
-    permissions: u64,
-    max_notional: u64,
-    expiry_slot: u64,
+    max_leverage: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
-    let delegate_info = next_account_info(account_info_iter)?;
-    let vault_info = next_account_info(account_info_iter)?;
-    let owner_info = next_account_info(account_info_iter)?;
-    let _global_config_info = next_account_info(account_info_iter)?;
-    let system_program_info = next_account_info(account_info_iter)?;
+    let global_config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
     
     // 验证
-    require_signer(owner_info)?;
-    require_writable(delegate_info)?;
-    require_writable(vault_info)?;
-    require_owner(vault_info, program_id)?;
+    require_signer(admin_info)?;
+    require_writable(global_config_info)?;
+    require_owner(global_config_info, program_id)?;
     
-    // 参数边界检查
-    if permissions == 0 {
-        msg!("Permissions cannot be empty");
-        return Err(VaultError::InvalidPermissions.into());
+    if max_leverage > MAX_LEVERAGE_LIMIT {
+        msg!("Max leverage too large: {}", max_leverage);
+        return Err(VaultError::InvalidMaxLeverage.into());
     }
     
-    if max_notional == 0 {
-        msg!("Max notional must be greater than 0");
-        return Err(VaultError::InvalidMaxNotional.into());
-    }
+    // 读取 global config
+    let mut global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
     
-    // 最大名义敞口上限：1B USDC
-    const MAX_NOTIONAL_LIMIT: u64 = 1_000_000_000_000_000;
-    if max_notional > MAX_NOTIONAL_LIMIT {
-        msg!("Max notional too large: {}", max_notional);
-        return Err(VaultError::InvalidMaxNotional.into());
+    // 验证当前 admin
+    if global_config.admin != *admin_info.key {
+        return Err(VaultError::InvalidAuthority.into());
     }
     
-    // 读取 vault
-    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    global_config.max_leverage = max_leverage;
+    global_config.serialize(&mut &mut global_config_info.data.borrow_mut()[..])?;
     
-    // 验证 owner
-    if vault.owner != *owner_info.key {
-        return Err(VaultError::InvalidOwner.into());
-    }
+    msg!("Global max leverage set: {}", max_leverage);
     
-    // 验证过期时间
-    let current_slot = Clock::get()?.slot;
-    if expiry_slot <= current_slot {
-        return Err(VaultError::InvalidExpirySlot.into());
+    Ok(())
+}
+
+/// 设置每个 vault 最多有效 delegate 数量
+///
+/// # 账户
+/// 0. `[writable]` GlobalConfig PDA
+/// 1. `[signer]` Current Admin
+fn process_set_max_delegates_per_vault(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_delegates: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    
+    let global_config_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(admin_info)?;
+    require_writable(global_config_info)?;
+    require_owner(global_config_info, program_id)?;
+    
+    // 限制 registry 账户大小（128 条约 14KB）
+    const MAX_DELEGATES_LIMIT: u64 = 128;
+    if max_delegates == 0 || max_delegates > MAX_DELEGATES_LIMIT {
+        msg!("Max delegates must be in 1..={}", MAX_DELEGATES_LIMIT);
+        return Err(VaultError::InvalidMaxDelegates.into());
     }
     
-    // 限制最大有效期（1 年）
-    const MAX_EXPIRY_DURATION: u64 = 365 * 24 * 60 * 60 / 2; // 约 1 年的 slots (假设 2s/slot)
-    if expiry_slot > current_slot + MAX_EXPIRY_DURATION {
-        msg!("Expiry too far in future. Max: 1 year");
-        return Err(VaultError::InvalidExpirySlot.into());
+    // 读取 global config
+    let mut global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
+    
+    // 验证当前 admin
+    if global_config.admin != *admin_info.key {
+        return Err(VaultError::InvalidAuthority.into());
     }
     
-    // 派生 DelegateAccount PDA
-    let delegate_seeds = &[
-        b"delegate".as_ref(),
-        owner_info.key.as_ref(),
-        delegate_pubkey.as_ref(),
-    ];
-    let delegate_bump = verify_pda(delegate_info.key, program_id, delegate_seeds)?;
-    let delegate_seeds_with_bump = &[
-        b"delegate".as_ref(),
-        owner_info.key.as_ref(),
-        delegate_pubkey.as_ref(),
-        &[delegate_bump],
-    ];
+    global_config.max_delegates_per_vault = max_delegates;
+    global_config.serialize(&mut &mut global_config_info.data.borrow_mut()[..])?;
     
-    let rent = Rent::get()?;
+    msg!("Max delegates per vault set: {}", max_delegates);
     
-    // 检查账户是否存在
-    let is_new = delegate_info.data_len() == 0;
+    Ok(())
+}
+
+/// 冻结 Vault
+///
+/// Owner 可以冻结自己的 vault，阻止所有操作（除了解冻）
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+fn process_freeze_vault(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
     
-    if is_new {
-        // 创建新账户
-        create_pda_account(
-            owner_info,
-            delegate_info,
-            system_program_info,
-            program_id,
-            &rent,
-            DelegateAccount::SIZE,
-            delegate_seeds_with_bump,
-        )?;
-        
-        // 初始化 delegate
-        let delegate = DelegateAccount::new(
-            *owner_info.key,
-            *vault_info.key,
-            delegate_pubkey,
-            permissions,
-            max_notional,
-            expiry_slot,
-            delegate_bump,
-        );
-        delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
-        
-        msg!("Delegate created: {}", delegate_pubkey);
-    } else {
-        // 更新现有 delegate
-        let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
-        
-        // 验证
-        if delegate.owner != *owner_info.key {
-            return Err(VaultError::InvalidOwner.into());
-        }
-        
-        // 更新字段
-        delegate.permissions = permissions;
-        delegate.max_notional = max_notional;
-        delegate.expiry_slot = expiry_slot;
-        delegate.is_active = true;
-        delegate.update_timestamp();
-        
-        delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
-        
-        msg!("Delegate updated: {}", delegate_pubkey);
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
+    
+    // 验证
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
     }
     
-    msg!("Permissions: {:064b}", permissions);
-    msg!("Max notional: {}", max_notional);
-    msg!("Expiry slot: {}", expiry_slot);
+    // 检查是否已冻结
+    if vault.is_frozen() {
+        msg!("Vault is already frozen");
+        return Ok(());
+    }
+    
+    // 冻结
+    vault.freeze();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    
+    msg!("🧊 Vault frozen");
+    msg!("Owner: {}", owner_info.key);
     
     Ok(())
 }
 
-/// 撤销 Delegate
-fn process_revoke_delegate(
+/// 设置 Delegate 代存款上限
+///
+/// 每个窗口内 delegate 通过 DelegateDeposit 存入的总额不能超过 deposit_limit，
+/// deposit_limit 为 0 表示禁止代存款
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[]` UserVault PDA
+/// 2. `[signer]` Owner
+fn process_set_delegate_deposit_limit(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     delegate_pubkey: Pubkey,
+    deposit_limit: u64,
+    window_slots: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
     let delegate_info = next_account_info(account_info_iter)?;
     let vault_info = next_account_info(account_info_iter)?;
     let owner_info = next_account_info(account_info_iter)?;
-    let _global_config_info = next_account_info(account_info_iter)?;
     
     // 验证
     require_signer(owner_info)?;
     require_writable(delegate_info)?;
     require_owner(delegate_info, program_id)?;
+    require_owner(vault_info, program_id)?;
+    
+    // 参数边界检查
+    if deposit_limit > 0 && window_slots == 0 {
+        msg!("Window slots must be greater than 0");
+        return Err(VaultError::InvalidDepositLimit.into());
+    }
     
     // 读取 vault
     let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
@@ -594,295 +4163,335 @@ fn process_revoke_delegate(
         return Err(VaultError::InvalidDelegate.into());
     }
     
-    // 撤销
-    delegate.is_active = false;
-    delegate.nonce = u64::MAX; // 防止旧交易重放
+    // 更新参数并重新开始计数
+    delegate.deposit_limit = deposit_limit;
+    delegate.deposit_window_slots = window_slots;
+    delegate.deposit_window_start_slot = Clock::get()?.slot;
+    delegate.deposit_window_used = 0;
     delegate.update_timestamp();
-    
     delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
     
-    msg!("Delegate revoked: {}", delegate_pubkey);
+    msg!("Delegate deposit limit set: {}", delegate_pubkey);
+    msg!("Deposit limit: {}", deposit_limit);
+    msg!("Window slots: {}", window_slots);
     
     Ok(())
 }
 
-/// 锁定保证金（CPI调用）
-fn process_lock_margin(
+/// 设置 Delegate 提款 co-sign 阈值
+///
+/// delegate 提款金额超过 threshold 时需要 owner 同时签名，0 表示不要求
+///
+/// # 账户
+/// 0. `[writable]` DelegateAccount PDA
+/// 1. `[]` UserVault PDA
+/// 2. `[signer]` Owner
+fn process_set_delegate_cosign_threshold(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    required_margin: u64,
-    required_notional: u64,
+    delegate_pubkey: Pubkey,
+    threshold: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
+    let delegate_info = next_account_info(account_info_iter)?;
     let vault_info = next_account_info(account_info_iter)?;
-    let signer_info = next_account_info(account_info_iter)?;
-    let delegate_info = account_info_iter.next(); // Optional
-    let _global_config_info = next_account_info(account_info_iter)?;
-    let _clock_sysvar_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
     
     // 验证
-    require_signer(signer_info)?;
-    require_writable(vault_info)?;
+    require_signer(owner_info)?;
+    require_writable(delegate_info)?;
+    require_owner(delegate_info, program_id)?;
     require_owner(vault_info, program_id)?;
     
     // 读取 vault
-    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
-    
-    // 检查是否冻结
-    if vault.is_frozen() {
-        return Err(VaultError::VaultFrozen.into());
-    }
-    
-    // 权限验证
-    let is_owner = *signer_info.key == vault.owner;
-    
-    if !is_owner {
-        // 如果不是 owner，必须是有 TRADE 权限的 delegate
-        let delegate_account_info = delegate_info.ok_or(VaultError::InvalidDelegate)?;
-        require_writable(delegate_account_info)?;
-        
-        let mut delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
-        
-        // 验证 delegate
-        if delegate.delegate != *signer_info.key {
-            return Err(VaultError::InvalidDelegate.into());
-        }
-        
-        if delegate.owner != vault.owner {
-            return Err(VaultError::InvalidOwner.into());
-        }
-        
-        // 检查权限
-        let current_slot = Clock::get()?.slot;
-        if !delegate.is_valid(current_slot) {
-            return Err(VaultError::DelegateExpired.into());
-        }
-        
-        if !delegate.has_permission(PERM_TRADE) {
-            return Err(VaultError::PermissionDenied.into());
-        }
-        
-        // 检查 notional 限额
-        if !delegate.can_use_notional(required_notional) {
-            return Err(VaultError::NotionalLimitExceeded.into());
-        }
-        
-        // 更新 delegate 的 used_notional
-        delegate.used_notional = safe_add(delegate.used_notional, required_notional)?;
-        delegate.update_timestamp();
-        delegate.serialize(&mut &mut delegate_account_info.data.borrow_mut()[..])?;
-    }
+    let vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
     
-    // 检查保证金充足
-    if vault.free_collateral < required_margin {
-        return Err(VaultError::InsufficientFreeCollateral.into());
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
     }
     
-    // 锁定保证金
-    vault.free_collateral = safe_sub(vault.free_collateral, required_margin)?;
-    vault.locked_collateral = safe_add(vault.locked_collateral, required_margin)?;
-    vault.update_timestamp();
+    // 读取 delegate
+    let mut delegate = DelegateAccount::try_from_slice(&delegate_info.data.borrow())?;
     
-    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    // 验证
+    if delegate.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
     
-    // Note: 不需要验证余额一致性，因为 lock 不改变总余额，只是内部转移
+    if delegate.delegate != delegate_pubkey {
+        return Err(VaultError::InvalidDelegate.into());
+    }
     
-    msg!("Locked margin: {}", required_margin);
-    msg!("Locked notional: {}", required_notional);
-    msg!("New free collateral: {}", vault.free_collateral);
-    msg!("New locked collateral: {}", vault.locked_collateral);
+    delegate.cosign_threshold = threshold;
+    delegate.update_timestamp();
+    delegate.serialize(&mut &mut delegate_info.data.borrow_mut()[..])?;
+    
+    msg!("Delegate co-sign threshold set: {}", delegate_pubkey);
+    msg!("Threshold: {}", threshold);
     
     Ok(())
 }
 
-/// 解锁保证金并更新 PnL（CPI调用）
-fn process_unlock_margin_and_update_pnl(
+/// 解冻 Vault
+///
+/// Owner 可以解冻自己的 vault，恢复正常操作
+///
+/// # 账户
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+fn process_unfreeze_vault(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    unlocked_margin: u64,
-    pnl_delta: i64,
-    notional_delta: i64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
     let vault_info = next_account_info(account_info_iter)?;
-    let signer_info = next_account_info(account_info_iter)?;
-    let delegate_info = account_info_iter.next(); // Optional
-    let _global_config_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
     
     // 验证
-    require_signer(signer_info)?;
+    require_signer(owner_info)?;
     require_writable(vault_info)?;
     require_owner(vault_info, program_id)?;
     
     // 读取 vault
     let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
     
-    // 权限验证
-    let is_owner = *signer_info.key == vault.owner;
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
+    }
     
-    if !is_owner {
-        // 如果不是 owner，必须是有 TRADE 权限的 delegate
-        let delegate_account_info = delegate_info.ok_or(VaultError::InvalidDelegate)?;
-        require_writable(delegate_account_info)?;
-        
-        let mut delegate = DelegateAccount::try_from_slice(&delegate_account_info.data.borrow())?;
-        
-        // 验证 delegate
-        if delegate.delegate != *signer_info.key {
-            return Err(VaultError::InvalidDelegate.into());
-        }
-        
-        if delegate.owner != vault.owner {
-            return Err(VaultError::InvalidOwner.into());
-        }
-        
-        // 检查权限
-        let current_slot = Clock::get()?.slot;
-        if !delegate.is_valid(current_slot) {
-            return Err(VaultError::DelegateExpired.into());
-        }
-        
-        if !delegate.has_permission(PERM_TRADE) {
-            return Err(VaultError::PermissionDenied.into());
-        }
-        
-        // 更新 delegate 的 used_notional
-        if notional_delta < 0 {
-            delegate.used_notional = safe_sub(delegate.used_notional, notional_delta.unsigned_abs())?;
-        } else {
-            // 释放敞口时 notional_delta 应该是负数
-            // 如果是正数，可能是错误，但为了兼容性也支持
-            delegate.used_notional = safe_add(delegate.used_notional, notional_delta as u64)?;
-        }
-        
-        delegate.update_timestamp();
-        delegate.serialize(&mut &mut delegate_account_info.data.borrow_mut()[..])?;
+    // 检查是否未冻结
+    if !vault.is_frozen() {
+        msg!("Vault is not frozen");
+        return Ok(());
     }
     
-    // 解锁保证金
-    vault.locked_collateral = safe_sub(vault.locked_collateral, unlocked_margin)?;
-    vault.free_collateral = safe_add(vault.free_collateral, unlocked_margin)?;
+    // 解冻
+    vault.unfreeze();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
     
-    // 应用 PnL
-    vault.free_collateral = safe_add_signed(vault.free_collateral, pnl_delta)?;
+    msg!("✅ Vault unfrozen");
+    msg!("Owner: {}", owner_info.key);
     
-    vault.update_timestamp();
-    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// 迁移旧版本账户
+///
+/// 旧布局的字段都是新布局的前缀，新增字段占用原 reserved 区域或追加在其后，
+/// 因此补 0 到当前大小即可按新布局读取，再补齐非 0 的默认值并写入当前 version
+///
+/// # 账户
+/// 0. `[writable]` UserVault 或 DelegateAccount PDA
+/// 1. `[signer, writable]` Payer
+/// 2. `[]` System Program
+fn process_migrate_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
     
-    // Note: PnL 会改变总余额，但这是正常的（盈利增加，亏损减少）
-    // 这里的验证会失败，因为实际的 USDC 转账由业务程序处理
-    // 所以 unlock 时不验证余额一致性
+    let account_info = next_account_info(account_info_iter)?;
+    let payer_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
     
-    msg!("Unlocked margin: {}", unlocked_margin);
-    msg!("PnL delta: {}", pnl_delta);
-    msg!("Notional delta: {}", notional_delta);
-    msg!("New free collateral: {}", vault.free_collateral);
-    msg!("New locked collateral: {}", vault.locked_collateral);
+    // 验证
+    require_signer(payer_info)?;
+    require_writable(payer_info)?;
+    require_writable(account_info)?;
+    require_owner(account_info, program_id)?;
+    
+    let mut data = account_info.data.borrow().to_vec();
+    if data.len() < 9 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&data[..8]);
+    let discriminator = u64::from_le_bytes(discriminator);
+    let old_version = data[8];
+    let old_size = data.len();
+    
+    let (size, version) = match discriminator {
+        UserVault::DISCRIMINATOR => (UserVault::SIZE, UserVault::VERSION),
+        DelegateAccount::DISCRIMINATOR => (DelegateAccount::SIZE, DelegateAccount::VERSION),
+        _ => {
+            msg!("Unsupported account type: {:#018x}", discriminator);
+            return Err(ProgramError::InvalidAccountData);
+        }
+    };
+    
+    if old_version > version {
+        msg!("Unsupported account version: {}", old_version);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    
+    if old_version == version && old_size == size {
+        msg!("Account already at version {}", version);
+        return Ok(());
+    }
+    
+    // 补 0（或截掉旧版本多分配的空间）后按当前布局读取
+    data.resize(size, 0);
+    let migrated = if discriminator == UserVault::DISCRIMINATOR {
+        let mut vault = UserVault::try_from_slice(&data)?;
+        vault.version = version;
+        if vault.rent_payer == Pubkey::default() {
+            vault.rent_payer = vault.owner;
+        }
+        vault.try_to_vec()?
+    } else {
+        let mut delegate = DelegateAccount::try_from_slice(&data)?;
+        delegate.version = version;
+        if delegate.rent_payer == Pubkey::default() {
+            delegate.rent_payer = delegate.owner;
+        }
+        delegate.try_to_vec()?
+    };
+    
+    if old_size < size {
+        let rent = Rent::get()?;
+        realloc_pda_account(payer_info, account_info, system_program_info, &rent, size)?;
+    } else if old_size > size {
+        account_info.realloc(size, false)?;
+    }
+    account_info.data.borrow_mut().copy_from_slice(&migrated);
+    
+    msg!("Account migrated: {}", account_info.key);
+    msg!("Version: {} -> {}", old_version, version);
+    msg!("Size: {} -> {}", old_size, size);
     
     Ok(())
 }
 
-/// 转移 Admin 权限
+/// 设置亏损熔断参数
 ///
-/// 将 GlobalConfig 的 admin 转移给新地址
+/// Owner 配置窗口内 delegate 允许的最大净亏损，loss_limit 为 0 表示关闭
 ///
 /// # 账户
-/// 0. `[writable]` GlobalConfig PDA
-/// 1. `[signer]` Current Admin
-fn process_transfer_admin(
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+fn process_set_loss_limit(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    new_admin: Pubkey,
+    loss_limit: u64,
+    window_slots: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
-    let global_config_info = next_account_info(account_info_iter)?;
-    let admin_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
     
     // 验证
-    require_signer(admin_info)?;
-    require_writable(global_config_info)?;
-    require_owner(global_config_info, program_id)?;
-    
-    // 读取 global config
-    let mut global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
     
-    // 验证当前 admin
-    if global_config.admin != *admin_info.key {
-        return Err(VaultError::InvalidAuthority.into());
+    // 参数边界检查
+    if loss_limit > 0 && window_slots == 0 {
+        msg!("Window slots must be greater than 0");
+        return Err(VaultError::InvalidLossLimit.into());
     }
     
-    // 不允许转移给默认地址（使用 RenounceAdmin）
-    if new_admin == Pubkey::default() {
-        msg!("Cannot transfer to default pubkey. Use RenounceAdmin instead.");
-        return Err(VaultError::InvalidAuthority.into());
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
     }
     
-    // 转移 admin
-    let old_admin = global_config.admin;
-    global_config.admin = new_admin;
-    global_config.serialize(&mut &mut global_config_info.data.borrow_mut()[..])?;
+    // 更新参数并重新开始计数
+    let current_slot = Clock::get()?.slot;
+    vault.loss_limit = loss_limit;
+    vault.loss_window_slots = window_slots;
+    vault.loss_window_start_slot = current_slot;
+    vault.loss_window_pnl = 0;
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
     
-    msg!("✅ Admin transferred");
-    msg!("Old admin: {}", old_admin);
-    msg!("New admin: {}", new_admin);
+    msg!("Loss limit set: {}", loss_limit);
+    msg!("Window slots: {}", window_slots);
     
     Ok(())
 }
 
-/// 放弃 Admin 权限（实现完全非托管）
+/// 最大提款时间锁：约 30 天的 slots
+const MAX_WITHDRAWAL_TIMELOCK_SLOTS: u64 = 30 * SLOTS_PER_DAY;
+
+/// 设置大额提款时间锁
 ///
-/// 将 admin 设为 Pubkey::default()，之后无人可修改 GlobalConfig
+/// 超过 threshold 的提款必须等待 delay_slots，threshold 为 0 表示关闭。
+/// 收紧立即生效；关闭、提高阈值或缩短延迟需等待当前延迟后生效
 ///
 /// # 账户
-/// 0. `[writable]` GlobalConfig PDA
-/// 1. `[signer]` Current Admin
-fn process_renounce_admin(
+/// 0. `[writable]` UserVault PDA
+/// 1. `[signer]` Owner
+fn process_set_withdrawal_timelock(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
+    threshold: u64,
+    delay_slots: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
-    let global_config_info = next_account_info(account_info_iter)?;
-    let admin_info = next_account_info(account_info_iter)?;
+    let vault_info = next_account_info(account_info_iter)?;
+    let owner_info = next_account_info(account_info_iter)?;
     
     // 验证
-    require_signer(admin_info)?;
-    require_writable(global_config_info)?;
-    require_owner(global_config_info, program_id)?;
+    require_signer(owner_info)?;
+    require_writable(vault_info)?;
+    require_owner(vault_info, program_id)?;
     
-    // 读取 global config
-    let mut global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
+    // 参数边界检查
+    if threshold > 0 && (delay_slots == 0 || delay_slots > MAX_WITHDRAWAL_TIMELOCK_SLOTS) {
+        msg!("Delay slots must be between 1 and {}", MAX_WITHDRAWAL_TIMELOCK_SLOTS);
+        return Err(VaultError::InvalidWithdrawalTimelock.into());
+    }
     
-    // 验证当前 admin
-    if global_config.admin != *admin_info.key {
-        return Err(VaultError::InvalidAuthority.into());
+    // 读取 vault
+    let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
+    
+    // 验证 owner
+    if vault.owner != *owner_info.key {
+        return Err(VaultError::InvalidOwner.into());
     }
     
-    // 放弃 admin
-    let old_admin = global_config.admin;
-    global_config.admin = Pubkey::default();
-    global_config.serialize(&mut &mut global_config_info.data.borrow_mut()[..])?;
+    let current_slot = Clock::get()?.slot;
+    vault.apply_pending_withdrawal_timelock(current_slot);
+    
+    if vault.weakens_withdrawal_timelock(threshold, delay_slots) {
+        // 放宽时间锁需等待当前延迟，防止一笔交易移除取消窗口
+        let effective_slot = safe_add(current_slot, vault.withdrawal_timelock_slots)?;
+        vault.pending_timelock_threshold = threshold;
+        vault.pending_timelock_slots = delay_slots;
+        vault.pending_timelock_effective_slot = effective_slot;
+        msg!("Withdrawal timelock change queued until slot {}", effective_slot);
+    } else {
+        // 收紧立即生效，并取消排队中的放宽设置
+        vault.withdrawal_timelock_threshold = threshold;
+        vault.withdrawal_timelock_slots = delay_slots;
+        vault.clear_pending_withdrawal_timelock();
+    }
+    vault.update_timestamp();
+    vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
     
-    msg!("⚠️  Admin renounced - Program is now fully non-custodial!");
-    msg!("Previous admin: {}", old_admin);
-    msg!("New admin: {} (none)", Pubkey::default());
+    msg!("Withdrawal timelock threshold: {}", threshold);
+    msg!("Delay slots: {}", delay_slots);
     
     Ok(())
 }
 
-/// 冻结 Vault
+/// 设置 vault 总名义敞口上限
 ///
-/// Owner 可以冻结自己的 vault，阻止所有操作（除了解冻）
+/// Owner 限制 owner 与所有 delegate 的总名义敞口，0 表示不限制
 ///
 /// # 账户
 /// 0. `[writable]` UserVault PDA
 /// 1. `[signer]` Owner
-fn process_freeze_vault(
+fn process_set_max_total_notional(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
+    max_total_notional: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     
@@ -894,6 +4503,11 @@ fn process_freeze_vault(
     require_writable(vault_info)?;
     require_owner(vault_info, program_id)?;
     
+    if max_total_notional > MAX_NOTIONAL_LIMIT {
+        msg!("Max total notional too large: {}", max_total_notional);
+        return Err(VaultError::InvalidMaxNotional.into());
+    }
+    
     // 读取 vault
     let mut vault = UserVault::try_from_slice(&vault_info.data.borrow())?;
     
@@ -902,30 +4516,24 @@ fn process_freeze_vault(
         return Err(VaultError::InvalidOwner.into());
     }
     
-    // 检查是否已冻结
-    if vault.is_frozen() {
-        msg!("Vault is already frozen");
-        return Ok(());
-    }
-    
-    // 冻结
-    vault.freeze();
+    vault.max_total_notional = max_total_notional;
+    vault.update_timestamp();
     vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
     
-    msg!("🧊 Vault frozen");
-    msg!("Owner: {}", owner_info.key);
+    msg!("Max total notional set: {}", max_total_notional);
+    msg!("Current total notional: {}", vault.total_notional);
     
     Ok(())
 }
 
-/// 解冻 Vault
+/// 恢复交易
 ///
-/// Owner 可以解冻自己的 vault，恢复正常操作
+/// Owner 解除亏损熔断，delegate 可以重新开仓
 ///
 /// # 账户
 /// 0. `[writable]` UserVault PDA
 /// 1. `[signer]` Owner
-fn process_unfreeze_vault(
+fn process_resume_trading(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
 ) -> ProgramResult {
@@ -947,19 +4555,127 @@ fn process_unfreeze_vault(
         return Err(VaultError::InvalidOwner.into());
     }
     
-    // 检查是否未冻结
-    if !vault.is_frozen() {
-        msg!("Vault is not frozen");
+    // 检查是否处于熔断状态
+    if !vault.is_close_only() {
+        msg!("Vault is not close-only");
         return Ok(());
     }
     
-    // 解冻
-    vault.unfreeze();
+    // 恢复
+    let current_slot = Clock::get()?.slot;
+    vault.resume_trading(current_slot);
     vault.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
     
-    msg!("✅ Vault unfrozen");
+    msg!("✅ Trading resumed");
     msg!("Owner: {}", owner_info.key);
     
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> UserVault {
+        UserVault::new(Pubkey::new_unique(), Pubkey::new_unique(), 255, 254)
+    }
+
+    fn delegate(vault: &UserVault) -> DelegateAccount {
+        DelegateAccount::new(
+            vault.owner,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            PERM_TRADE,
+            1_000_000,
+            1_000,
+            0,
+            0,
+            [0; DELEGATE_LABEL_LEN],
+            255,
+        )
+    }
+
+    #[test]
+    fn settle_returns_margin_and_pnl_to_allocation() {
+        let mut vault = vault();
+        let mut holder = delegate(&vault);
+        holder.allocated_collateral = 40;
+        holder.allocated_locked = 60;
+        vault.locked_collateral = 60;
+        vault.delegate_locked_collateral = 60;
+        vault.delegated_collateral = 40;
+
+        settle_delegate_unlock(&mut vault, &mut holder, 60, 15).unwrap();
+        assert_eq!(holder.allocated_collateral, 115);
+        assert_eq!(holder.allocated_locked, 0);
+        assert_eq!(vault.locked_collateral, 0);
+        assert_eq!(vault.delegate_locked_collateral, 0);
+        assert_eq!(vault.delegated_collateral, 115);
+        assert_eq!(vault.free_collateral, 0);
+    }
+
+    #[test]
+    fn settle_splits_pnl_pro_rata_with_owner_margin() {
+        // 引入分配额度之前锁定的仓位：locked_collateral 中只有 25 属于该 delegate
+        let mut vault = vault();
+        let mut holder = delegate(&vault);
+        holder.allocated_locked = 25;
+        vault.locked_collateral = 100;
+        vault.delegate_locked_collateral = 25;
+        vault.free_collateral = 50;
+
+        settle_delegate_unlock(&mut vault, &mut holder, 100, -40).unwrap();
+        // delegate 承担 25/100 的亏损，其余 75 保证金和 -30 PnL 按 owner 仓位结算
+        assert_eq!(holder.allocated_collateral, 15);
+        assert_eq!(holder.allocated_locked, 0);
+        assert_eq!(vault.delegated_collateral, 15);
+        assert_eq!(vault.locked_collateral, 0);
+        assert_eq!(vault.delegate_locked_collateral, 0);
+        assert_eq!(vault.free_collateral, 50 + 75 - 30);
+    }
+
+    #[test]
+    fn settle_absorbs_allocation_shortfall_from_free_collateral() {
+        let mut vault = vault();
+        let mut holder = delegate(&vault);
+        holder.allocated_locked = 20;
+        vault.locked_collateral = 20;
+        vault.delegate_locked_collateral = 20;
+        vault.free_collateral = 100;
+
+        settle_delegate_unlock(&mut vault, &mut holder, 20, -50).unwrap();
+        assert_eq!(holder.allocated_collateral, 0);
+        assert_eq!(vault.delegated_collateral, 0);
+        assert_eq!(vault.free_collateral, 70);
+    }
+
+    #[test]
+    fn settle_rejects_margin_beyond_locked() {
+        let mut vault = vault();
+        let mut holder = delegate(&vault);
+        holder.allocated_locked = 20;
+        vault.locked_collateral = 20;
+        vault.delegate_locked_collateral = 20;
+
+        assert_eq!(
+            settle_delegate_unlock(&mut vault, &mut holder, 30, 0),
+            Err(VaultError::InsufficientLockedMargin.into())
+        );
+    }
+
+    #[test]
+    fn settle_earmarks_released_allocation_for_withdrawal_request() {
+        let mut vault = vault();
+        let mut holder = delegate(&vault);
+        holder.allocated_locked = 60;
+        vault.locked_collateral = 60;
+        vault.delegate_locked_collateral = 60;
+        vault.withdrawal_requested = 50;
+
+        settle_delegate_unlock(&mut vault, &mut holder, 60, 10).unwrap();
+        assert_eq!(vault.earmarked_collateral, 50);
+        assert_eq!(vault.withdrawal_requested, 0);
+        assert_eq!(holder.allocated_collateral, 20);
+        assert_eq!(vault.delegated_collateral, 20);
+    }
+}
@@ -6,7 +6,9 @@
 //! - DelegateAccount: API Key 授权记录
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::{pubkey::Pubkey, clock::Clock, sysvar::Sysvar};
+use solana_program::{pubkey::Pubkey, clock::Clock, program_error::ProgramError, sysvar::Sysvar};
+
+use crate::error::VaultError;
 
 /// 全局配置（单例PDA）
 /// PDA Seeds: [b"global", version]
@@ -33,17 +35,26 @@ pub struct GlobalConfig {
     /// 创建时间戳（秒）
     pub created_at: i64,
     
+    /// 全局最大杠杆倍数（notional / margin，0 表示不限制）
+    pub max_leverage: u64,
+    
+    /// 每个 vault 最多有效 delegate 数量（0 表示使用默认值）
+    pub max_delegates_per_vault: u64,
+    
     /// 预留扩展字段
-    pub reserved: [u8; 64],
+    pub reserved: [u8; 48],
 }
 
 impl GlobalConfig {
     pub const DISCRIMINATOR: u64 = 0x474c4243_46470000;
     pub const VERSION: u8 = 1;
     
-    /// 8 + 1 + 1 + 6 + 32 + 32 + 8 + 64 = 152 bytes
+    /// 8 + 1 + 1 + 6 + 32 + 32 + 8 + 8 + 8 + 48 = 152 bytes
     pub const SIZE: usize = 152;
     
+    /// 默认每个 vault 最多有效 delegate 数量
+    pub const DEFAULT_MAX_DELEGATES_PER_VAULT: u64 = 32;
+    
     pub fn new(admin: Pubkey, usdc_mint: Pubkey, bump: u8) -> Self {
         let now = Clock::get()
             .map(|clock| clock.unix_timestamp)
@@ -57,7 +68,18 @@ impl GlobalConfig {
             admin,
             usdc_mint,
             created_at: now,
-            reserved: [0; 64],
+            max_leverage: 0,
+            max_delegates_per_vault: Self::DEFAULT_MAX_DELEGATES_PER_VAULT,
+            reserved: [0; 48],
+        }
+    }
+    
+    /// 每个 vault 最多有效 delegate 数量
+    pub fn max_delegates(&self) -> u64 {
+        if self.max_delegates_per_vault == 0 {
+            Self::DEFAULT_MAX_DELEGATES_PER_VAULT
+        } else {
+            self.max_delegates_per_vault
         }
     }
 }
@@ -101,7 +123,8 @@ pub struct UserVault {
     
     /// 状态标记位
     /// Bit 0: is_frozen (冻结)
-    /// Bit 1-63: 预留
+    /// Bit 1: is_close_only (亏损熔断，delegate 只能平仓)
+    /// Bit 2-63: 预留
     pub flags: u64,
     
     /// 创建时间戳（秒）
@@ -110,16 +133,87 @@ pub struct UserVault {
     /// 更新时间戳（秒）
     pub updated_at: i64,
     
+    /// 窗口内最大允许亏损（e6格式，0 表示不启用）
+    pub loss_limit: u64,
+    
+    /// 亏损统计窗口长度（slot 数）
+    pub loss_window_slots: u64,
+    
+    /// 当前亏损统计窗口起始 slot
+    pub loss_window_start_slot: u64,
+    
+    /// 当前窗口内 delegate 累计净 PnL（e6格式）
+    pub loss_window_pnl: i64,
+    
+    /// 已分配给各 delegate 且未锁定的保证金总和（e6格式）
+    pub delegated_collateral: u64,
+    
+    /// locked_collateral 中由 delegate 分配额度锁定的部分（e6格式），其余属于 owner
+    pub delegate_locked_collateral: u64,
+    
+    /// Owner 专用储备金（e6格式，delegate 无法使用）
+    pub reserve_collateral: u64,
+    
+    /// 当前总名义敞口（owner + 所有 delegate，e6格式）
+    pub total_notional: u64,
+    
+    /// 总名义敞口上限（e6格式，0 表示不限制）
+    pub max_total_notional: u64,
+    
+    /// Delegate 代数（RevokeAllDelegates 时递增，旧代数的 delegate 全部失效）
+    pub delegate_epoch: u64,
+    
+    /// 已创建的 DelegateAccount 数量（只增不减，作为 DelegateAccount.serial 分配）
+    pub delegate_serial: u64,
+    
+    /// Owner 链下签名许可的 nonce（每执行一个许可递增）
+    pub permit_nonce: u64,
+    
+    /// 支付 UserVault 和 Vault USDC 账户租金的账户（关闭时租金退回）
+    pub rent_payer: Pubkey,
+    
+    /// 提款请求中尚未预留的金额（e6格式）
+    pub withdrawal_requested: u64,
+    
+    /// 已为提款请求预留、等待 ExecuteWithdrawal 支付的保证金（e6格式）
+    pub earmarked_collateral: u64,
+    
+    /// 需要时间锁的提款金额阈值（e6格式，0 表示不启用）
+    pub withdrawal_timelock_threshold: u64,
+    
+    /// 大额提款的时间锁长度（slot 数）
+    pub withdrawal_timelock_slots: u64,
+    
+    /// 已进入时间锁、等待 ExecutePendingWithdrawal 的保证金（e6格式）
+    pub timelocked_collateral: u64,
+    
+    /// 排队中的时间锁阈值（放宽时间锁需等待当前延迟后生效）
+    pub pending_timelock_threshold: u64,
+    
+    /// 排队中的时间锁长度（slot 数）
+    pub pending_timelock_slots: u64,
+    
+    /// 排队中的时间锁设置生效的 slot（0 表示没有排队的设置）
+    pub pending_timelock_effective_slot: u64,
+    
     /// 预留扩展字段
-    pub reserved: [u8; 64],
+    pub reserved: [u8; 16],
 }
 
 impl UserVault {
     pub const DISCRIMINATOR: u64 = 0x55534552_564c5400;
-    pub const VERSION: u8 = 1;
     
-    /// 8 + 1 + 1 + 1 + 5 + 32 + 32 + 8*6 + 8 + 8 + 64 = 208 bytes
-    pub const SIZE: usize = 208;
+    /// 布局版本（布局变化时递增，旧账户通过 MigrateAccount 升级）
+    pub const VERSION: u8 = 5;
+    
+    /// 8 + 1 + 1 + 1 + 5 + 32 + 32 + 8*5 + 8 + 8 + 8*12 + 32 + 8*8 + 16 = 344 bytes
+    pub const SIZE: usize = 344;
+    
+    /// 状态位：冻结
+    pub const FLAG_FROZEN: u64 = 1 << 0;
+    
+    /// 状态位：亏损熔断（只允许平仓）
+    pub const FLAG_CLOSE_ONLY: u64 = 1 << 1;
     
     pub fn new(owner: Pubkey, usdc_vault: Pubkey, bump: u8, usdc_bump: u8) -> Self {
         let now = Clock::get()
@@ -141,27 +235,180 @@ impl UserVault {
             flags: 0,
             created_at: now,
             updated_at: now,
-            reserved: [0; 64],
+            loss_limit: 0,
+            loss_window_slots: 0,
+            loss_window_start_slot: 0,
+            loss_window_pnl: 0,
+            delegated_collateral: 0,
+            delegate_locked_collateral: 0,
+            reserve_collateral: 0,
+            total_notional: 0,
+            max_total_notional: 0,
+            delegate_epoch: 0,
+            delegate_serial: 0,
+            permit_nonce: 0,
+            rent_payer: owner,
+            withdrawal_requested: 0,
+            earmarked_collateral: 0,
+            withdrawal_timelock_threshold: 0,
+            withdrawal_timelock_slots: 0,
+            timelocked_collateral: 0,
+            pending_timelock_threshold: 0,
+            pending_timelock_slots: 0,
+            pending_timelock_effective_slot: 0,
+            reserved: [0; 16],
         }
     }
     
     /// 检查 vault 是否冻结
     pub fn is_frozen(&self) -> bool {
-        self.flags & 1 != 0
+        self.flags & Self::FLAG_FROZEN != 0
     }
     
     /// 冻结 vault
     pub fn freeze(&mut self) {
-        self.flags |= 1;
+        self.flags |= Self::FLAG_FROZEN;
         self.update_timestamp();
     }
     
     /// 解冻 vault
     pub fn unfreeze(&mut self) {
-        self.flags &= !1;
+        self.flags &= !Self::FLAG_FROZEN;
+        self.update_timestamp();
+    }
+    
+    /// 检查 vault 是否处于亏损熔断（只允许平仓）状态
+    pub fn is_close_only(&self) -> bool {
+        self.flags & Self::FLAG_CLOSE_ONLY != 0
+    }
+    
+    /// 检查是否可以增加指定的总名义敞口
+    pub fn can_use_notional(&self, additional_notional: u64) -> bool {
+        self.max_total_notional == 0
+            || self.total_notional.saturating_add(additional_notional) <= self.max_total_notional
+    }
+    
+    /// 按 notional_delta 调整总名义敞口
+    ///
+    /// 释放时饱和到 0（升级前已存在的 owner 仓位没有被计入）
+    pub fn apply_notional_delta(&mut self, notional_delta: i64) -> Result<(), ProgramError> {
+        if notional_delta < 0 {
+            self.total_notional = self.total_notional.saturating_sub(notional_delta.unsigned_abs());
+        } else {
+            self.total_notional = self.total_notional
+                .checked_add(notional_delta as u64)
+                .ok_or(VaultError::ArithmeticOverflow)?;
+        }
+        Ok(())
+    }
+    
+    /// 恢复交易：清除熔断标记并重置亏损窗口
+    pub fn resume_trading(&mut self, current_slot: u64) {
+        self.flags &= !Self::FLAG_CLOSE_ONLY;
+        self.loss_window_start_slot = current_slot;
+        self.loss_window_pnl = 0;
         self.update_timestamp();
     }
     
+    /// 记录 delegate 产生的 PnL，超过亏损上限时进入熔断状态
+    ///
+    /// 返回本次是否触发熔断
+    pub fn record_delegate_pnl(&mut self, pnl_delta: i64, current_slot: u64) -> bool {
+        if self.loss_limit == 0 {
+            return false;
+        }
+        
+        // 窗口过期则重新计数
+        if current_slot >= self.loss_window_start_slot.saturating_add(self.loss_window_slots) {
+            self.loss_window_start_slot = current_slot;
+            self.loss_window_pnl = 0;
+        }
+        
+        self.loss_window_pnl = self.loss_window_pnl.saturating_add(pnl_delta);
+        
+        let triggered = self.loss_window_pnl < 0
+            && self.loss_window_pnl.unsigned_abs() > self.loss_limit
+            && !self.is_close_only();
+        
+        if triggered {
+            self.flags |= Self::FLAG_CLOSE_ONLY;
+        }
+        
+        triggered
+    }
+    
+    /// owner 自己锁定的保证金（包括引入 delegate 分配额度之前锁定的部分）
+    pub fn owner_locked_collateral(&self) -> u64 {
+        self.locked_collateral.saturating_sub(self.delegate_locked_collateral)
+    }
+    
+    /// 将 PnL 计入 free_collateral，亏损超过 free_collateral 的部分不再扣除
+    ///
+    /// 返回未能吸收的亏损金额
+    pub fn absorb_pnl(&mut self, pnl_delta: i64) -> Result<u64, ProgramError> {
+        if pnl_delta >= 0 {
+            self.free_collateral = self.free_collateral
+                .checked_add(pnl_delta as u64)
+                .ok_or(VaultError::ArithmeticOverflow)?;
+            return Ok(0);
+        }
+        
+        let loss = pnl_delta.unsigned_abs();
+        let covered = loss.min(self.free_collateral);
+        self.free_collateral -= covered;
+        Ok(loss - covered)
+    }
+    
+    /// 分配下一个 DelegateAccount 序号
+    pub fn next_delegate_serial(&mut self) -> Result<u64, ProgramError> {
+        self.delegate_serial = self.delegate_serial
+            .checked_add(1)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        Ok(self.delegate_serial)
+    }
+    
+    /// 从 free_collateral 中为提款请求预留资金，返回本次预留金额
+    pub fn earmark_free_collateral(&mut self) -> Result<u64, ProgramError> {
+        let amount = self.withdrawal_requested.min(self.free_collateral);
+        self.free_collateral -= amount;
+        self.withdrawal_requested -= amount;
+        self.earmarked_collateral = self.earmarked_collateral
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        Ok(amount)
+    }
+    
+    /// 提款金额是否超过阈值，需要走时间锁
+    pub fn requires_withdrawal_timelock(&self, amount: u64) -> bool {
+        self.withdrawal_timelock_threshold > 0 && amount > self.withdrawal_timelock_threshold
+    }
+    
+    /// 新的时间锁设置是否比当前设置宽松（关闭、提高阈值或缩短延迟）
+    pub fn weakens_withdrawal_timelock(&self, threshold: u64, delay_slots: u64) -> bool {
+        self.withdrawal_timelock_threshold > 0
+            && (threshold == 0
+                || threshold > self.withdrawal_timelock_threshold
+                || delay_slots < self.withdrawal_timelock_slots)
+    }
+    
+    /// 排队的时间锁设置到期后生效
+    pub fn apply_pending_withdrawal_timelock(&mut self, current_slot: u64) {
+        if self.pending_timelock_effective_slot != 0
+            && current_slot >= self.pending_timelock_effective_slot
+        {
+            self.withdrawal_timelock_threshold = self.pending_timelock_threshold;
+            self.withdrawal_timelock_slots = self.pending_timelock_slots;
+            self.clear_pending_withdrawal_timelock();
+        }
+    }
+    
+    /// 清除排队的时间锁设置
+    pub fn clear_pending_withdrawal_timelock(&mut self) {
+        self.pending_timelock_threshold = 0;
+        self.pending_timelock_slots = 0;
+        self.pending_timelock_effective_slot = 0;
+    }
+    
     /// 更新时间戳
     pub fn update_timestamp(&mut self) {
         self.updated_at = Clock::get()
@@ -175,6 +422,9 @@ pub const PERM_TRADE: u64 = 1 << 0;          // 允许交易（开平仓）
 pub const PERM_WITHDRAW: u64 = 1 << 1;       // 允许提现
 pub const PERM_CLOSE_ONLY: u64 = 1 << 2;     // 只允许平仓（减仓）
 pub const PERM_VIEW_ONLY: u64 = 1 << 3;      // 只读权限（未来扩展）
+pub const PERM_SUBDELEGATE: u64 = 1 << 4;    // 允许创建子 delegate（会话密钥）
+pub const PERM_DEPOSIT: u64 = 1 << 5;        // 允许通过 SPL 授权从 owner 钱包存款
+pub const PERM_TRANSFER: u64 = 1 << 6;       // 允许在 vault 之间划转
 
 /// API Key 授权记录（每个 vault × delegate 一条记录）
 /// PDA Seeds: [b"delegate", owner_wallet, delegate_pubkey]
@@ -204,8 +454,11 @@ pub struct DelegateAccount {
     /// 是否激活
     pub is_active: bool,
     
+    /// 是否暂停（owner 临时停用，保留全部配置）
+    pub is_paused: bool,
+    
     /// 预留字段（对齐）
-    pub reserved_align2: [u8; 7],
+    pub reserved_align2: [u8; 6],
     
     /// 权限位掩码（使用上述 PERM_* 常量）
     pub permissions: u64,
@@ -219,7 +472,7 @@ pub struct DelegateAccount {
     /// 过期时间（slot number）
     pub expiry_slot: u64,
     
-    /// 防重放计数器（每次操作递增）
+    /// 防重放计数器（链下签名授权的 nonce 必须严格大于该值，使用后更新）
     pub nonce: u64,
     
     /// 创建时间戳（秒）
@@ -228,16 +481,84 @@ pub struct DelegateAccount {
     /// 更新时间戳（秒）
     pub updated_at: i64,
     
+    /// 最大杠杆倍数（notional / margin，0 表示不限制）
+    pub max_leverage: u64,
+    
+    /// 分配给该 delegate 的可用保证金（e6格式）
+    pub allocated_collateral: u64,
+    
+    /// 该 delegate 从分配额度中锁定的保证金（e6格式）
+    pub allocated_locked: u64,
+    
+    /// 创建/更新时 vault 的 delegate 代数
+    pub delegate_epoch: u64,
+    
+    /// 标签（UTF-8，末尾 0 填充），如 "grid bot EU"
+    pub label: [u8; DELEGATE_LABEL_LEN],
+    
+    /// 策略标识（由 owner 自定义）
+    pub strategy_id: u64,
+    
+    /// 父 DelegateAccount PDA（Pubkey::default() 表示顶级 delegate）
+    pub parent: Pubkey,
+    
+    /// 作为父 delegate：子 delegate 代数（撤销时递增，旧代数的子 delegate 全部失效）
+    pub subdelegate_epoch: u64,
+    
+    /// 作为子 delegate：创建时父 delegate 的 subdelegate_epoch
+    pub parent_subdelegate_epoch: u64,
+    
+    /// 生效时间（slot number，0 表示立即生效）
+    pub not_before_slot: u64,
+    
+    /// 过期时间（Unix 时间戳，秒，0 表示仅使用 expiry_slot）
+    pub expiry_unix_ts: i64,
+    
+    /// 心跳间隔（秒，0 表示不要求心跳）
+    pub heartbeat_interval_secs: u64,
+    
+    /// 最近一次心跳时间戳（秒）
+    pub last_heartbeat_at: i64,
+    
+    /// 允许调用 LockMargin/UnlockMarginAndUpdatePnl 的业务程序（全部为默认值表示不限制）
+    pub allowed_programs: [Pubkey; MAX_ALLOWED_PROGRAMS],
+    
+    /// 允许开仓的市场位图（第 i 位对应 market_id = i，0 表示不限制）
+    pub allowed_markets: u128,
+    
+    /// 创建时分配的 vault 内序号（UserVault.delegate_serial，同一 key 重新创建后不同）
+    pub serial: u64,
+    
+    /// 支付该账户租金的账户（关闭时租金退回）
+    pub rent_payer: Pubkey,
+    
+    /// 每个窗口内可代 owner 存款的上限（e6格式，0 表示禁止）
+    pub deposit_limit: u64,
+    
+    /// 存款窗口长度（slots）
+    pub deposit_window_slots: u64,
+    
+    /// 当前存款窗口起始 slot
+    pub deposit_window_start_slot: u64,
+    
+    /// 当前窗口内已存款金额（e6格式）
+    pub deposit_window_used: u64,
+    
+    /// 提款或划转金额超过该值时需要 owner 同时签名（e6格式，0 表示不要求）
+    pub cosign_threshold: u64,
+    
     /// 预留扩展字段
-    pub reserved: [u8; 64],
+    pub reserved: [u8; 8],
 }
 
 impl DelegateAccount {
     pub const DISCRIMINATOR: u64 = 0x44454c45_47415445;
-    pub const VERSION: u8 = 1;
     
-    /// 8 + 1 + 1 + 6 + 32*3 + 1 + 7 + 8*5 + 8 + 8 + 64 = 237 bytes
-    pub const SIZE: usize = 237;
+    /// 布局版本（布局变化时递增，旧账户通过 MigrateAccount 升级）
+    pub const VERSION: u8 = 10;
+    
+    /// 8 + 1 + 1 + 6 + 32*3 + 1 + 1 + 6 + 8*5 + 8 + 8 + 8*4 + 32 + 8 + 32 + 8*6 + 32*4 + 16 + 8 + 32 + 8*5 + 8 = 560 bytes
+    pub const SIZE: usize = 560;
     
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -247,6 +568,9 @@ impl DelegateAccount {
         permissions: u64,
         max_notional: u64,
         expiry_slot: u64,
+        max_leverage: u64,
+        delegate_epoch: u64,
+        label: [u8; DELEGATE_LABEL_LEN],
         bump: u8,
     ) -> Self {
         let now = Clock::get()
@@ -262,7 +586,8 @@ impl DelegateAccount {
             vault,
             delegate,
             is_active: true,
-            reserved_align2: [0; 7],
+            is_paused: false,
+            reserved_align2: [0; 6],
             permissions,
             max_notional,
             used_notional: 0,
@@ -270,7 +595,29 @@ impl DelegateAccount {
             nonce: 0,
             created_at: now,
             updated_at: now,
-            reserved: [0; 64],
+            max_leverage,
+            allocated_collateral: 0,
+            allocated_locked: 0,
+            delegate_epoch,
+            label,
+            strategy_id: 0,
+            parent: Pubkey::default(),
+            subdelegate_epoch: 0,
+            parent_subdelegate_epoch: 0,
+            not_before_slot: 0,
+            expiry_unix_ts: 0,
+            heartbeat_interval_secs: 0,
+            last_heartbeat_at: now,
+            allowed_programs: [Pubkey::default(); MAX_ALLOWED_PROGRAMS],
+            allowed_markets: 0,
+            serial: 0,
+            rent_payer: owner,
+            deposit_limit: 0,
+            deposit_window_slots: 0,
+            deposit_window_start_slot: 0,
+            deposit_window_used: 0,
+            cosign_threshold: 0,
+            reserved: [0; 8],
         }
     }
     
@@ -279,9 +626,66 @@ impl DelegateAccount {
         self.permissions & permission != 0
     }
     
-    /// 检查是否在有效期内
-    pub fn is_valid(&self, current_slot: u64) -> bool {
-        self.is_active && current_slot <= self.expiry_slot
+    /// 检查是否在有效期内（已生效、未暂停，且未被 RevokeAllDelegates 作废）
+    ///
+    /// 同时设置了 expiry_slot 和 expiry_unix_ts 时，两者都必须未过期
+    pub fn is_valid(&self, current_slot: u64, current_ts: i64, vault_epoch: u64) -> bool {
+        self.is_active
+            && !self.is_paused
+            && self.delegate_epoch == vault_epoch
+            && current_slot >= self.not_before_slot
+            && current_slot <= self.expiry_slot
+            && (self.expiry_unix_ts == 0 || current_ts <= self.expiry_unix_ts)
+    }
+    
+    /// 检查心跳是否按时（未设置心跳间隔时始终为 true）
+    ///
+    /// 心跳超时的 delegate 不能开新仓，但仍可平仓
+    pub fn is_alive(&self, current_ts: i64) -> bool {
+        self.heartbeat_interval_secs == 0
+            || current_ts.saturating_sub(self.last_heartbeat_at)
+                <= self.heartbeat_interval_secs as i64
+    }
+    
+    /// 是否设置了调用方程序白名单
+    pub fn has_program_allowlist(&self) -> bool {
+        self.allowed_programs.iter().any(|program| *program != Pubkey::default())
+    }
+    
+    /// 检查调用方程序是否在白名单内（未设置白名单时始终为 true）
+    pub fn is_program_allowed(&self, program: &Pubkey) -> bool {
+        !self.has_program_allowlist() || self.allowed_programs.contains(program)
+    }
+    
+    /// 检查市场是否在白名单内（未设置白名单时始终为 true）
+    pub fn is_market_allowed(&self, market_id: u16) -> bool {
+        self.allowed_markets == 0
+            || (market_id < MAX_MARKET_ID && self.allowed_markets & (1u128 << market_id) != 0)
+    }
+    
+    /// 提款金额是否需要 owner 同时签名
+    pub fn requires_owner_cosign(&self, amount: u64) -> bool {
+        self.cosign_threshold > 0 && amount > self.cosign_threshold
+    }
+    
+    /// 记录一笔代 owner 存款，超过窗口上限时返回错误
+    pub fn record_deposit(&mut self, amount: u64, current_slot: u64) -> Result<(), ProgramError> {
+        // 窗口过期则重新计数
+        if current_slot >= self.deposit_window_start_slot.saturating_add(self.deposit_window_slots) {
+            self.deposit_window_start_slot = current_slot;
+            self.deposit_window_used = 0;
+        }
+        
+        let used = self
+            .deposit_window_used
+            .checked_add(amount)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        if used > self.deposit_limit {
+            return Err(VaultError::DepositLimitExceeded.into());
+        }
+        
+        self.deposit_window_used = used;
+        Ok(())
     }
     
     /// 检查是否可以使用指定的名义敞口
@@ -289,6 +693,68 @@ impl DelegateAccount {
         self.used_notional.saturating_add(additional_notional) <= self.max_notional
     }
     
+    /// 检查杠杆是否在允许范围内
+    pub fn within_leverage(&self, margin: u64, notional: u64) -> bool {
+        within_leverage(margin, notional, self.max_leverage)
+    }
+    
+    /// 是否为子 delegate
+    pub fn is_subdelegate(&self) -> bool {
+        self.parent != Pubkey::default()
+    }
+    
+    /// 从分配额度中锁定保证金
+    pub fn lock_allocation(&mut self, margin: u64) -> Result<(), ProgramError> {
+        if self.allocated_collateral < margin {
+            return Err(VaultError::InsufficientAllocation.into());
+        }
+        self.allocated_collateral -= margin;
+        self.allocated_locked = self.allocated_locked
+            .checked_add(margin)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        Ok(())
+    }
+    
+    /// 解锁保证金并将 PnL 计入分配额度
+    ///
+    /// 亏损最多扣到分配额度为 0，返回超出分配额度、未能吸收的亏损金额
+    pub fn unlock_allocation(&mut self, margin: u64, pnl_delta: i64) -> Result<u64, ProgramError> {
+        self.allocated_locked = self.allocated_locked
+            .checked_sub(margin)
+            .ok_or(VaultError::InsufficientLockedMargin)?;
+        let allocated = self.allocated_collateral
+            .checked_add(margin)
+            .ok_or(VaultError::ArithmeticOverflow)?;
+        
+        if pnl_delta >= 0 {
+            self.allocated_collateral = allocated
+                .checked_add(pnl_delta as u64)
+                .ok_or(VaultError::ArithmeticOverflow)?;
+            return Ok(0);
+        }
+        
+        let loss = pnl_delta.unsigned_abs();
+        let covered = loss.min(allocated);
+        self.allocated_collateral = allocated - covered;
+        Ok(loss - covered)
+    }
+    
+    /// 按 notional_delta 调整 used_notional
+    pub fn apply_notional_delta(&mut self, notional_delta: i64) -> Result<(), ProgramError> {
+        self.used_notional = if notional_delta < 0 {
+            self.used_notional
+                .checked_sub(notional_delta.unsigned_abs())
+                .ok_or(VaultError::ArithmeticUnderflow)?
+        } else {
+            // 释放敞口时 notional_delta 应该是负数
+            // 如果是正数，可能是错误，但为了兼容性也支持
+            self.used_notional
+                .checked_add(notional_delta as u64)
+                .ok_or(VaultError::ArithmeticOverflow)?
+        };
+        Ok(())
+    }
+    
     /// 更新时间戳
     pub fn update_timestamp(&mut self) {
         self.updated_at = Clock::get()
@@ -297,3 +763,876 @@ impl DelegateAccount {
     }
 }
 
+/// 提款请求（free_collateral 不足时排队，解锁后预留资金）
+/// PDA Seeds: [b"withdrawal", owner_wallet]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct WithdrawalRequest {
+    /// 账户类型标识符 "WDREQ" = 0x57445245_51000000
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 6],
+    
+    /// Vault 所有者
+    pub owner: Pubkey,
+    
+    /// 对应的 UserVault PDA
+    pub vault: Pubkey,
+    
+    /// 请求提款总额（e6格式）
+    pub amount: u64,
+    
+    /// 已支付金额（e6格式）
+    pub paid: u64,
+    
+    /// 创建时间戳（秒）
+    pub created_at: i64,
+    
+    /// 预留扩展字段
+    pub reserved: [u8; 32],
+}
+
+impl WithdrawalRequest {
+    pub const DISCRIMINATOR: u64 = 0x57445245_51000000;
+    pub const VERSION: u8 = 1;
+    
+    /// 8 + 1 + 1 + 6 + 32 + 32 + 8 + 8 + 8 + 32 = 136 bytes
+    pub const SIZE: usize = 136;
+    
+    pub fn new(owner: Pubkey, vault: Pubkey, amount: u64, bump: u8) -> Self {
+        let now = Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+        
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved_align: [0; 6],
+            owner,
+            vault,
+            amount,
+            paid: 0,
+            created_at: now,
+            reserved: [0; 32],
+        }
+    }
+    
+    /// 剩余未支付金额
+    pub fn remaining(&self) -> u64 {
+        self.amount.saturating_sub(self.paid)
+    }
+}
+
+/// 时间锁中的大额提款
+/// PDA Seeds: [b"pending-withdrawal", owner_wallet]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct PendingWithdrawal {
+    /// 账户类型标识符 "PNDWD" = 0x504E4457_44000000
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 6],
+    
+    /// Vault 所有者
+    pub owner: Pubkey,
+    
+    /// 对应的 UserVault PDA
+    pub vault: Pubkey,
+    
+    /// 发起人（owner 或 delegate），支付租金，关闭时租金退回
+    pub initiator: Pubkey,
+    
+    /// 提款金额（e6格式）
+    pub amount: u64,
+    
+    /// 可执行的最早 slot
+    pub unlock_slot: u64,
+    
+    /// 创建时间戳（秒）
+    pub created_at: i64,
+    
+    /// 预留扩展字段
+    pub reserved: [u8; 32],
+}
+
+impl PendingWithdrawal {
+    pub const DISCRIMINATOR: u64 = 0x504E4457_44000000;
+    pub const VERSION: u8 = 1;
+    
+    /// 8 + 1 + 1 + 6 + 32*3 + 8 + 8 + 8 + 32 = 168 bytes
+    pub const SIZE: usize = 168;
+    
+    pub fn new(
+        owner: Pubkey,
+        vault: Pubkey,
+        initiator: Pubkey,
+        amount: u64,
+        unlock_slot: u64,
+        bump: u8,
+    ) -> Self {
+        let now = Clock::get()
+            .map(|clock| clock.unix_timestamp)
+            .unwrap_or(0);
+        
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved_align: [0; 6],
+            owner,
+            vault,
+            initiator,
+            amount,
+            unlock_slot,
+            created_at: now,
+            reserved: [0; 32],
+        }
+    }
+    
+    /// 时间锁是否已到期
+    pub fn is_unlocked(&self, current_slot: u64) -> bool {
+        current_slot >= self.unlock_slot
+    }
+}
+
+/// Delegate 标签长度（UTF-8，末尾以 0 填充）
+pub const DELEGATE_LABEL_LEN: usize = 32;
+
+/// 每个 delegate 最多允许的调用方程序数量
+pub const MAX_ALLOWED_PROGRAMS: usize = 4;
+
+/// 市场白名单位图可表示的市场数量（market_id 取值 0..128）
+pub const MAX_MARKET_ID: u16 = 128;
+
+/// Registry 中的 delegate 状态
+pub const REGISTRY_STATUS_REVOKED: u8 = 0;
+pub const REGISTRY_STATUS_ACTIVE: u8 = 1;
+pub const REGISTRY_STATUS_PAUSED: u8 = 2;
+
+/// Delegate 链下签名授权：锁定保证金
+pub const INTENT_ACTION_LOCK_MARGIN: u8 = 1;
+
+/// Delegate 链下签名授权：提款
+pub const INTENT_ACTION_WITHDRAW: u8 = 2;
+
+/// Delegate 链下签名授权消息
+///
+/// borsh 序列化后由 delegate 私钥进行 ed25519 签名，relayer 在同一交易中
+/// 先提交 Ed25519 程序验签指令，再提交 *WithIntent 指令
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct DelegateIntent {
+    /// 本程序 ID（防止跨程序重放）
+    pub program_id: Pubkey,
+    
+    /// UserVault PDA
+    pub vault: Pubkey,
+    
+    /// Delegate 公钥（签名者）
+    pub delegate: Pubkey,
+    
+    /// DelegateAccount.delegate_epoch（RevokeAllDelegates 后旧授权失效）
+    pub delegate_epoch: u64,
+    
+    /// DelegateAccount.serial（账户关闭后重新创建时旧授权失效）
+    pub delegate_serial: u64,
+    
+    /// 操作类型（INTENT_ACTION_*）
+    pub action: u8,
+    
+    /// 金额（锁定的保证金或提款金额，e6格式）
+    pub amount: u64,
+    
+    /// 名义敞口（仅 LockMargin，e6格式）
+    pub notional: u64,
+    
+    /// 市场编号（仅 LockMargin）
+    pub market_id: u16,
+    
+    /// 防重放计数器，必须大于 DelegateAccount.nonce
+    pub nonce: u64,
+    
+    /// 授权过期时间（Unix 时间戳，秒）
+    pub expiry_unix_ts: i64,
+}
+
+/// Owner 链下签名许可消息
+///
+/// borsh 序列化后由 owner 私钥进行 ed25519 签名，授权 relayer 代为执行一条
+/// UpsertDelegate 或 RevokeDelegate 指令
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct OwnerPermit {
+    /// 本程序 ID（防止跨程序重放）
+    pub program_id: Pubkey,
+    
+    /// UserVault PDA
+    pub vault: Pubkey,
+    
+    /// 必须等于 UserVault.permit_nonce
+    pub nonce: u64,
+    
+    /// 许可截止时间（Unix 时间戳，秒）
+    pub deadline: i64,
+    
+    /// 被授权的指令数据（borsh 编码的 VaultInstruction）
+    pub instruction_data: Vec<u8>,
+}
+
+/// Registry 中的单条 delegate 记录
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct DelegateRegistryEntry {
+    /// delegate 公钥（API Key）
+    pub delegate: Pubkey,
+    
+    /// 标签（UTF-8）
+    pub label: [u8; DELEGATE_LABEL_LEN],
+    
+    /// 状态（REGISTRY_STATUS_*）
+    pub status: u8,
+    
+    /// 过期时间（slot number）
+    pub expiry_slot: u64,
+    
+    /// 父 delegate 公钥（直接授权的 delegate 为默认值）
+    pub parent: Pubkey,
+    
+    /// 过期时间（Unix 时间戳，秒，0 表示仅使用 expiry_slot）
+    pub expiry_unix_ts: i64,
+}
+
+impl DelegateRegistryEntry {
+    /// 32 + 32 + 1 + 8 + 32 + 8 = 113 bytes
+    pub const SIZE: usize = 113;
+    
+    pub fn new(
+        delegate: Pubkey,
+        label: [u8; DELEGATE_LABEL_LEN],
+        expiry_slot: u64,
+        expiry_unix_ts: i64,
+        parent: Pubkey,
+    ) -> Self {
+        Self {
+            delegate,
+            label,
+            status: REGISTRY_STATUS_ACTIVE,
+            expiry_slot,
+            parent,
+            expiry_unix_ts,
+        }
+    }
+    
+    /// 检查记录是否已过期（slot 或时间戳任一到期）
+    pub fn is_expired(&self, current_slot: u64, current_ts: i64) -> bool {
+        current_slot > self.expiry_slot
+            || (self.expiry_unix_ts != 0 && current_ts > self.expiry_unix_ts)
+    }
+}
+
+/// Delegate 注册表（每个 vault 一个，随 delegate 增加而 realloc）
+/// PDA Seeds: [b"registry", owner_wallet]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct DelegateRegistry {
+    /// 账户类型标识符 "DLGREG" = 0x444c4752_45470000
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved_align: [u8; 6],
+    
+    /// Vault 所有者
+    pub owner: Pubkey,
+    
+    /// 对应的 UserVault PDA
+    pub vault: Pubkey,
+    
+    /// delegate 记录列表
+    pub entries: Vec<DelegateRegistryEntry>,
+}
+
+impl DelegateRegistry {
+    pub const DISCRIMINATOR: u64 = 0x444c4752_45470000;
+    pub const VERSION: u8 = 1;
+    
+    /// 8 + 1 + 1 + 6 + 32 + 32 + 4 = 84 bytes（不含 entries）
+    pub const HEADER_SIZE: usize = 84;
+    
+    pub fn new(owner: Pubkey, vault: Pubkey, bump: u8) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved_align: [0; 6],
+            owner,
+            vault,
+            entries: Vec::new(),
+        }
+    }
+    
+    /// 存放 entry_count 条记录所需的账户大小
+    pub fn space(entry_count: usize) -> usize {
+        Self::HEADER_SIZE + entry_count * DelegateRegistryEntry::SIZE
+    }
+    
+    /// 查找 delegate 记录
+    pub fn find(&self, delegate: &Pubkey) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.delegate == *delegate)
+    }
+    
+    /// 检查记录是否计入数量上限：未撤销、未过期，子 delegate 的父 delegate 也需如此
+    pub fn is_live(&self, index: usize, current_slot: u64, current_ts: i64) -> bool {
+        let entry = &self.entries[index];
+        if entry.status == REGISTRY_STATUS_REVOKED || entry.is_expired(current_slot, current_ts) {
+            return false;
+        }
+        
+        if entry.parent == Pubkey::default() {
+            return true;
+        }
+        
+        match self.find(&entry.parent) {
+            Some(parent_index) => {
+                let parent = &self.entries[parent_index];
+                parent.status != REGISTRY_STATUS_REVOKED
+                    && !parent.is_expired(current_slot, current_ts)
+            }
+            None => false,
+        }
+    }
+    
+    /// 检查 delegate 是否还有未撤销的子 delegate
+    pub fn has_subdelegates(&self, parent: &Pubkey) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.parent == *parent && entry.status != REGISTRY_STATUS_REVOKED)
+    }
+    
+    /// 将 parent 的全部子 delegate 标记为已撤销，返回标记的数量
+    pub fn revoke_subdelegates(&mut self, parent: &Pubkey) -> usize {
+        let mut count = 0;
+        for entry in self.entries.iter_mut() {
+            if entry.parent == *parent && entry.status != REGISTRY_STATUS_REVOKED {
+                entry.status = REGISTRY_STATUS_REVOKED;
+                count += 1;
+            }
+        }
+        count
+    }
+    
+    /// 有效 delegate 数量
+    pub fn active_count(&self, current_slot: u64, current_ts: i64) -> usize {
+        (0..self.entries.len())
+            .filter(|&index| self.is_live(index, current_slot, current_ts))
+            .count()
+    }
+    
+    /// 更新 delegate 状态，返回是否找到记录
+    pub fn set_status(&mut self, delegate: &Pubkey, status: u8) -> bool {
+        match self.find(delegate) {
+            Some(index) => {
+                self.entries[index].status = status;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// 检查 notional / margin 是否不超过 max_leverage（0 表示不限制）
+pub fn within_leverage(margin: u64, notional: u64, max_leverage: u64) -> bool {
+    if max_leverage == 0 {
+        return true;
+    }
+    (notional as u128) <= (margin as u128) * (max_leverage as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> UserVault {
+        UserVault::new(Pubkey::new_unique(), Pubkey::new_unique(), 255, 254)
+    }
+
+    fn delegate() -> DelegateAccount {
+        DelegateAccount::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            PERM_TRADE,
+            1_000_000_000,
+            1_000,
+            0,
+            0,
+            [0; DELEGATE_LABEL_LEN],
+            255,
+        )
+    }
+
+    #[test]
+    fn account_sizes_match_serialized_length() {
+        let config = GlobalConfig::new(Pubkey::new_unique(), Pubkey::new_unique(), 255);
+        assert_eq!(config.try_to_vec().unwrap().len(), GlobalConfig::SIZE);
+        assert_eq!(vault().try_to_vec().unwrap().len(), UserVault::SIZE);
+        assert_eq!(delegate().try_to_vec().unwrap().len(), DelegateAccount::SIZE);
+    }
+
+    #[test]
+    fn leverage_zero_means_unlimited() {
+        assert!(within_leverage(0, u64::MAX, 0));
+        assert!(within_leverage(1, u64::MAX, 0));
+    }
+
+    #[test]
+    fn leverage_boundary() {
+        assert!(within_leverage(10, 50, 5));
+        assert!(!within_leverage(10, 51, 5));
+        // 没有保证金时只允许 0 名义敞口
+        assert!(within_leverage(0, 0, 5));
+        assert!(!within_leverage(0, 1, 5));
+    }
+
+    #[test]
+    fn leverage_does_not_overflow() {
+        assert!(within_leverage(u64::MAX, u64::MAX, 1_000));
+        assert!(!within_leverage(u64::MAX / 2, u64::MAX, 1));
+    }
+
+    #[test]
+    fn delegate_leverage_uses_own_cap() {
+        let mut delegate = delegate();
+        assert!(delegate.within_leverage(1, 1_000_000));
+        delegate.max_leverage = 10;
+        assert!(delegate.within_leverage(100, 1_000));
+        assert!(!delegate.within_leverage(100, 1_001));
+    }
+
+    #[test]
+    fn lock_allocation_moves_margin_to_locked() {
+        let mut delegate = delegate();
+        delegate.allocated_collateral = 100;
+        assert!(delegate.lock_allocation(101).is_err());
+        delegate.lock_allocation(60).unwrap();
+        assert_eq!(delegate.allocated_collateral, 40);
+        assert_eq!(delegate.allocated_locked, 60);
+    }
+
+    #[test]
+    fn unlock_allocation_applies_pnl() {
+        let mut delegate = delegate();
+        delegate.allocated_collateral = 40;
+        delegate.allocated_locked = 60;
+
+        assert_eq!(delegate.unlock_allocation(30, 5).unwrap(), 0);
+        assert_eq!(delegate.allocated_collateral, 75);
+        assert_eq!(delegate.allocated_locked, 30);
+
+        assert_eq!(delegate.unlock_allocation(30, -25).unwrap(), 0);
+        assert_eq!(delegate.allocated_collateral, 80);
+        assert_eq!(delegate.allocated_locked, 0);
+    }
+
+    #[test]
+    fn unlock_allocation_returns_unabsorbed_loss() {
+        let mut delegate = delegate();
+        delegate.allocated_collateral = 10;
+        delegate.allocated_locked = 20;
+        assert_eq!(delegate.unlock_allocation(20, -50).unwrap(), 20);
+        assert_eq!(delegate.allocated_collateral, 0);
+        assert_eq!(delegate.allocated_locked, 0);
+    }
+
+    #[test]
+    fn unlock_allocation_rejects_more_than_locked() {
+        let mut delegate = delegate();
+        delegate.allocated_locked = 20;
+        assert_eq!(
+            delegate.unlock_allocation(21, 0),
+            Err(VaultError::InsufficientLockedMargin.into())
+        );
+    }
+
+    #[test]
+    fn owner_locked_excludes_delegate_locks() {
+        let mut vault = vault();
+        vault.locked_collateral = 100;
+        vault.delegate_locked_collateral = 30;
+        assert_eq!(vault.owner_locked_collateral(), 70);
+    }
+
+    #[test]
+    fn absorb_pnl_caps_loss_at_free_collateral() {
+        let mut vault = vault();
+        vault.free_collateral = 50;
+        assert_eq!(vault.absorb_pnl(10).unwrap(), 0);
+        assert_eq!(vault.free_collateral, 60);
+        assert_eq!(vault.absorb_pnl(-20).unwrap(), 0);
+        assert_eq!(vault.free_collateral, 40);
+        assert_eq!(vault.absorb_pnl(-55).unwrap(), 15);
+        assert_eq!(vault.free_collateral, 0);
+    }
+
+    #[test]
+    fn delegate_pnl_ignored_without_loss_limit() {
+        let mut vault = vault();
+        assert!(!vault.record_delegate_pnl(-1_000_000_000, 10));
+        assert!(!vault.is_close_only());
+        assert_eq!(vault.loss_window_pnl, 0);
+    }
+
+    #[test]
+    fn delegate_losses_trigger_close_only_once() {
+        let mut vault = vault();
+        vault.loss_limit = 100;
+        vault.loss_window_slots = 50;
+
+        assert!(!vault.record_delegate_pnl(-60, 10));
+        assert!(!vault.record_delegate_pnl(20, 20));
+        // 窗口内净亏损 -40 - 61 = -101，超过上限
+        assert!(vault.record_delegate_pnl(-61, 30));
+        assert!(vault.is_close_only());
+        // 已熔断时不再重复触发
+        assert!(!vault.record_delegate_pnl(-10, 40));
+        assert_eq!(vault.loss_window_pnl, -111);
+    }
+
+    #[test]
+    fn loss_exactly_at_limit_does_not_trigger() {
+        let mut vault = vault();
+        vault.loss_limit = 100;
+        vault.loss_window_slots = 50;
+        assert!(!vault.record_delegate_pnl(-100, 0));
+        assert!(!vault.is_close_only());
+    }
+
+    #[test]
+    fn loss_window_resets_after_expiry() {
+        let mut vault = vault();
+        vault.loss_limit = 100;
+        vault.loss_window_slots = 50;
+
+        assert!(!vault.record_delegate_pnl(-90, 0));
+        // slot 50 开始新的窗口，之前的亏损不再累计
+        assert!(!vault.record_delegate_pnl(-90, 50));
+        assert_eq!(vault.loss_window_start_slot, 50);
+        assert_eq!(vault.loss_window_pnl, -90);
+        assert!(vault.record_delegate_pnl(-11, 99));
+    }
+
+    #[test]
+    fn resume_trading_clears_close_only_and_window() {
+        let mut vault = vault();
+        vault.loss_limit = 100;
+        vault.loss_window_slots = 50;
+        vault.freeze();
+        assert!(vault.record_delegate_pnl(-200, 10));
+
+        vault.resume_trading(20);
+        assert!(!vault.is_close_only());
+        assert!(vault.is_frozen());
+        assert_eq!(vault.loss_window_start_slot, 20);
+        assert_eq!(vault.loss_window_pnl, 0);
+    }
+
+    #[test]
+    fn total_notional_cap() {
+        let mut vault = vault();
+        // 0 表示不限制
+        assert!(vault.can_use_notional(u64::MAX));
+
+        vault.max_total_notional = 100;
+        vault.total_notional = 60;
+        assert!(vault.can_use_notional(40));
+        assert!(!vault.can_use_notional(41));
+        assert!(!vault.can_use_notional(u64::MAX));
+    }
+
+    #[test]
+    fn apply_notional_delta_saturates_on_release() {
+        let mut vault = vault();
+        vault.apply_notional_delta(50).unwrap();
+        vault.apply_notional_delta(-20).unwrap();
+        assert_eq!(vault.total_notional, 30);
+
+        // 升级前开立的仓位未计入，释放时饱和到 0
+        vault.apply_notional_delta(-100).unwrap();
+        assert_eq!(vault.total_notional, 0);
+
+        vault.total_notional = u64::MAX;
+        assert!(vault.apply_notional_delta(1).is_err());
+    }
+
+    #[test]
+    fn earmark_free_collateral_up_to_request() {
+        let mut vault = vault();
+        vault.free_collateral = 30;
+        assert_eq!(vault.earmark_free_collateral().unwrap(), 0);
+
+        vault.withdrawal_requested = 50;
+        assert_eq!(vault.earmark_free_collateral().unwrap(), 30);
+        assert_eq!(vault.free_collateral, 0);
+        assert_eq!(vault.withdrawal_requested, 20);
+
+        vault.free_collateral = 100;
+        assert_eq!(vault.earmark_free_collateral().unwrap(), 20);
+        assert_eq!(vault.free_collateral, 80);
+        assert_eq!(vault.withdrawal_requested, 0);
+        assert_eq!(vault.earmarked_collateral, 50);
+    }
+
+    #[test]
+    fn withdrawal_timelock_weakening() {
+        let mut vault = vault();
+        // 未开启时任何设置都是收紧
+        assert!(!vault.weakens_withdrawal_timelock(0, 0));
+
+        vault.withdrawal_timelock_threshold = 100;
+        vault.withdrawal_timelock_slots = 50;
+        assert!(vault.weakens_withdrawal_timelock(0, 0));
+        assert!(vault.weakens_withdrawal_timelock(200, 50));
+        assert!(vault.weakens_withdrawal_timelock(100, 10));
+        assert!(!vault.weakens_withdrawal_timelock(100, 50));
+        assert!(!vault.weakens_withdrawal_timelock(50, 80));
+    }
+
+    #[test]
+    fn pending_withdrawal_timelock_applies_at_effective_slot() {
+        let mut vault = vault();
+        vault.withdrawal_timelock_threshold = 100;
+        vault.withdrawal_timelock_slots = 50;
+        vault.pending_timelock_threshold = 0;
+        vault.pending_timelock_slots = 0;
+        vault.pending_timelock_effective_slot = 150;
+
+        vault.apply_pending_withdrawal_timelock(149);
+        assert_eq!(vault.withdrawal_timelock_threshold, 100);
+
+        vault.apply_pending_withdrawal_timelock(150);
+        assert_eq!(vault.withdrawal_timelock_threshold, 0);
+        assert_eq!(vault.withdrawal_timelock_slots, 0);
+        assert_eq!(vault.pending_timelock_effective_slot, 0);
+    }
+
+    #[test]
+    fn delegate_serial_is_monotonic() {
+        let mut vault = vault();
+        assert_eq!(vault.next_delegate_serial().unwrap(), 1);
+        assert_eq!(vault.next_delegate_serial().unwrap(), 2);
+
+        vault.delegate_serial = u64::MAX;
+        assert!(vault.next_delegate_serial().is_err());
+    }
+
+    #[test]
+    fn delegate_validity_checks_expiry_and_epoch() {
+        let mut delegate = delegate();
+        assert!(delegate.is_valid(1_000, 0, 0));
+        assert!(!delegate.is_valid(1_001, 0, 0));
+
+        // RevokeAllDelegates 后旧代数的 delegate 失效
+        assert!(!delegate.is_valid(0, 0, 1));
+        delegate.delegate_epoch = 1;
+        assert!(delegate.is_valid(0, 0, 1));
+
+        delegate.is_active = false;
+        assert!(!delegate.is_valid(0, 0, 1));
+    }
+
+    #[test]
+    fn delegate_validity_checks_not_before_and_pause() {
+        let mut delegate = delegate();
+        delegate.not_before_slot = 500;
+        assert!(!delegate.is_valid(499, 0, 0));
+        assert!(delegate.is_valid(500, 0, 0));
+
+        // 暂停期间无效，恢复后立即可用
+        delegate.is_paused = true;
+        assert!(!delegate.is_valid(600, 0, 0));
+        delegate.is_paused = false;
+        assert!(delegate.is_valid(600, 0, 0));
+    }
+
+    #[test]
+    fn delegate_heartbeat_liveness() {
+        let mut delegate = delegate();
+        delegate.last_heartbeat_at = 1_000;
+        assert!(delegate.is_alive(i64::MAX));
+
+        delegate.heartbeat_interval_secs = 60;
+        assert!(delegate.is_alive(1_060));
+        assert!(!delegate.is_alive(1_061));
+
+        // 迁移前的账户 last_heartbeat_at 为 0，时钟回拨也不应溢出
+        delegate.last_heartbeat_at = 0;
+        assert!(delegate.is_alive(-1));
+        assert!(!delegate.is_alive(61));
+    }
+
+    #[test]
+    fn delegate_program_allowlist() {
+        let mut delegate = delegate();
+        let program = Pubkey::new_unique();
+        assert!(!delegate.has_program_allowlist());
+        assert!(delegate.is_program_allowed(&program));
+
+        delegate.allowed_programs[1] = program;
+        assert!(delegate.has_program_allowlist());
+        assert!(delegate.is_program_allowed(&program));
+        assert!(!delegate.is_program_allowed(&Pubkey::new_unique()));
+        // 白名单中的空位不放行其他调用方
+        assert!(!delegate.is_program_allowed(&crate::id()));
+    }
+
+    #[test]
+    fn delegate_market_allowlist() {
+        let mut delegate = delegate();
+        assert!(delegate.is_market_allowed(0));
+        assert!(delegate.is_market_allowed(u16::MAX));
+
+        delegate.allowed_markets = (1 << 3) | (1 << 127);
+        assert!(delegate.is_market_allowed(3));
+        assert!(delegate.is_market_allowed(127));
+        assert!(!delegate.is_market_allowed(0));
+        // 超出位图范围的市场不能通过白名单
+        assert!(!delegate.is_market_allowed(128));
+    }
+
+    #[test]
+    fn delegate_validity_checks_unix_timestamp_expiry() {
+        let mut delegate = delegate();
+        delegate.expiry_unix_ts = 1_700_000_000;
+        assert!(delegate.is_valid(0, 1_700_000_000, 0));
+        assert!(!delegate.is_valid(0, 1_700_000_001, 0));
+
+        // 两种过期方式同时生效
+        assert!(!delegate.is_valid(1_001, 1_600_000_000, 0));
+
+        // 0 表示不按时间戳过期
+        delegate.expiry_unix_ts = 0;
+        assert!(delegate.is_valid(0, i64::MAX, 0));
+    }
+
+    #[test]
+    fn delegate_deposit_window_limit() {
+        let mut delegate = delegate();
+        // 默认上限为 0，禁止代存款
+        assert!(delegate.record_deposit(1, 0).is_err());
+
+        delegate.deposit_limit = 100;
+        delegate.deposit_window_slots = 10;
+        delegate.record_deposit(60, 0).unwrap();
+        delegate.record_deposit(40, 9).unwrap();
+        assert!(delegate.record_deposit(1, 9).is_err());
+        assert_eq!(delegate.deposit_window_used, 100);
+
+        // 窗口过期后重新计数
+        delegate.record_deposit(100, 10).unwrap();
+        assert_eq!(delegate.deposit_window_start_slot, 10);
+        assert_eq!(delegate.deposit_window_used, 100);
+    }
+
+    #[test]
+    fn owner_cosign_above_threshold() {
+        let mut delegate = delegate();
+        // 阈值为 0 时不要求 co-sign
+        assert!(!delegate.requires_owner_cosign(u64::MAX));
+
+        delegate.cosign_threshold = 100;
+        assert!(!delegate.requires_owner_cosign(100));
+        assert!(delegate.requires_owner_cosign(101));
+    }
+
+    fn registry_entry(expiry_slot: u64) -> DelegateRegistryEntry {
+        DelegateRegistryEntry::new(
+            Pubkey::new_unique(),
+            [0; DELEGATE_LABEL_LEN],
+            expiry_slot,
+            0,
+            Pubkey::default(),
+        )
+    }
+
+    #[test]
+    fn registry_counts_only_live_entries() {
+        let mut registry = DelegateRegistry::new(Pubkey::new_unique(), Pubkey::new_unique(), 255);
+        registry.entries.push(registry_entry(100));
+        registry.entries.push(registry_entry(200));
+        registry.entries.push(registry_entry(300));
+        assert_eq!(registry.active_count(100, 0), 3);
+
+        // 过期与撤销的记录都不计入上限
+        assert_eq!(registry.active_count(150, 0), 2);
+        assert!(!registry.is_live(0, 150, 0));
+        let revoked = registry.entries[2].delegate;
+        assert!(registry.set_status(&revoked, REGISTRY_STATUS_REVOKED));
+        assert_eq!(registry.active_count(150, 0), 1);
+        assert!(!registry.set_status(&Pubkey::new_unique(), REGISTRY_STATUS_REVOKED));
+    }
+
+    #[test]
+    fn registry_entry_expires_by_unix_timestamp() {
+        let mut registry = DelegateRegistry::new(Pubkey::new_unique(), Pubkey::new_unique(), 255);
+        let mut entry = registry_entry(u64::MAX);
+        entry.expiry_unix_ts = 1_700_000_000;
+        registry.entries.push(entry);
+        assert!(registry.is_live(0, 100, 1_700_000_000));
+        assert!(!registry.is_live(0, 100, 1_700_000_001));
+        assert_eq!(registry.active_count(100, 1_700_000_001), 0);
+    }
+
+    #[test]
+    fn registry_space_matches_serialized_length() {
+        let mut registry = DelegateRegistry::new(Pubkey::new_unique(), Pubkey::new_unique(), 255);
+        assert_eq!(registry.try_to_vec().unwrap().len(), DelegateRegistry::space(0));
+        registry.entries.push(registry_entry(100));
+        registry.entries.push(registry_entry(100));
+        assert_eq!(registry.try_to_vec().unwrap().len(), DelegateRegistry::space(2));
+    }
+
+    #[test]
+    fn registry_children_follow_parent() {
+        let mut registry = DelegateRegistry::new(Pubkey::new_unique(), Pubkey::new_unique(), 255);
+        let parent = registry_entry(1_000);
+        let parent_key = parent.delegate;
+        let mut child = registry_entry(500);
+        child.parent = parent_key;
+        registry.entries.push(parent);
+        registry.entries.push(child);
+        assert!(registry.is_live(1, 0, 0));
+        assert!(registry.has_subdelegates(&parent_key));
+
+        // 父 delegate 撤销后子 delegate 不再计入上限
+        registry.set_status(&parent_key, REGISTRY_STATUS_REVOKED);
+        assert!(!registry.is_live(1, 0, 0));
+        assert_eq!(registry.active_count(0, 0), 0);
+
+        assert_eq!(registry.revoke_subdelegates(&parent_key), 1);
+        assert_eq!(registry.revoke_subdelegates(&parent_key), 0);
+        assert!(!registry.has_subdelegates(&parent_key));
+    }
+
+    #[test]
+    fn registry_child_of_unknown_parent_is_not_live() {
+        let mut registry = DelegateRegistry::new(Pubkey::new_unique(), Pubkey::new_unique(), 255);
+        let mut child = registry_entry(500);
+        child.parent = Pubkey::new_unique();
+        registry.entries.push(child);
+        assert!(!registry.is_live(0, 0, 0));
+    }
+}
@@ -9,6 +9,11 @@ use solana_program::{
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
+    ed25519_program,
+    sysvar::instructions::{
+        self as instructions_sysvar, get_instruction_relative, load_current_index_checked,
+        load_instruction_at_checked,
+    },
 };
 use spl_token::state::Account as TokenAccount;
 use crate::error::VaultError;
@@ -49,6 +54,46 @@ pub fn create_pda_account<'a>(
     Ok(())
 }
 
+/// 扩容 PDA 账户（补足租金后 realloc）
+pub fn realloc_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    pda: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    rent: &Rent,
+    new_space: usize,
+) -> ProgramResult {
+    let required_lamports = rent
+        .minimum_balance(new_space)
+        .saturating_sub(pda.lamports());
+    
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, pda.key, required_lamports),
+            &[payer.clone(), pda.clone(), system_program.clone()],
+        )?;
+    }
+    
+    pda.realloc(new_space, false)
+}
+
+/// 关闭程序账户，租金退回 destination
+pub fn close_pda_account<'a>(
+    account: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+) -> ProgramResult {
+    let lamports = account.lamports();
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(VaultError::ArithmeticOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+    
+    account.realloc(0, false)?;
+    account.assign(&solana_program::system_program::id());
+    
+    Ok(())
+}
+
 /// 验证 PDA
 pub fn verify_pda(
     pda: &Pubkey,
@@ -139,6 +184,23 @@ pub fn verify_token_account(
     Ok(token_account)
 }
 
+/// 解析可选的独立 payer 账户
+///
+/// 提供时必须签名且可写，否则由 default_payer 支付
+pub fn resolve_payer<'a, 'b>(
+    payer_info: Option<&'a AccountInfo<'b>>,
+    default_payer: &'a AccountInfo<'b>,
+) -> Result<&'a AccountInfo<'b>, ProgramError> {
+    match payer_info {
+        Some(payer_info) => {
+            require_signer(payer_info)?;
+            require_writable(payer_info)?;
+            Ok(payer_info)
+        }
+        None => Ok(default_payer),
+    }
+}
+
 /// 安全加法（检查溢出）
 pub fn safe_add(a: u64, b: u64) -> Result<u64, ProgramError> {
     a.checked_add(b)
@@ -162,6 +224,18 @@ pub fn safe_add_signed(a: u64, delta: i64) -> Result<u64, ProgramError> {
     }
 }
 
+/// 验证 delegate 标签：必须是合法 UTF-8 且不含控制字符（末尾 0 填充部分除外）
+pub fn validate_label(label: &[u8]) -> ProgramResult {
+    let len = label.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let text = std::str::from_utf8(&label[..len]).map_err(|_| VaultError::InvalidLabel)?;
+    
+    // 标签会出现在事件和链下界面中，不允许中间的 0 字节和控制字符
+    if text.chars().any(char::is_control) {
+        return Err(VaultError::InvalidLabel.into());
+    }
+    Ok(())
+}
+
 /// 检查账户是否为签名者
 pub fn require_signer(account: &AccountInfo) -> ProgramResult {
     if !account.is_signer {
@@ -186,9 +260,28 @@ pub fn require_owner(account: &AccountInfo, expected_owner: &Pubkey) -> ProgramR
     Ok(())
 }
 
+/// 读取并验证 GlobalConfig 账户
+pub fn load_global_config(
+    global_config_info: &AccountInfo,
+    program_id: &Pubkey,
+) -> Result<crate::state::GlobalConfig, ProgramError> {
+    use borsh::BorshDeserialize;
+    use crate::state::GlobalConfig;
+    
+    require_owner(global_config_info, program_id)?;
+    
+    let global_config = GlobalConfig::try_from_slice(&global_config_info.data.borrow())?;
+    if global_config.discriminator != GlobalConfig::DISCRIMINATOR {
+        return Err(VaultError::InvalidGlobalConfig.into());
+    }
+    
+    Ok(global_config)
+}
+
 /// 验证 Vault 余额一致性
 /// 
-/// 确保 Token Account 的实际余额 = free_collateral + locked_collateral
+/// 确保 Token Account 的实际余额 =
+/// free_collateral + locked_collateral + delegated_collateral + reserve_collateral
 /// 
 /// # 参数
 /// - `vault`: UserVault 账户
@@ -207,12 +300,18 @@ pub fn verify_vault_balance_integrity(
     
     let expected_balance = vault.free_collateral
         .checked_add(vault.locked_collateral)
+        .and_then(|sum| sum.checked_add(vault.delegated_collateral))
+        .and_then(|sum| sum.checked_add(vault.reserve_collateral))
+        .and_then(|sum| sum.checked_add(vault.earmarked_collateral))
+        .and_then(|sum| sum.checked_add(vault.timelocked_collateral))
         .ok_or(VaultError::ArithmeticOverflow)?;
     
     if token_account.amount != expected_balance {
         msg!("❌ Balance mismatch detected!");
-        msg!("Expected: {} (free: {} + locked: {})", 
-            expected_balance, vault.free_collateral, vault.locked_collateral);
+        msg!("Expected: {} (free: {} + locked: {} + delegated: {} + reserve: {} + earmarked: {} + timelocked: {})", 
+            expected_balance, vault.free_collateral, vault.locked_collateral,
+            vault.delegated_collateral, vault.reserve_collateral, vault.earmarked_collateral,
+            vault.timelocked_collateral);
         msg!("Actual token balance: {}", token_account.amount);
         return Err(VaultError::InvalidTokenAccount.into());
     }
@@ -220,3 +319,198 @@ pub fn verify_vault_balance_integrity(
     Ok(())
 }
 
+
+/// 通过 instructions sysvar 获取当前顶层指令的 program id
+///
+/// 业务程序 CPI 调用本程序时即为该业务程序；直接调用时为本程序
+pub fn get_invoking_program(instructions_sysvar_info: &AccountInfo) -> Result<Pubkey, ProgramError> {
+    if !instructions_sysvar::check_id(instructions_sysvar_info.key) {
+        return Err(ProgramError::UnsupportedSysvar);
+    }
+    
+    let current_instruction = get_instruction_relative(0, instructions_sysvar_info)?;
+    Ok(current_instruction.program_id)
+}
+
+/// 验证同一交易中此前的 Ed25519 程序指令包含 signer 对 message 的签名
+///
+/// Ed25519 预编译程序在交易执行前已完成验签，这里只需确认其验证的公钥和消息
+/// 与预期一致，且公钥和消息都位于该指令自身的数据中
+pub fn verify_ed25519_signature(
+    instructions_sysvar_info: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> ProgramResult {
+    if !instructions_sysvar::check_id(instructions_sysvar_info.key) {
+        return Err(ProgramError::UnsupportedSysvar);
+    }
+    
+    let current_index = load_current_index_checked(instructions_sysvar_info)?;
+    for index in 0..current_index {
+        let instruction = load_instruction_at_checked(index as usize, instructions_sysvar_info)?;
+        if instruction.program_id != ed25519_program::id() {
+            continue;
+        }
+        
+        if ed25519_instruction_contains(&instruction.data, signer, message) {
+            return Ok(());
+        }
+    }
+    
+    Err(VaultError::InvalidSignature.into())
+}
+
+/// 解析 Ed25519 程序指令数据，检查是否包含指定公钥和消息的签名
+///
+/// 数据格式：[签名数量 u8, padding u8, 每个签名 14 字节 offsets...]
+fn ed25519_instruction_contains(data: &[u8], signer: &Pubkey, message: &[u8]) -> bool {
+    const HEADER_LEN: usize = 2;
+    const OFFSETS_LEN: usize = 14;
+    const CURRENT_INSTRUCTION: u16 = u16::MAX;
+    
+    let Some(&count) = data.first() else {
+        return false;
+    };
+    
+    for i in 0..count as usize {
+        let start = HEADER_LEN + i * OFFSETS_LEN;
+        let Some(offsets) = data.get(start..start + OFFSETS_LEN) else {
+            return false;
+        };
+        let read_u16 = |at: usize| u16::from_le_bytes([offsets[at], offsets[at + 1]]);
+        
+        let signature_instruction_index = read_u16(2);
+        let public_key_offset = read_u16(4) as usize;
+        let public_key_instruction_index = read_u16(6);
+        let message_offset = read_u16(8) as usize;
+        let message_size = read_u16(10) as usize;
+        let message_instruction_index = read_u16(12);
+        
+        // 签名、公钥和消息必须都在该指令自身的数据中
+        if signature_instruction_index != CURRENT_INSTRUCTION
+            || public_key_instruction_index != CURRENT_INSTRUCTION
+            || message_instruction_index != CURRENT_INSTRUCTION
+        {
+            continue;
+        }
+        
+        let public_key = data.get(public_key_offset..public_key_offset + 32);
+        let signed_message = data.get(message_offset..message_offset + message_size);
+        
+        if public_key == Some(signer.as_ref()) && signed_message == Some(message) {
+            return true;
+        }
+    }
+    
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::DELEGATE_LABEL_LEN;
+    
+    const OFFSETS_START: usize = 2;
+    const PUBKEY_START: usize = 16;
+    const SIGNATURE_START: usize = PUBKEY_START + 32;
+    const MESSAGE_START: usize = SIGNATURE_START + 64;
+    
+    fn label(text: &[u8]) -> [u8; DELEGATE_LABEL_LEN] {
+        let mut label = [0u8; DELEGATE_LABEL_LEN];
+        label[..text.len()].copy_from_slice(text);
+        label
+    }
+    
+    #[test]
+    fn label_accepts_utf8_with_zero_padding() {
+        assert!(validate_label(&label(b"grid bot EU")).is_ok());
+        assert!(validate_label(&label("网格 策略".as_bytes())).is_ok());
+        assert!(validate_label(&[0u8; DELEGATE_LABEL_LEN]).is_ok());
+    }
+    
+    #[test]
+    fn label_rejects_invalid_utf8() {
+        assert!(validate_label(&label(&[0xff, 0xfe])).is_err());
+    }
+    
+    #[test]
+    fn label_rejects_interior_nul_and_control_characters() {
+        assert!(validate_label(&label(b"grid\0bot")).is_err());
+        assert!(validate_label(&label(b"grid\nbot")).is_err());
+        assert!(validate_label(&label(b"\x1b[31mred")).is_err());
+        assert!(validate_label(&label("bot\u{85}".as_bytes())).is_err());
+    }
+    
+    /// 构造只包含一个签名的 Ed25519 程序指令数据，三个 instruction_index 可分别指定
+    fn ed25519_data(signer: &Pubkey, message: &[u8], instruction_indexes: [u16; 3]) -> Vec<u8> {
+        let offsets = [
+            SIGNATURE_START as u16,
+            instruction_indexes[0],
+            PUBKEY_START as u16,
+            instruction_indexes[1],
+            MESSAGE_START as u16,
+            message.len() as u16,
+            instruction_indexes[2],
+        ];
+        
+        let mut data = vec![1u8, 0];
+        for value in offsets {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[0u8; 64]);
+        data.extend_from_slice(message);
+        data
+    }
+    
+    #[test]
+    fn ed25519_data_matches_signer_and_message() {
+        let signer = Pubkey::new_unique();
+        let data = ed25519_data(&signer, b"intent", [u16::MAX; 3]);
+        
+        assert!(ed25519_instruction_contains(&data, &signer, b"intent"));
+    }
+    
+    #[test]
+    fn ed25519_offsets_pointing_at_other_instruction_are_ignored() {
+        let signer = Pubkey::new_unique();
+        
+        for position in 0..3 {
+            let mut indexes = [u16::MAX; 3];
+            indexes[position] = 0;
+            let data = ed25519_data(&signer, b"intent", indexes);
+            
+            assert!(!ed25519_instruction_contains(&data, &signer, b"intent"));
+        }
+    }
+    
+    #[test]
+    fn ed25519_truncated_data_is_rejected() {
+        let signer = Pubkey::new_unique();
+        let data = ed25519_data(&signer, b"intent", [u16::MAX; 3]);
+        
+        assert!(!ed25519_instruction_contains(&[], &signer, b"intent"));
+        // offsets 不完整
+        assert!(!ed25519_instruction_contains(&data[..OFFSETS_START + 7], &signer, b"intent"));
+        // 公钥被截断
+        assert!(!ed25519_instruction_contains(&data[..PUBKEY_START + 16], &signer, b"intent"));
+        // 消息被截断
+        assert!(!ed25519_instruction_contains(&data[..data.len() - 1], &signer, b"intent"));
+        
+        // 签名数量大于实际 offsets 条目数
+        let mut overcounted = data.clone();
+        overcounted[0] = 2;
+        overcounted.truncate(PUBKEY_START);
+        assert!(!ed25519_instruction_contains(&overcounted, &signer, b"intent"));
+    }
+    
+    #[test]
+    fn ed25519_pubkey_or_message_mismatch_is_rejected() {
+        let signer = Pubkey::new_unique();
+        let data = ed25519_data(&signer, b"intent", [u16::MAX; 3]);
+        
+        assert!(!ed25519_instruction_contains(&data, &Pubkey::new_unique(), b"intent"));
+        assert!(!ed25519_instruction_contains(&data, &signer, b"intenT"));
+        assert!(!ed25519_instruction_contains(&data, &signer, b"inten"));
+    }
+}
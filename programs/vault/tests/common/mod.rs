@@ -0,0 +1,993 @@
+//! 集成测试公共工具
+//!
+//! - VaultTest: 启动 program-test，创建 USDC Mint 并初始化 GlobalConfig
+//! - VaultUser: 已创建 Vault 的测试用户
+//! - 指令构造函数与 PDA 派生
+
+#![allow(dead_code)]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    ed25519_program, system_instruction, system_program, sysvar,
+};
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    compute_budget::ComputeBudgetInstruction,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use vault_program::{
+    DelegateAccount, DelegateRegistry, OwnerPermit, UserVault, VaultError, VaultInstruction,
+    DELEGATE_LABEL_LEN,
+};
+
+/// 1 USDC (e6)
+pub const USDC: u64 = 1_000_000;
+
+/// 末尾 0 填充的 delegate 标签
+pub fn label(text: &str) -> [u8; DELEGATE_LABEL_LEN] {
+    let mut label = [0u8; DELEGATE_LABEL_LEN];
+    label[..text.len()].copy_from_slice(text.as_bytes());
+    label
+}
+
+pub fn global_config_pda() -> Pubkey {
+    Pubkey::find_program_address(&[b"global", &[1u8]], &vault_program::id()).0
+}
+
+pub fn vault_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", owner.as_ref()], &vault_program::id()).0
+}
+
+pub fn vault_usdc_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault-usdc", owner.as_ref()], &vault_program::id()).0
+}
+
+pub fn delegate_pda(owner: &Pubkey, delegate: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"delegate", owner.as_ref(), delegate.as_ref()],
+        &vault_program::id(),
+    )
+    .0
+}
+
+pub fn registry_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"registry", owner.as_ref()], &vault_program::id()).0
+}
+
+pub fn withdrawal_request_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"withdrawal", owner.as_ref()], &vault_program::id()).0
+}
+
+pub fn pending_withdrawal_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"pending-withdrawal", owner.as_ref()], &vault_program::id()).0
+}
+
+pub fn deposit_authority_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"deposit-authority", owner.as_ref()], &vault_program::id()).0
+}
+
+pub fn vault_ix(accounts: Vec<AccountMeta>, data: VaultInstruction) -> Instruction {
+    Instruction {
+        program_id: vault_program::id(),
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+pub fn migrate_account_ix(account: &Pubkey, payer: &Pubkey) -> Instruction {
+    vault_ix(
+        vec![
+            AccountMeta::new(*account, false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        VaultInstruction::MigrateAccount,
+    )
+}
+
+/// 仅需 [GlobalConfig, Admin] 两个账户的 admin 指令
+pub fn admin_ix(admin: &Pubkey, data: VaultInstruction) -> Instruction {
+    vault_ix(
+        vec![
+            AccountMeta::new(global_config_pda(), false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+        data,
+    )
+}
+
+/// 已创建 Vault 的测试用户
+/// Ed25519 预编译程序指令：验证 `signer` 对 `message` 的签名
+///
+/// 公钥、签名和消息都放在指令自身的数据中
+pub fn ed25519_ix(signer: &Keypair, message: &[u8]) -> Instruction {
+    const PUBKEY_START: u16 = 16;
+    const SIGNATURE_START: u16 = PUBKEY_START + 32;
+    const MESSAGE_START: u16 = SIGNATURE_START + 64;
+
+    let offsets = [
+        SIGNATURE_START,
+        u16::MAX,
+        PUBKEY_START,
+        u16::MAX,
+        MESSAGE_START,
+        message.len() as u16,
+        u16::MAX,
+    ];
+    let mut data = vec![1u8, 0];
+    for value in offsets {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signer.sign_message(message).as_ref());
+    data.extend_from_slice(message);
+
+    Instruction { program_id: ed25519_program::id(), accounts: vec![], data }
+}
+
+pub struct VaultUser {
+    pub owner: Keypair,
+    pub usdc: Pubkey,
+    pub vault: Pubkey,
+    pub vault_usdc: Pubkey,
+}
+
+impl VaultUser {
+    pub fn owner_key(&self) -> Pubkey {
+        self.owner.pubkey()
+    }
+
+    pub fn delegate_pda(&self, delegate: &Pubkey) -> Pubkey {
+        delegate_pda(&self.owner.pubkey(), delegate)
+    }
+
+    pub fn registry_pda(&self) -> Pubkey {
+        registry_pda(&self.owner.pubkey())
+    }
+
+    /// 仅需 [UserVault, Owner] 两个账户的 owner 指令
+    pub fn owner_ix(&self, data: VaultInstruction) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+            ],
+            data,
+        )
+    }
+
+    /// 账户为 [DelegateAccount, UserVault, Owner] 的 owner 指令
+    pub fn delegate_owner_ix(&self, delegate: &Pubkey, data: VaultInstruction) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+            ],
+            data,
+        )
+    }
+
+    /// 位于账户列表中间的可选 DelegateAccount：owner 签名时用 program id 占位
+    fn optional_delegate_meta(&self, delegate: Option<&Pubkey>) -> AccountMeta {
+        match delegate {
+            Some(delegate) => AccountMeta::new(self.delegate_pda(delegate), false),
+            None => AccountMeta::new_readonly(vault_program::id(), false),
+        }
+    }
+
+    pub fn deposit_ix(&self, amount: u64) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+                AccountMeta::new(self.usdc, false),
+                AccountMeta::new(self.vault_usdc, false),
+                AccountMeta::new_readonly(global_config_pda(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+            VaultInstruction::Deposit { amount },
+        )
+    }
+
+    /// `depositor` 从自己的 USDC 账户为本 vault 存款
+    pub fn deposit_for_ix(&self, depositor: &VaultUser, amount: u64) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(depositor.owner.pubkey(), true),
+                AccountMeta::new(depositor.usdc, false),
+                AccountMeta::new(self.vault_usdc, false),
+                AccountMeta::new_readonly(global_config_pda(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+            VaultInstruction::DepositFor { owner: self.owner.pubkey(), amount },
+        )
+    }
+
+    /// owner 对 deposit authority PDA 的 SPL 授权
+    pub fn approve_deposit_authority_ix(&self, amount: u64) -> Instruction {
+        spl_token::instruction::approve(
+            &spl_token::id(),
+            &self.usdc,
+            &deposit_authority_pda(&self.owner.pubkey()),
+            &self.owner.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap()
+    }
+
+    pub fn set_delegate_deposit_limit_ix(
+        &self,
+        delegate: &Pubkey,
+        deposit_limit: u64,
+        window_slots: u64,
+    ) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new_readonly(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+            ],
+            VaultInstruction::SetDelegateDepositLimit {
+                delegate_pubkey: *delegate,
+                deposit_limit,
+                window_slots,
+            },
+        )
+    }
+
+    pub fn delegate_deposit_ix(&self, delegate: &Pubkey, amount: u64) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(*delegate, true),
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new(self.usdc, false),
+                AccountMeta::new(self.vault_usdc, false),
+                AccountMeta::new_readonly(deposit_authority_pda(&self.owner.pubkey()), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+            VaultInstruction::DelegateDeposit { amount },
+        )
+    }
+
+    /// 从本 vault 划转到 `to`，signer 为 delegate 时附带对应的 DelegateAccount
+    pub fn transfer_to_vault_ix(
+        &self,
+        to: &VaultUser,
+        signer: &Pubkey,
+        delegate: Option<&Pubkey>,
+        amount: u64,
+    ) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new(self.vault_usdc, false),
+            AccountMeta::new(to.vault, false),
+            AccountMeta::new(to.vault_usdc, false),
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        if let Some(delegate) = delegate {
+            accounts.push(AccountMeta::new_readonly(self.delegate_pda(delegate), false));
+        }
+        vault_ix(accounts, VaultInstruction::TransferBetweenVaults { amount })
+    }
+
+    pub fn request_withdrawal_ix(&self, amount: u64) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(withdrawal_request_pda(&self.owner.pubkey()), false),
+                AccountMeta::new(self.owner.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            VaultInstruction::RequestWithdrawal { amount },
+        )
+    }
+
+    /// 任何人都可以提交，owner 不需要签名
+    pub fn execute_withdrawal_ix(&self) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(withdrawal_request_pda(&self.owner.pubkey()), false),
+                AccountMeta::new(self.usdc, false),
+                AccountMeta::new(self.vault_usdc, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.owner.pubkey(), false),
+            ],
+            VaultInstruction::ExecuteWithdrawal,
+        )
+    }
+
+    pub fn cancel_withdrawal_request_ix(&self) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(withdrawal_request_pda(&self.owner.pubkey()), false),
+                AccountMeta::new(self.owner.pubkey(), true),
+            ],
+            VaultInstruction::CancelWithdrawalRequest,
+        )
+    }
+
+    pub fn schedule_withdrawal_ix(&self, amount: u64) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(pending_withdrawal_pda(&self.owner.pubkey()), false),
+                AccountMeta::new(self.owner.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            VaultInstruction::ScheduleWithdrawal { amount },
+        )
+    }
+
+    /// 任何人都可以提交，租金退回 owner（发起人）
+    pub fn execute_pending_withdrawal_ix(&self) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(pending_withdrawal_pda(&self.owner.pubkey()), false),
+                AccountMeta::new(self.usdc, false),
+                AccountMeta::new(self.vault_usdc, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.owner.pubkey(), false),
+            ],
+            VaultInstruction::ExecutePendingWithdrawal,
+        )
+    }
+
+    pub fn cancel_pending_withdrawal_ix(&self) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(pending_withdrawal_pda(&self.owner.pubkey()), false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+                AccountMeta::new(self.owner.pubkey(), false),
+            ],
+            VaultInstruction::CancelPendingWithdrawal,
+        )
+    }
+
+    /// signer 为 delegate 时需要传入 delegate 公钥，附带对应的 DelegateAccount
+    pub fn withdraw_ix(&self, signer: &Pubkey, delegate: Option<&Pubkey>, amount: u64) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new(self.usdc, false),
+            AccountMeta::new(self.vault_usdc, false),
+            AccountMeta::new_readonly(global_config_pda(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        if let Some(delegate) = delegate {
+            accounts.push(AccountMeta::new(self.delegate_pda(delegate), false));
+        }
+        vault_ix(accounts, VaultInstruction::Withdraw { amount })
+    }
+
+    pub fn upsert_delegate_ix(
+        &self,
+        delegate: &Pubkey,
+        permissions: u64,
+        max_notional: u64,
+        expiry_slot: u64,
+        max_leverage: u64,
+    ) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(self.owner.pubkey(), true),
+                AccountMeta::new_readonly(global_config_pda(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::UpsertDelegate {
+                delegate_pubkey: *delegate,
+                permissions,
+                max_notional,
+                expiry_slot,
+                max_leverage,
+                label: [0; DELEGATE_LABEL_LEN],
+                not_before_slot: 0,
+                expiry_unix_ts: 0,
+                heartbeat_interval_secs: 0,
+                allowed_markets: 0,
+            },
+        )
+    }
+
+    /// 以默认额度创建带生效 slot 和时间戳过期的 delegate
+    pub fn scheduled_delegate_ix(
+        &self,
+        delegate: &Pubkey,
+        permissions: u64,
+        not_before_slot: u64,
+        expiry_slot: u64,
+        expiry_unix_ts: i64,
+    ) -> Instruction {
+        let mut ix = self.upsert_delegate_ix(delegate, permissions, 1_000 * USDC, expiry_slot, 0);
+        ix.data = VaultInstruction::UpsertDelegate {
+            delegate_pubkey: *delegate,
+            permissions,
+            max_notional: 1_000 * USDC,
+            expiry_slot,
+            max_leverage: 0,
+            label: [0; DELEGATE_LABEL_LEN],
+            not_before_slot,
+            expiry_unix_ts,
+            heartbeat_interval_secs: 0,
+            allowed_markets: 0,
+        }
+        .try_to_vec()
+        .unwrap();
+        ix
+    }
+
+    /// 以默认额度授权（或更新）要求心跳的 delegate
+    pub fn heartbeat_delegate_ix(
+        &self,
+        delegate: &Pubkey,
+        permissions: u64,
+        expiry_slot: u64,
+        heartbeat_interval_secs: u64,
+    ) -> Instruction {
+        let mut ix = self.upsert_delegate_ix(delegate, permissions, 1_000 * USDC, expiry_slot, 0);
+        ix.data = VaultInstruction::UpsertDelegate {
+            delegate_pubkey: *delegate,
+            permissions,
+            max_notional: 1_000 * USDC,
+            expiry_slot,
+            max_leverage: 0,
+            label: [0; DELEGATE_LABEL_LEN],
+            not_before_slot: 0,
+            expiry_unix_ts: 0,
+            heartbeat_interval_secs,
+            allowed_markets: 0,
+        }
+        .try_to_vec()
+        .unwrap();
+        ix
+    }
+
+    pub fn revoke_delegate_ix(&self, delegate: &Pubkey) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+                AccountMeta::new_readonly(global_config_pda(), false),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::RevokeDelegate { delegate_pubkey: *delegate },
+        )
+    }
+
+    pub fn set_delegate_metadata_ix(
+        &self,
+        delegate: &Pubkey,
+        label: [u8; DELEGATE_LABEL_LEN],
+        strategy_id: u64,
+    ) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new_readonly(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::SetDelegateMetadata {
+                delegate_pubkey: *delegate,
+                label,
+                strategy_id,
+            },
+        )
+    }
+
+    pub fn rotate_delegate_ix(&self, old_delegate: &Pubkey, new_delegate: &Pubkey) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(old_delegate), false),
+                AccountMeta::new(self.delegate_pda(new_delegate), false),
+                AccountMeta::new_readonly(self.vault, false),
+                AccountMeta::new(self.owner.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::RotateDelegate {
+                old_delegate_pubkey: *old_delegate,
+                new_delegate_pubkey: *new_delegate,
+            },
+        )
+    }
+
+    /// `paused` 为 true 时暂停，否则恢复
+    pub fn pause_delegate_ix(&self, delegate: &Pubkey, paused: bool) -> Instruction {
+        let data = if paused {
+            VaultInstruction::PauseDelegate { delegate_pubkey: *delegate }
+        } else {
+            VaultInstruction::ResumeDelegate { delegate_pubkey: *delegate }
+        };
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new_readonly(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            data,
+        )
+    }
+
+    pub fn heartbeat_ix(&self, delegate: &Pubkey) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new_readonly(*delegate, true),
+            ],
+            VaultInstruction::Heartbeat,
+        )
+    }
+
+    /// 由 `signer` 签名撤销 `delegate` 的授权
+    pub fn self_revoke_delegate_ix(&self, delegate: &Pubkey, signer: &Pubkey) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new_readonly(self.vault, false),
+                AccountMeta::new_readonly(*signer, true),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::SelfRevokeDelegate,
+        )
+    }
+
+    /// 由父 delegate `parent` 签名创建子 delegate `child`
+    pub fn create_subdelegate_ix(
+        &self,
+        parent: &Pubkey,
+        child: &Pubkey,
+        permissions: u64,
+        max_notional: u64,
+        expiry_slot: u64,
+        max_leverage: u64,
+    ) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(child), false),
+                AccountMeta::new_readonly(self.delegate_pda(parent), false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(*parent, true),
+                AccountMeta::new_readonly(global_config_pda(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::CreateSubDelegate {
+                child_pubkey: *child,
+                permissions,
+                max_notional,
+                expiry_slot,
+                max_leverage,
+                label: [0; DELEGATE_LABEL_LEN],
+            },
+        )
+    }
+
+    /// 在 Withdraw / LockMargin / Unlock 末尾追加子 delegate 的父 DelegateAccount
+    pub fn with_parent(&self, mut ix: Instruction, parent: &Pubkey) -> Instruction {
+        ix.accounts.push(AccountMeta::new(self.delegate_pda(parent), false));
+        ix
+    }
+
+    /// 以默认额度授权（或更新）只能在 `allowed_markets` 内开仓的 delegate
+    pub fn market_delegate_ix(
+        &self,
+        delegate: &Pubkey,
+        permissions: u64,
+        expiry_slot: u64,
+        allowed_markets: u128,
+    ) -> Instruction {
+        let mut ix = self.upsert_delegate_ix(delegate, permissions, 1_000 * USDC, expiry_slot, 0);
+        ix.data = VaultInstruction::UpsertDelegate {
+            delegate_pubkey: *delegate,
+            permissions,
+            max_notional: 1_000 * USDC,
+            expiry_slot,
+            max_leverage: 0,
+            label: [0; DELEGATE_LABEL_LEN],
+            not_before_slot: 0,
+            expiry_unix_ts: 0,
+            heartbeat_interval_secs: 0,
+            allowed_markets,
+        }
+        .try_to_vec()
+        .unwrap();
+        ix
+    }
+
+    /// 在 LockMargin / Unlock 末尾追加 instructions sysvar
+    /// 追加可选的独立 payer 账户（代付租金）
+    pub fn with_payer(&self, mut ix: Instruction, payer: &Pubkey) -> Instruction {
+        ix.accounts.push(AccountMeta::new(*payer, true));
+        ix
+    }
+
+    pub fn with_instructions_sysvar(&self, mut ix: Instruction) -> Instruction {
+        ix.accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+        ix
+    }
+
+    pub fn set_delegate_programs_ix(&self, delegate: &Pubkey, programs: Vec<Pubkey>) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.delegate_pda(delegate), false),
+                AccountMeta::new_readonly(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+            ],
+            VaultInstruction::SetDelegatePrograms { delegate_pubkey: *delegate, programs },
+        )
+    }
+
+    pub fn revoke_all_delegates_ix(&self) -> Instruction {
+        vault_ix(
+            vec![
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new_readonly(self.owner.pubkey(), true),
+                AccountMeta::new(self.registry_pda(), false),
+            ],
+            VaultInstruction::RevokeAllDelegates,
+        )
+    }
+
+    pub fn lock_margin_ix(
+        &self,
+        signer: &Pubkey,
+        delegate: Option<&Pubkey>,
+        required_margin: u64,
+        required_notional: u64,
+    ) -> Instruction {
+        self.lock_market_margin_ix(signer, delegate, required_margin, required_notional, 0)
+    }
+
+    pub fn lock_market_margin_ix(
+        &self,
+        signer: &Pubkey,
+        delegate: Option<&Pubkey>,
+        required_margin: u64,
+        required_notional: u64,
+        market_id: u16,
+    ) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new_readonly(*signer, true),
+        ];
+        accounts.push(self.optional_delegate_meta(delegate));
+        accounts.push(AccountMeta::new_readonly(global_config_pda(), false));
+        accounts.push(AccountMeta::new_readonly(sysvar::clock::id(), false));
+        vault_ix(
+            accounts,
+            VaultInstruction::LockMargin { required_margin, required_notional, market_id },
+        )
+    }
+
+    /// relayer 提交的 LockMarginWithIntent，`delegate` 不签名交易
+    pub fn lock_margin_with_intent_ix(
+        &self,
+        delegate: &Pubkey,
+        required_margin: u64,
+        required_notional: u64,
+        nonce: u64,
+        expiry_unix_ts: i64,
+    ) -> Instruction {
+        let mut ix = self.lock_margin_ix(delegate, Some(delegate), required_margin, required_notional);
+        ix.accounts[1].is_signer = false;
+        ix.data = VaultInstruction::LockMarginWithIntent {
+            required_margin,
+            required_notional,
+            market_id: 0,
+            nonce,
+            expiry_unix_ts,
+        }
+        .try_to_vec()
+        .unwrap();
+        self.with_instructions_sysvar(ix)
+    }
+
+    /// relayer 提交的 WithdrawWithIntent，`delegate` 不签名交易
+    pub fn withdraw_with_intent_ix(
+        &self,
+        delegate: &Pubkey,
+        amount: u64,
+        nonce: u64,
+        expiry_unix_ts: i64,
+    ) -> Instruction {
+        let mut ix = self.withdraw_ix(delegate, Some(delegate), amount);
+        ix.accounts[1].is_signer = false;
+        ix.data = VaultInstruction::WithdrawWithIntent { amount, nonce, expiry_unix_ts }
+            .try_to_vec()
+            .unwrap();
+        self.with_instructions_sysvar(ix)
+    }
+
+    /// owner 对 `inner` 签名的许可消息（borsh 编码的 OwnerPermit）
+    pub fn owner_permit_message(&self, inner: &Instruction, nonce: u64, deadline: i64) -> Vec<u8> {
+        OwnerPermit {
+            program_id: vault_program::id(),
+            vault: self.vault,
+            nonce,
+            deadline,
+            instruction_data: inner.data.clone(),
+        }
+        .try_to_vec()
+        .unwrap()
+    }
+
+    /// relayer 提交的 ExecuteOwnerPermit，owner 不签名交易，`payer` 支付租金
+    pub fn execute_owner_permit_ix(
+        &self,
+        inner: Instruction,
+        payer: &Pubkey,
+        nonce: u64,
+        deadline: i64,
+    ) -> Instruction {
+        let mut accounts = inner.accounts;
+        for meta in accounts.iter_mut() {
+            if meta.pubkey == self.owner.pubkey() {
+                meta.is_signer = false;
+            }
+        }
+        accounts.push(AccountMeta::new(*payer, true));
+        accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+        vault_ix(
+            accounts,
+            VaultInstruction::ExecuteOwnerPermit {
+                instruction_data: inner.data,
+                nonce,
+                deadline,
+            },
+        )
+    }
+
+    pub fn unlock_margin_ix(
+        &self,
+        signer: &Pubkey,
+        delegate: Option<&Pubkey>,
+        unlocked_margin: u64,
+        pnl_delta: i64,
+        notional_delta: i64,
+    ) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new_readonly(*signer, true),
+        ];
+        accounts.push(self.optional_delegate_meta(delegate));
+        accounts.push(AccountMeta::new_readonly(global_config_pda(), false));
+        vault_ix(
+            accounts,
+            VaultInstruction::UnlockMarginAndUpdatePnl { unlocked_margin, pnl_delta, notional_delta },
+        )
+    }
+}
+
+/// program-test 环境：USDC Mint 和 GlobalConfig 已初始化，payer 为 admin
+pub struct VaultTest {
+    pub context: ProgramTestContext,
+    pub usdc_mint: Keypair,
+    tx_count: u32,
+}
+
+impl VaultTest {
+    pub async fn start() -> Self {
+        let program_test = ProgramTest::new(
+            "vault_program",
+            vault_program::id(),
+            processor!(vault_program::process_instruction),
+        );
+        let context = program_test.start_with_context().await;
+        let mut test = Self {
+            context,
+            usdc_mint: Keypair::new(),
+            tx_count: 0,
+        };
+
+        // 创建 USDC Mint（6 位小数，payer 为 mint authority）
+        let payer = test.payer();
+        let rent = test.context.banks_client.get_rent().await.unwrap();
+        let mint = test.usdc_mint.pubkey();
+        let create_mint = system_instruction::create_account(
+            &payer,
+            &mint,
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        );
+        let init_mint =
+            spl_token::instruction::initialize_mint2(&spl_token::id(), &mint, &payer, None, 6)
+                .unwrap();
+        let mint_signer = test.usdc_mint.insecure_clone();
+        test.process(&[create_mint, init_mint], &[&mint_signer]).await.unwrap();
+
+        // 初始化 GlobalConfig
+        let init_config = vault_ix(
+            vec![
+                AccountMeta::new(global_config_pda(), false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            VaultInstruction::InitializeGlobalConfig { usdc_mint: mint },
+        );
+        test.process(&[init_config], &[]).await.unwrap();
+
+        test
+    }
+
+    pub fn payer(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    /// 发送交易（payer 付费并签名）
+    ///
+    /// 末尾附加一条递增的 compute unit limit 指令，避免内容相同的交易被当作重复交易丢弃
+    pub async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        self.tx_count += 1;
+        let mut instructions = instructions.to_vec();
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+            1_400_000 - self.tx_count,
+        ));
+
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let blockhash = self.context.banks_client.get_latest_blockhash().await.unwrap();
+        let tx = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        self.context.banks_client.process_transaction(tx).await
+    }
+
+    /// 创建用户：转入 SOL、创建 USDC ATA 并 mint `usdc_amount`，然后创建 Vault
+    /// 给账户转入 SOL（delegate 支付租金时使用）
+    pub async fn fund(&mut self, address: &Pubkey, lamports: u64) {
+        let payer = self.payer();
+        self.process(&[system_instruction::transfer(&payer, address, lamports)], &[])
+            .await
+            .unwrap();
+    }
+
+    pub async fn create_user(&mut self, usdc_amount: u64) -> VaultUser {
+        let owner = Keypair::new();
+        let payer = self.payer();
+        let mint = self.usdc_mint.pubkey();
+        let usdc = get_associated_token_address(&owner.pubkey(), &mint);
+
+        let fund = system_instruction::transfer(&payer, &owner.pubkey(), 10_000_000_000);
+        let create_ata =
+            create_associated_token_account(&payer, &owner.pubkey(), &mint, &spl_token::id());
+        let mint_to = spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &mint,
+            &usdc,
+            &payer,
+            &[],
+            usdc_amount,
+        )
+        .unwrap();
+        self.process(&[fund, create_ata, mint_to], &[]).await.unwrap();
+
+        let user = VaultUser {
+            vault: vault_pda(&owner.pubkey()),
+            vault_usdc: vault_usdc_pda(&owner.pubkey()),
+            usdc,
+            owner,
+        };
+        let create_vault = vault_ix(
+            vec![
+                AccountMeta::new(user.vault, false),
+                AccountMeta::new(user.vault_usdc, false),
+                AccountMeta::new(user.owner.pubkey(), true),
+                AccountMeta::new_readonly(global_config_pda(), false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            VaultInstruction::CreateVault,
+        );
+        let owner = user.owner.insecure_clone();
+        self.process(&[create_vault], &[&owner]).await.unwrap();
+
+        user
+    }
+
+    /// 创建用户并存入 `deposit` USDC（钱包中额外保留 `deposit` USDC）
+    pub async fn create_funded_user(&mut self, deposit: u64) -> VaultUser {
+        let user = self.create_user(deposit * 2).await;
+        let owner = user.owner.insecure_clone();
+        self.process(&[user.deposit_ix(deposit)], &[&owner]).await.unwrap();
+        user
+    }
+
+    pub async fn vault(&mut self, user: &VaultUser) -> UserVault {
+        self.account(&user.vault).await
+    }
+
+    pub async fn delegate(&mut self, user: &VaultUser, delegate: &Pubkey) -> DelegateAccount {
+        self.account(&user.delegate_pda(delegate)).await
+    }
+
+    pub async fn registry(&mut self, user: &VaultUser) -> DelegateRegistry {
+        self.account(&user.registry_pda()).await
+    }
+
+    pub async fn raw_account(&mut self, address: &Pubkey) -> Account {
+        self.context
+            .banks_client
+            .get_account(*address)
+            .await
+            .unwrap()
+            .expect("account not found")
+    }
+
+    pub async fn account_exists(&mut self, address: &Pubkey) -> bool {
+        self.context.banks_client.get_account(*address).await.unwrap().is_some()
+    }
+
+    pub async fn account<T: BorshDeserialize>(&mut self, address: &Pubkey) -> T {
+        let account = self
+            .context
+            .banks_client
+            .get_account(*address)
+            .await
+            .unwrap()
+            .expect("account not found");
+        T::try_from_slice(&account.data).unwrap()
+    }
+
+    pub async fn token_balance(&mut self, address: &Pubkey) -> u64 {
+        let account = self
+            .context
+            .banks_client
+            .get_account(*address)
+            .await
+            .unwrap()
+            .expect("token account not found");
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    pub async fn slot(&mut self) -> u64 {
+        self.clock().await.slot
+    }
+
+    pub async fn clock(&mut self) -> Clock {
+        self.context.banks_client.get_sysvar::<Clock>().await.unwrap()
+    }
+}
+
+/// 断言交易因指定的 VaultError 失败
+pub fn assert_vault_error(result: Result<(), BanksClientError>, expected: VaultError) {
+    match result.expect_err("transaction should fail").unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, expected as u32, "expected {:?}", expected);
+        }
+        other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+}
@@ -0,0 +1,204 @@
+//! Delegate 链下签名授权测试
+//!
+//! relayer 提交 LockMarginWithIntent / WithdrawWithIntent，nonce 防重放，
+//! 授权绑定 delegate_epoch 和 DelegateAccount.serial
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::BanksClientError;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{
+    DelegateIntent, VaultError, VaultInstruction, INTENT_ACTION_LOCK_MARGIN,
+    INTENT_ACTION_WITHDRAW, PERM_TRADE, PERM_WITHDRAW,
+};
+
+/// 创建有 PERM_TRADE | PERM_WITHDRAW 权限并分配 50 USDC 的 delegate
+async fn add_delegate(test: &mut VaultTest, user: &VaultUser) -> Keypair {
+    let api_key = Keypair::new();
+    let owner = user.owner.insecure_clone();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[
+            user.upsert_delegate_ix(
+                &api_key.pubkey(),
+                PERM_TRADE | PERM_WITHDRAW,
+                1_000 * USDC,
+                expiry_slot,
+                0,
+            ),
+            user.delegate_owner_ix(
+                &api_key.pubkey(),
+                VaultInstruction::AllocateCollateral {
+                    delegate_pubkey: api_key.pubkey(),
+                    amount: 50 * USDC,
+                },
+            ),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    api_key
+}
+
+/// 按当前 DelegateAccount 构造待签名的授权消息
+async fn intent(
+    test: &mut VaultTest,
+    user: &VaultUser,
+    delegate: &Pubkey,
+    action: u8,
+    amount: u64,
+    notional: u64,
+    nonce: u64,
+) -> Vec<u8> {
+    let account = test.delegate(user, delegate).await;
+    let expiry_unix_ts = test.clock().await.unix_timestamp + 60;
+    DelegateIntent {
+        program_id: vault_program::id(),
+        vault: user.vault,
+        delegate: *delegate,
+        delegate_epoch: account.delegate_epoch,
+        delegate_serial: account.serial,
+        action,
+        amount,
+        notional,
+        market_id: 0,
+        nonce,
+        expiry_unix_ts,
+    }
+    .try_to_vec()
+    .unwrap()
+}
+
+/// 由 relayer（测试 payer）提交签名后的 LockMarginWithIntent
+async fn relay_lock(
+    test: &mut VaultTest,
+    user: &VaultUser,
+    signer: &Keypair,
+    message: &[u8],
+    nonce: u64,
+) -> Result<(), BanksClientError> {
+    let delegate = test.delegate(user, &signer.pubkey()).await;
+    let expiry_unix_ts = DelegateIntent::try_from_slice(message).unwrap().expiry_unix_ts;
+    let lock = user.lock_margin_with_intent_ix(
+        &delegate.delegate,
+        10 * USDC,
+        10 * USDC,
+        nonce,
+        expiry_unix_ts,
+    );
+    test.process(&[ed25519_ix(signer, message), lock], &[]).await
+}
+
+#[tokio::test]
+async fn test_relayer_submits_signed_lock_intent() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let api_key = add_delegate(&mut test, &user).await;
+    let key = api_key.pubkey();
+
+    let message = intent(&mut test, &user, &key, INTENT_ACTION_LOCK_MARGIN, 10 * USDC, 10 * USDC, 1).await;
+    relay_lock(&mut test, &user, &api_key, &message, 1).await.unwrap();
+    let delegate = test.delegate(&user, &key).await;
+    assert_eq!(delegate.nonce, 1);
+    assert_eq!(delegate.allocated_locked, 10 * USDC);
+
+    // 同一授权不能重放
+    let result = relay_lock(&mut test, &user, &api_key, &message, 1).await;
+    assert_vault_error(result, VaultError::InvalidIntentNonce);
+}
+
+#[tokio::test]
+async fn test_intent_requires_delegate_signature() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let api_key = add_delegate(&mut test, &user).await;
+    let key = api_key.pubkey();
+    let message = intent(&mut test, &user, &key, INTENT_ACTION_LOCK_MARGIN, 10 * USDC, 10 * USDC, 1).await;
+    let expiry_unix_ts = DelegateIntent::try_from_slice(&message).unwrap().expiry_unix_ts;
+    let lock = user.lock_margin_with_intent_ix(&key, 10 * USDC, 10 * USDC, 1, expiry_unix_ts);
+
+    // 缺少 Ed25519 验签指令
+    let result = test.process(std::slice::from_ref(&lock), &[]).await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+
+    // 其他 key 的签名
+    let result = test.process(&[ed25519_ix(&Keypair::new(), &message), lock.clone()], &[]).await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+
+    // 签名消息与指令参数不一致
+    let other = intent(&mut test, &user, &key, INTENT_ACTION_LOCK_MARGIN, 20 * USDC, 10 * USDC, 1).await;
+    let result = test.process(&[ed25519_ix(&api_key, &other), lock], &[]).await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+}
+
+#[tokio::test]
+async fn test_intent_is_bound_to_delegate_account_serial() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = add_delegate(&mut test, &user).await;
+    let key = api_key.pubkey();
+    let first_serial = test.delegate(&user, &key).await.serial;
+    let stale = intent(&mut test, &user, &key, INTENT_ACTION_LOCK_MARGIN, 10 * USDC, 10 * USDC, 1).await;
+
+    // 轮换关闭旧账户后重新授权同一 key：nonce 归零，但 serial 不同
+    let rotated = Keypair::new();
+    test.process(&[user.rotate_delegate_ix(&key, &rotated.pubkey())], &[&owner])
+        .await
+        .unwrap();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[user.upsert_delegate_ix(&key, PERM_TRADE, 1_000 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let delegate = test.delegate(&user, &key).await;
+    assert_eq!(delegate.nonce, 0);
+    assert!(delegate.serial > first_serial);
+    assert_eq!(test.delegate(&user, &rotated.pubkey()).await.serial, first_serial);
+
+    let result = relay_lock(&mut test, &user, &api_key, &stale, 1).await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+}
+
+#[tokio::test]
+async fn test_relayer_submits_signed_withdraw_intent() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let api_key = add_delegate(&mut test, &user).await;
+    let key = api_key.pubkey();
+    let balance = test.token_balance(&user.usdc).await;
+
+    let message = intent(&mut test, &user, &key, INTENT_ACTION_WITHDRAW, 5 * USDC, 0, 7).await;
+    let expiry_unix_ts = DelegateIntent::try_from_slice(&message).unwrap().expiry_unix_ts;
+    test.process(
+        &[
+            ed25519_ix(&api_key, &message),
+            user.withdraw_with_intent_ix(&key, 5 * USDC, 7, expiry_unix_ts),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(test.token_balance(&user.usdc).await, balance + 5 * USDC);
+    assert_eq!(test.delegate(&user, &key).await.nonce, 7);
+
+    // 锁定授权不能当作提款授权使用
+    let lock_message = intent(&mut test, &user, &key, INTENT_ACTION_LOCK_MARGIN, 5 * USDC, 0, 8).await;
+    let expiry_unix_ts = DelegateIntent::try_from_slice(&lock_message).unwrap().expiry_unix_ts;
+    let result = test
+        .process(
+            &[
+                ed25519_ix(&api_key, &lock_message),
+                user.withdraw_with_intent_ix(&key, 5 * USDC, 8, expiry_unix_ts),
+            ],
+            &[],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+}
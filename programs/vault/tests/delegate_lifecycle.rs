@@ -0,0 +1,295 @@
+//! Delegate 生命周期测试
+//!
+//! API Key 轮换、自行撤销、暂停 / 恢复、延迟生效、按时间戳过期
+
+mod common;
+
+use common::*;
+use solana_program::instruction::AccountMeta;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{
+    VaultError, PERM_TRADE, PERM_WITHDRAW, REGISTRY_STATUS_ACTIVE, REGISTRY_STATUS_PAUSED,
+    REGISTRY_STATUS_REVOKED,
+};
+
+/// 创建有 PERM_TRADE | PERM_WITHDRAW 权限的 delegate
+async fn add_delegate(test: &mut VaultTest, user: &VaultUser) -> Keypair {
+    let api_key = Keypair::new();
+    let owner = user.owner.insecure_clone();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[user.upsert_delegate_ix(
+            &api_key.pubkey(),
+            PERM_TRADE | PERM_WITHDRAW,
+            1_000 * USDC,
+            expiry_slot,
+            0,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    api_key
+}
+
+#[tokio::test]
+async fn test_rotate_delegate_moves_state_to_new_key() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let old_key = add_delegate(&mut test, &user).await;
+    test.process(&[user.set_delegate_metadata_ix(&old_key.pubkey(), label("mm bot"), 3)], &[&owner])
+        .await
+        .unwrap();
+    let old = test.delegate(&user, &old_key.pubkey()).await;
+
+    let new_key = Keypair::new();
+    let old_pda = user.delegate_pda(&old_key.pubkey());
+    let owner_lamports = test.raw_account(&user.owner_key()).await.lamports;
+    test.process(&[user.rotate_delegate_ix(&old_key.pubkey(), &new_key.pubkey())], &[&owner])
+        .await
+        .unwrap();
+
+    // 旧账户关闭，退回的租金抵付新账户租金
+    assert!(!test.account_exists(&old_pda).await);
+    assert_eq!(test.raw_account(&user.owner_key()).await.lamports, owner_lamports);
+
+    let new = test.delegate(&user, &new_key.pubkey()).await;
+    assert_eq!(new.delegate, new_key.pubkey());
+    assert_eq!(new.permissions, old.permissions);
+    assert_eq!(new.max_notional, old.max_notional);
+    assert_eq!(new.expiry_slot, old.expiry_slot);
+    assert_eq!(new.label, label("mm bot"));
+    assert_eq!(new.strategy_id, 3);
+    assert_eq!(new.created_at, old.created_at);
+    let registry = test.registry(&user).await;
+    assert_eq!(registry.entries.len(), 1);
+    assert_eq!(registry.entries[0].delegate, new_key.pubkey());
+
+    // 新 key 可以直接使用，旧 key 失效
+    test.process(
+        &[user.withdraw_ix(&new_key.pubkey(), Some(&new_key.pubkey()), USDC)],
+        &[&new_key],
+    )
+    .await
+    .unwrap();
+    let result = test
+        .process(
+            &[user.withdraw_ix(&old_key.pubkey(), Some(&old_key.pubkey()), USDC)],
+            &[&old_key],
+        )
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_rotate_delegate_rejects_existing_or_same_key() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let first = add_delegate(&mut test, &user).await;
+    let second = add_delegate(&mut test, &user).await;
+
+    let result = test
+        .process(&[user.rotate_delegate_ix(&first.pubkey(), &second.pubkey())], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::AccountAlreadyExists);
+
+    let result = test
+        .process(&[user.rotate_delegate_ix(&first.pubkey(), &first.pubkey())], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InvalidDelegate);
+}
+
+#[tokio::test]
+async fn test_sponsored_delegate_rent_refunds_to_payer() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let sponsor = Keypair::new();
+    test.fund(&sponsor.pubkey(), 1_000_000_000).await;
+    let api_key = Keypair::new();
+    let expiry_slot = test.slot().await + 10_000;
+
+    // 独立 payer 支付新账户租金并被记录
+    let upsert = user.upsert_delegate_ix(&api_key.pubkey(), PERM_TRADE, 1_000 * USDC, expiry_slot, 0);
+    test.process(&[user.with_payer(upsert, &sponsor.pubkey())], &[&owner, &sponsor])
+        .await
+        .unwrap();
+    assert_eq!(test.delegate(&user, &api_key.pubkey()).await.rent_payer, sponsor.pubkey());
+
+    // 轮换时必须提供原 payer 接收退款
+    let new_key = Keypair::new();
+    let rotate = user.rotate_delegate_ix(&api_key.pubkey(), &new_key.pubkey());
+    let result = test.process(std::slice::from_ref(&rotate), &[&owner]).await;
+    assert_vault_error(result, VaultError::InvalidRentPayer);
+
+    let mut wrong = rotate.clone();
+    wrong.accounts.push(AccountMeta::new(Keypair::new().pubkey(), false));
+    let result = test.process(&[wrong], &[&owner]).await;
+    assert_vault_error(result, VaultError::InvalidRentPayer);
+
+    let old_rent = test.raw_account(&user.delegate_pda(&api_key.pubkey())).await.lamports;
+    let sponsor_lamports = test.raw_account(&sponsor.pubkey()).await.lamports;
+    let mut rotate = rotate;
+    rotate.accounts.push(AccountMeta::new(sponsor.pubkey(), false));
+    test.process(&[rotate], &[&owner]).await.unwrap();
+    assert_eq!(
+        test.raw_account(&sponsor.pubkey()).await.lamports,
+        sponsor_lamports + old_rent
+    );
+    assert_eq!(test.delegate(&user, &new_key.pubkey()).await.rent_payer, owner.pubkey());
+}
+
+#[tokio::test]
+async fn test_self_revoke_delegate() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let leaked = add_delegate(&mut test, &user).await;
+    let other = add_delegate(&mut test, &user).await;
+
+    // 只能撤销自己的授权
+    let result = test
+        .process(&[user.self_revoke_delegate_ix(&leaked.pubkey(), &other.pubkey())], &[&other])
+        .await;
+    assert_vault_error(result, VaultError::InvalidDelegate);
+
+    test.process(&[user.self_revoke_delegate_ix(&leaked.pubkey(), &leaked.pubkey())], &[&leaked])
+        .await
+        .unwrap();
+    let delegate = test.delegate(&user, &leaked.pubkey()).await;
+    assert!(!delegate.is_active);
+    assert_eq!(test.registry(&user).await.entries[0].status, REGISTRY_STATUS_REVOKED);
+
+    let result = test
+        .process(
+            &[user.withdraw_ix(&leaked.pubkey(), Some(&leaked.pubkey()), USDC)],
+            &[&leaked],
+        )
+        .await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+
+    // owner 可以重新授权
+    let expiry_slot = test.slot().await + 1_000;
+    test.process(
+        &[user.upsert_delegate_ix(&leaked.pubkey(), PERM_WITHDRAW, 1_000 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(test.registry(&user).await.entries[0].status, REGISTRY_STATUS_ACTIVE);
+    test.process(
+        &[user.withdraw_ix(&leaked.pubkey(), Some(&leaked.pubkey()), USDC)],
+        &[&leaked],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_paused_delegate_is_rejected_until_resumed() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = add_delegate(&mut test, &user).await;
+
+    test.process(&[user.pause_delegate_ix(&api_key.pubkey(), true)], &[&owner])
+        .await
+        .unwrap();
+    assert!(test.delegate(&user, &api_key.pubkey()).await.is_paused);
+    assert_eq!(test.registry(&user).await.entries[0].status, REGISTRY_STATUS_PAUSED);
+
+    let withdraw = [user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), USDC)];
+    let result = test.process(&withdraw, &[&api_key]).await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+
+    // 更新配置不会解除暂停
+    let expiry_slot = test.slot().await + 20_000;
+    test.process(
+        &[user.upsert_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, 500 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let delegate = test.delegate(&user, &api_key.pubkey()).await;
+    assert!(delegate.is_paused);
+    assert_eq!(delegate.max_notional, 500 * USDC);
+    assert_eq!(test.registry(&user).await.entries[0].status, REGISTRY_STATUS_PAUSED);
+
+    test.process(&[user.pause_delegate_ix(&api_key.pubkey(), false)], &[&owner])
+        .await
+        .unwrap();
+    assert_eq!(test.registry(&user).await.entries[0].status, REGISTRY_STATUS_ACTIVE);
+    test.process(&withdraw, &[&api_key]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_delegate_not_usable_before_not_before_slot() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let slot = test.slot().await;
+
+    // 生效时间不能晚于过期时间
+    let result = test
+        .process(
+            &[user.scheduled_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, slot + 100, slot + 100, 0)],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidExpirySlot);
+
+    test.process(
+        &[user.scheduled_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, slot + 50, slot + 10_000, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let withdraw = [user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), USDC)];
+    let result = test.process(&withdraw, &[&api_key]).await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+
+    test.context.warp_to_slot(slot + 50).unwrap();
+    test.process(&withdraw, &[&api_key]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_delegate_expires_by_unix_timestamp() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let now = test.clock().await.unix_timestamp;
+
+    // 时间戳不能早于当前时间
+    let result = test
+        .process(
+            &[user.scheduled_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, 0, 0, now)],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidExpiryTimestamp);
+
+    // expiry_slot 为 0 时仅按时间戳过期
+    test.process(
+        &[user.scheduled_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, 0, 0, now + 3_600)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let delegate = test.delegate(&user, &api_key.pubkey()).await;
+    assert_eq!(delegate.expiry_slot, u64::MAX);
+    assert_eq!(delegate.expiry_unix_ts, now + 3_600);
+    assert_eq!(test.registry(&user).await.entries[0].expiry_unix_ts, now + 3_600);
+
+    let withdraw = [user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), USDC)];
+    test.process(&withdraw, &[&api_key]).await.unwrap();
+
+    let mut clock = test.clock().await;
+    clock.unix_timestamp = now + 3_601;
+    test.context.set_sysvar(&clock);
+    let result = test.process(&withdraw, &[&api_key]).await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+}
@@ -0,0 +1,234 @@
+//! Delegate Registry 测试
+//!
+//! 注册表登记、数量上限、失效槽位复用、RevokeAllDelegates 同步、标签元数据
+
+mod common;
+
+use common::*;
+use solana_program::instruction::AccountMeta;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{
+    DelegateAccount, VaultError, VaultInstruction, PERM_TRADE, REGISTRY_STATUS_REVOKED,
+};
+
+/// 授权一个新的 delegate，成功时返回其 API Key
+async fn grant(test: &mut VaultTest, user: &VaultUser, expiry_slot: u64) -> Option<Keypair> {
+    let api_key = Keypair::new();
+    let owner = user.owner.insecure_clone();
+    test.process(
+        &[user.upsert_delegate_ix(&api_key.pubkey(), PERM_TRADE, 1_000 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .ok()
+    .map(|_| api_key)
+}
+
+async fn set_max_delegates(test: &mut VaultTest, max_delegates: u64) {
+    let payer = test.payer();
+    test.process(&[admin_ix(&payer, VaultInstruction::SetMaxDelegatesPerVault { max_delegates })], &[])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_registry_limits_live_delegates_and_reuses_slots() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    set_max_delegates(&mut test, 2).await;
+    let expiry_slot = test.slot().await + 1_000;
+
+    let first = grant(&mut test, &user, expiry_slot).await.unwrap();
+    let second = grant(&mut test, &user, expiry_slot).await.unwrap();
+    let registry = test.registry(&user).await;
+    assert_eq!(registry.owner, user.owner_key());
+    assert_eq!(registry.vault, user.vault);
+    assert_eq!(registry.entries.len(), 2);
+
+    let api_key = Keypair::new();
+    let result = test
+        .process(
+            &[user.upsert_delegate_ix(&api_key.pubkey(), PERM_TRADE, 1_000 * USDC, expiry_slot, 0)],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::TooManyDelegates);
+
+    // 更新已登记的 delegate 不受上限影响
+    test.process(
+        &[user.upsert_delegate_ix(&first.pubkey(), PERM_TRADE, 500 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 撤销后槽位被复用，registry 不再增长
+    test.process(&[user.revoke_delegate_ix(&first.pubkey())], &[&owner])
+        .await
+        .unwrap();
+    let third = grant(&mut test, &user, expiry_slot).await.unwrap();
+    let registry = test.registry(&user).await;
+    assert_eq!(registry.entries.len(), 2);
+    assert_eq!(registry.entries[0].delegate, third.pubkey());
+    assert_eq!(registry.entries[1].delegate, second.pubkey());
+
+    // 被撤销的 delegate 重新启用时同样受上限约束
+    let result = test
+        .process(
+            &[user.upsert_delegate_ix(&first.pubkey(), PERM_TRADE, 1_000 * USDC, expiry_slot, 0)],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::TooManyDelegates);
+}
+
+#[tokio::test]
+async fn test_registry_reuses_expired_slots() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    set_max_delegates(&mut test, 1).await;
+    let slot = test.slot().await;
+
+    let expiring = grant(&mut test, &user, slot + 5).await.unwrap();
+    assert!(grant(&mut test, &user, slot + 1_000).await.is_none());
+
+    test.context.warp_to_slot(slot + 10).unwrap();
+    let replacement = grant(&mut test, &user, slot + 1_000).await.unwrap();
+    let registry = test.registry(&user).await;
+    assert_eq!(registry.entries.len(), 1);
+    assert_eq!(registry.entries[0].delegate, replacement.pubkey());
+    assert_ne!(registry.entries[0].delegate, expiring.pubkey());
+}
+
+#[tokio::test]
+async fn test_revoke_all_delegates_syncs_registry() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+
+    // 尚未创建 registry 时传入派生地址
+    test.process(&[user.revoke_all_delegates_ix()], &[&owner])
+        .await
+        .unwrap();
+
+    set_max_delegates(&mut test, 2).await;
+    let expiry_slot = test.slot().await + 1_000;
+    grant(&mut test, &user, expiry_slot).await.unwrap();
+    grant(&mut test, &user, expiry_slot).await.unwrap();
+    test.process(&[user.revoke_all_delegates_ix()], &[&owner])
+        .await
+        .unwrap();
+    let registry = test.registry(&user).await;
+    assert!(registry.entries.iter().all(|entry| entry.status == REGISTRY_STATUS_REVOKED));
+
+    // 所有槽位都可复用
+    grant(&mut test, &user, expiry_slot).await.unwrap();
+    grant(&mut test, &user, expiry_slot).await.unwrap();
+    assert_eq!(test.registry(&user).await.entries.len(), 2);
+}
+
+#[tokio::test]
+async fn test_set_max_delegates_per_vault_bounds() {
+    let mut test = VaultTest::start().await;
+    let payer = test.payer();
+
+    for max_delegates in [0, 129] {
+        let result = test
+            .process(
+                &[admin_ix(&payer, VaultInstruction::SetMaxDelegatesPerVault { max_delegates })],
+                &[],
+            )
+            .await;
+        assert_vault_error(result, VaultError::InvalidMaxDelegates);
+    }
+
+    let user = test.create_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let result = test
+        .process(
+            &[admin_ix(&user.owner_key(), VaultInstruction::SetMaxDelegatesPerVault { max_delegates: 8 })],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidAuthority);
+}
+
+#[tokio::test]
+async fn test_set_delegate_metadata_syncs_registry() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let expiry_slot = test.slot().await + 1_000;
+    let api_key = grant(&mut test, &user, expiry_slot).await.unwrap();
+    let key = api_key.pubkey();
+
+    test.process(&[user.set_delegate_metadata_ix(&key, label("grid bot EU"), 7)], &[&owner])
+        .await
+        .unwrap();
+    let delegate = test.delegate(&user, &key).await;
+    assert_eq!(delegate.label, label("grid bot EU"));
+    assert_eq!(delegate.strategy_id, 7);
+    assert_eq!(test.registry(&user).await.entries[0].label, label("grid bot EU"));
+
+    // 控制字符与非法 UTF-8 被拒绝
+    for bad in [label("grid\nbot"), label("\u{1b}[31mred")] {
+        let result = test
+            .process(&[user.set_delegate_metadata_ix(&key, bad, 7)], &[&owner])
+            .await;
+        assert_vault_error(result, VaultError::InvalidLabel);
+    }
+    let mut invalid_utf8 = label("bot");
+    invalid_utf8[3] = 0xff;
+    let result = test
+        .process(&[user.set_delegate_metadata_ix(&key, invalid_utf8, 7)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InvalidLabel);
+
+    // 只有 owner 可以修改
+    let result = test
+        .process(
+            &[vault_ix(
+                vec![
+                    AccountMeta::new(user.delegate_pda(&key), false),
+                    AccountMeta::new_readonly(user.vault, false),
+                    AccountMeta::new_readonly(key, true),
+                    AccountMeta::new(user.registry_pda(), false),
+                ],
+                VaultInstruction::SetDelegateMetadata {
+                    delegate_pubkey: key,
+                    label: label("hijacked"),
+                    strategy_id: 0,
+                },
+            )],
+            &[&api_key],
+        )
+        .await;
+    assert!(result.is_err());
+    assert_eq!(test.delegate(&user, &key).await.label, label("grid bot EU"));
+}
+
+#[tokio::test]
+async fn test_migrate_v1_delegate_layout() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(10 * USDC).await;
+    let expiry_slot = test.slot().await + 1_000;
+    let api_key = grant(&mut test, &user, expiry_slot).await.unwrap();
+    let address = user.delegate_pda(&api_key.pubkey());
+
+    // v1 DelegateAccount 为 240 字节，没有 label / strategy_id
+    let mut account = test.raw_account(&address).await;
+    account.data.truncate(240);
+    account.data[8] = 1;
+    test.context.set_account(&address, &account.into());
+
+    let payer = test.payer();
+    test.process(&[migrate_account_ix(&address, &payer)], &[]).await.unwrap();
+    assert_eq!(test.raw_account(&address).await.data.len(), DelegateAccount::SIZE);
+    let delegate = test.delegate(&user, &api_key.pubkey()).await;
+    assert_eq!(delegate.version, DelegateAccount::VERSION);
+    assert_eq!(delegate.delegate, api_key.pubkey());
+    assert_eq!(delegate.label, label(""));
+    assert_eq!(delegate.strategy_id, 0);
+    assert_eq!(delegate.rent_payer, user.owner_key());
+}
@@ -0,0 +1,681 @@
+//! Delegate 风控测试
+//!
+//! 亏损熔断、杠杆上限、分配额度、储备金、心跳、调用方程序与市场白名单、代存款上限、
+//! 大额提款 owner co-sign 等 delegate 风险限制
+
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, InstructionError},
+    pubkey::Pubkey,
+};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transaction::TransactionError,
+};
+use vault_program::{
+    DelegateAccount, VaultError, VaultInstruction, MAX_ALLOWED_PROGRAMS, PERM_DEPOSIT, PERM_TRADE,
+    PERM_TRANSFER, PERM_WITHDRAW,
+};
+
+/// 创建有 PERM_TRADE 权限的 delegate
+async fn add_trader(
+    test: &mut VaultTest,
+    user: &VaultUser,
+    max_notional: u64,
+    max_leverage: u64,
+) -> Keypair {
+    let api_key = Keypair::new();
+    let owner = user.owner.insecure_clone();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[user.upsert_delegate_ix(
+            &api_key.pubkey(),
+            PERM_TRADE,
+            max_notional,
+            expiry_slot,
+            max_leverage,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    api_key
+}
+
+/// owner 给 delegate 分配保证金
+async fn allocate(test: &mut VaultTest, user: &VaultUser, delegate: &Pubkey, amount: u64) {
+    let owner = user.owner.insecure_clone();
+    test.process(
+        &[user.delegate_owner_ix(
+            delegate,
+            VaultInstruction::AllocateCollateral { delegate_pubkey: *delegate, amount },
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+}
+
+/// 创建 delegate 并分配 `allocation` 保证金
+async fn add_funded_trader(test: &mut VaultTest, user: &VaultUser, allocation: u64) -> Keypair {
+    let trader = add_trader(test, user, 1_000 * USDC, 0).await;
+    allocate(test, user, &trader.pubkey(), allocation).await;
+    trader
+}
+
+#[tokio::test]
+async fn test_loss_limit_switches_vault_to_close_only() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 50 * USDC).await;
+    let key = trader.pubkey();
+
+    test.process(
+        &[user.owner_ix(VaultInstruction::SetLossLimit {
+            loss_limit: 10 * USDC,
+            window_slots: 1_000,
+        })],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 亏损 15 USDC，超过 10 USDC 上限
+    test.process(
+        &[
+            user.lock_margin_ix(&key, Some(&key), 20 * USDC, 20 * USDC),
+            user.unlock_margin_ix(&key, Some(&key), 20 * USDC, -15 * USDC as i64, -20 * USDC as i64),
+        ],
+        &[&trader],
+    )
+    .await
+    .unwrap();
+    let vault = test.vault(&user).await;
+    assert!(vault.is_close_only());
+    assert_eq!(vault.loss_window_pnl, -15 * USDC as i64);
+    assert_eq!(vault.free_collateral, 50 * USDC);
+    assert_eq!(vault.delegated_collateral, 35 * USDC);
+
+    // 熔断期间 delegate 不能开仓，owner 不受影响
+    let result = test
+        .process(&[user.lock_margin_ix(&key, Some(&key), USDC, USDC)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::VaultCloseOnly);
+    test.process(&[user.lock_margin_ix(&owner.pubkey(), None, USDC, USDC)], &[&owner])
+        .await
+        .unwrap();
+
+    // owner 恢复交易后 delegate 可以重新开仓
+    test.process(&[user.owner_ix(VaultInstruction::ResumeTrading)], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert!(!vault.is_close_only());
+    assert_eq!(vault.loss_window_pnl, 0);
+    test.process(&[user.lock_margin_ix(&key, Some(&key), USDC, USDC)], &[&trader])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_loss_limit_requires_window() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(0).await;
+    let owner = user.owner.insecure_clone();
+
+    let result = test
+        .process(
+            &[user.owner_ix(VaultInstruction::SetLossLimit {
+                loss_limit: 10 * USDC,
+                window_slots: 0,
+            })],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidLossLimit);
+}
+
+#[tokio::test]
+async fn test_delegate_leverage_cap() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let trader = add_trader(&mut test, &user, 1_000 * USDC, 5).await;
+    let key = trader.pubkey();
+    allocate(&mut test, &user, &key, 10 * USDC).await;
+
+    let result = test
+        .process(&[user.lock_margin_ix(&key, Some(&key), 10 * USDC, 51 * USDC)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::LeverageExceeded);
+
+    test.process(&[user.lock_margin_ix(&key, Some(&key), 10 * USDC, 50 * USDC)], &[&trader])
+        .await
+        .unwrap();
+    assert_eq!(test.delegate(&user, &key).await.used_notional, 50 * USDC);
+}
+
+#[tokio::test]
+async fn test_global_leverage_cap_applies_to_owner() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let admin = test.payer();
+
+    // 只有 admin 可以设置全局杠杆上限
+    let result = test
+        .process(
+            &[admin_ix(&owner.pubkey(), VaultInstruction::SetMaxLeverage { max_leverage: 3 })],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidAuthority);
+    test.process(&[admin_ix(&admin, VaultInstruction::SetMaxLeverage { max_leverage: 3 })], &[])
+        .await
+        .unwrap();
+
+    let result = test
+        .process(&[user.lock_margin_ix(&owner.pubkey(), None, 10 * USDC, 31 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::LeverageExceeded);
+    test.process(&[user.lock_margin_ix(&owner.pubkey(), None, 10 * USDC, 30 * USDC)], &[&owner])
+        .await
+        .unwrap();
+
+    // 超过上限的参数被拒绝
+    let result = test
+        .process(&[admin_ix(&admin, VaultInstruction::SetMaxLeverage { max_leverage: 1_001 })], &[])
+        .await;
+    assert_vault_error(result, VaultError::InvalidMaxLeverage);
+}
+
+#[tokio::test]
+async fn test_delegate_trades_only_from_allocation() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 30 * USDC).await;
+    let key = trader.pubkey();
+
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 70 * USDC);
+    assert_eq!(vault.delegated_collateral, 30 * USDC);
+
+    let result = test
+        .process(&[user.lock_margin_ix(&key, Some(&key), 31 * USDC, 31 * USDC)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::InsufficientAllocation);
+
+    test.process(&[user.lock_margin_ix(&key, Some(&key), 20 * USDC, 20 * USDC)], &[&trader])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.locked_collateral, 20 * USDC);
+    assert_eq!(vault.delegate_locked_collateral, 20 * USDC);
+    assert_eq!(vault.delegated_collateral, 10 * USDC);
+
+    // 盈利回到分配额度，owner 不能收回已锁定的部分
+    test.process(
+        &[user.unlock_margin_ix(&key, Some(&key), 20 * USDC, 5 * USDC as i64, -20 * USDC as i64)],
+        &[&trader],
+    )
+    .await
+    .unwrap();
+    let delegate = test.delegate(&user, &key).await;
+    assert_eq!(delegate.allocated_collateral, 35 * USDC);
+    assert_eq!(delegate.allocated_locked, 0);
+    assert_eq!(delegate.used_notional, 0);
+    assert_eq!(test.vault(&user).await.delegated_collateral, 35 * USDC);
+
+    test.process(
+        &[user.delegate_owner_ix(
+            &key,
+            VaultInstruction::ReclaimCollateral { delegate_pubkey: key, amount: 35 * USDC },
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 105 * USDC);
+    assert_eq!(vault.delegated_collateral, 0);
+}
+
+#[tokio::test]
+async fn test_owner_settles_revoked_delegate_position() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 30 * USDC).await;
+    let key = trader.pubkey();
+
+    test.process(&[user.lock_margin_ix(&key, Some(&key), 20 * USDC, 20 * USDC)], &[&trader])
+        .await
+        .unwrap();
+    test.process(&[user.revoke_delegate_ix(&key)], &[&owner]).await.unwrap();
+
+    // 撤销后 delegate 不能再结算，owner 可以代为平仓；超出分配额度的亏损由 free_collateral 吸收
+    let result = test
+        .process(
+            &[user.unlock_margin_ix(&key, Some(&key), 20 * USDC, 0, -20 * USDC as i64)],
+            &[&trader],
+        )
+        .await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+    test.process(
+        &[user.unlock_margin_ix(
+            &owner.pubkey(),
+            Some(&key),
+            20 * USDC,
+            -40 * USDC as i64,
+            -20 * USDC as i64,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let delegate = test.delegate(&user, &key).await;
+    assert_eq!(delegate.allocated_collateral, 0);
+    assert_eq!(delegate.allocated_locked, 0);
+    assert_eq!(delegate.used_notional, 0);
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.locked_collateral, 0);
+    assert_eq!(vault.delegate_locked_collateral, 0);
+    assert_eq!(vault.delegated_collateral, 0);
+    assert_eq!(vault.free_collateral, 60 * USDC);
+}
+
+#[tokio::test]
+async fn test_unlock_settles_against_position_holder() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 30 * USDC).await;
+    let key = trader.pubkey();
+
+    test.process(&[user.lock_margin_ix(&owner.pubkey(), None, 10 * USDC, 10 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    test.process(&[user.lock_margin_ix(&key, Some(&key), 5 * USDC, 5 * USDC)], &[&trader])
+        .await
+        .unwrap();
+
+    // delegate 不能结算 owner 的仓位
+    let result = test
+        .process(&[user.unlock_margin_ix(&key, None, 10 * USDC, 0, 0)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::InvalidDelegate);
+
+    // owner 仓位只能解锁 owner 自己锁定的保证金
+    let result = test
+        .process(&[user.unlock_margin_ix(&owner.pubkey(), None, 15 * USDC, 0, 0)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InsufficientLockedMargin);
+
+    test.process(
+        &[user.unlock_margin_ix(&owner.pubkey(), None, 10 * USDC, -2 * USDC as i64, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 68 * USDC);
+    assert_eq!(vault.locked_collateral, 5 * USDC);
+    assert_eq!(vault.delegate_locked_collateral, 5 * USDC);
+    assert_eq!(test.delegate(&user, &key).await.allocated_locked, 5 * USDC);
+}
+
+#[tokio::test]
+async fn test_reserve_is_out_of_delegate_reach() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_trader(&mut test, &user, 1_000 * USDC, 0).await;
+    let key = trader.pubkey();
+
+    test.process(&[user.owner_ix(VaultInstruction::MoveToReserve { amount: 80 * USDC })], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 20 * USDC);
+    assert_eq!(vault.reserve_collateral, 80 * USDC);
+
+    // 储备金不能分配给 delegate，也不能被 owner 直接提取
+    let result = test
+        .process(
+            &[user.delegate_owner_ix(
+                &key,
+                VaultInstruction::AllocateCollateral { delegate_pubkey: key, amount: 21 * USDC },
+            )],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InsufficientFreeCollateral);
+    let result = test
+        .process(&[user.withdraw_ix(&owner.pubkey(), None, 21 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InsufficientFreeCollateral);
+
+    let result = test
+        .process(&[user.owner_ix(VaultInstruction::ReleaseReserve { amount: 81 * USDC })], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InsufficientReserve);
+    test.process(&[user.owner_ix(VaultInstruction::ReleaseReserve { amount: 30 * USDC })], &[&owner])
+        .await
+        .unwrap();
+    test.process(&[user.withdraw_ix(&owner.pubkey(), None, 50 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 0);
+    assert_eq!(vault.reserve_collateral, 50 * USDC);
+    assert_eq!(test.token_balance(&user.vault_usdc).await, 50 * USDC);
+}
+
+#[tokio::test]
+async fn test_vault_total_notional_cap() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 20 * USDC).await;
+    let key = trader.pubkey();
+
+    test.process(
+        &[user.owner_ix(VaultInstruction::SetMaxTotalNotional { max_total_notional: 100 * USDC })],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // owner 与 delegate 共享同一个上限
+    test.process(&[user.lock_margin_ix(&owner.pubkey(), None, 10 * USDC, 60 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let result = test
+        .process(&[user.lock_margin_ix(&key, Some(&key), 10 * USDC, 41 * USDC)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::VaultNotionalLimitExceeded);
+    test.process(&[user.lock_margin_ix(&key, Some(&key), 10 * USDC, 40 * USDC)], &[&trader])
+        .await
+        .unwrap();
+    assert_eq!(test.vault(&user).await.total_notional, 100 * USDC);
+
+    // 平仓释放名义敞口
+    test.process(
+        &[user.unlock_margin_ix(&owner.pubkey(), None, 10 * USDC, 0, -((60 * USDC) as i64))],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(test.vault(&user).await.total_notional, 40 * USDC);
+
+    let result = test
+        .process(
+            &[user.owner_ix(VaultInstruction::SetMaxTotalNotional {
+                max_total_notional: u64::MAX,
+            })],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::InvalidMaxNotional);
+}
+
+#[tokio::test]
+async fn test_missed_heartbeat_blocks_new_positions() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 50 * USDC).await;
+    let key = trader.pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(&[user.heartbeat_delegate_ix(&key, PERM_TRADE, expiry_slot, 60)], &[&owner])
+        .await
+        .unwrap();
+    let last_heartbeat_at = test.delegate(&user, &key).await.last_heartbeat_at;
+
+    let lock = [user.lock_margin_ix(&key, Some(&key), 10 * USDC, 10 * USDC)];
+    test.process(&lock, &[&trader]).await.unwrap();
+
+    let mut clock = test.clock().await;
+    clock.unix_timestamp = last_heartbeat_at + 61;
+    test.context.set_sysvar(&clock);
+    let result = test.process(&lock, &[&trader]).await;
+    assert_vault_error(result, VaultError::HeartbeatMissed);
+
+    // 心跳超时仍可平仓
+    test.process(&[user.unlock_margin_ix(&key, Some(&key), 10 * USDC, 0, -((10 * USDC) as i64))], &[&trader])
+        .await
+        .unwrap();
+
+    test.process(&[user.heartbeat_ix(&key)], &[&trader]).await.unwrap();
+    assert_eq!(test.delegate(&user, &key).await.last_heartbeat_at, last_heartbeat_at + 61);
+    test.process(&lock, &[&trader]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_program_allowlist_restricts_callers() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 50 * USDC).await;
+    let key = trader.pubkey();
+
+    let too_many = vec![Pubkey::new_unique(); MAX_ALLOWED_PROGRAMS + 1];
+    let result = test.process(&[user.set_delegate_programs_ix(&key, too_many)], &[&owner]).await;
+    assert_vault_error(result, VaultError::TooManyAllowedPrograms);
+
+    test.process(&[user.set_delegate_programs_ix(&key, vec![Pubkey::new_unique()])], &[&owner])
+        .await
+        .unwrap();
+
+    // 设置白名单后必须提供 instructions sysvar，直接调用的顶层程序不在白名单内
+    let lock = user.lock_margin_ix(&key, Some(&key), 10 * USDC, 10 * USDC);
+    let scoped_lock = [user.with_instructions_sysvar(lock.clone())];
+    assert!(test.process(&[lock], &[&trader]).await.is_err());
+    let result = test.process(&scoped_lock, &[&trader]).await;
+    assert_vault_error(result, VaultError::ProgramNotAllowed);
+
+    test.process(&[user.set_delegate_programs_ix(&key, vec![vault_program::id()])], &[&owner])
+        .await
+        .unwrap();
+    test.process(&scoped_lock, &[&trader]).await.unwrap();
+
+    // owner 代为平仓不受白名单限制
+    test.process(&[user.set_delegate_programs_ix(&key, vec![Pubkey::new_unique()])], &[&owner])
+        .await
+        .unwrap();
+    test.process(
+        &[user.unlock_margin_ix(&owner.pubkey(), Some(&key), 10 * USDC, 0, -((10 * USDC) as i64))],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert_eq!(test.delegate(&user, &key).await.allocated_locked, 0);
+}
+
+#[tokio::test]
+async fn test_market_allowlist_restricts_delegate_locks() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let trader = add_funded_trader(&mut test, &user, 50 * USDC).await;
+    let key = trader.pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(&[user.market_delegate_ix(&key, PERM_TRADE, expiry_slot, 1 << 2)], &[&owner])
+        .await
+        .unwrap();
+    assert_eq!(test.delegate(&user, &key).await.allowed_markets, 1 << 2);
+
+    let result = test
+        .process(&[user.lock_market_margin_ix(&key, Some(&key), USDC, USDC, 1)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::MarketNotAllowed);
+    test.process(&[user.lock_market_margin_ix(&key, Some(&key), USDC, USDC, 2)], &[&trader])
+        .await
+        .unwrap();
+
+    // owner 开仓不受 delegate 市场白名单限制
+    test.process(&[user.lock_market_margin_ix(&owner.pubkey(), None, USDC, USDC, 1)], &[&owner])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_delegate_deposit_within_window_limit() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let key = api_key.pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[
+            user.deposit_ix(10 * USDC),
+            user.upsert_delegate_ix(&key, PERM_DEPOSIT, 1_000 * USDC, expiry_slot, 0),
+            user.approve_deposit_authority_ix(100 * USDC),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 未设置上限时禁止代存款
+    let result = test.process(&[user.delegate_deposit_ix(&key, USDC)], &[&api_key]).await;
+    assert_vault_error(result, VaultError::DepositLimitExceeded);
+
+    test.process(&[user.set_delegate_deposit_limit_ix(&key, 20 * USDC, 1_000)], &[&owner])
+        .await
+        .unwrap();
+    let balance = test.token_balance(&user.usdc).await;
+    test.process(&[user.delegate_deposit_ix(&key, 15 * USDC)], &[&api_key])
+        .await
+        .unwrap();
+    assert_eq!(test.token_balance(&user.usdc).await, balance - 15 * USDC);
+    assert_eq!(test.vault(&user).await.free_collateral, 25 * USDC);
+
+    // 超过窗口上限
+    let result = test.process(&[user.delegate_deposit_ix(&key, 6 * USDC)], &[&api_key]).await;
+    assert_vault_error(result, VaultError::DepositLimitExceeded);
+
+    // 没有 PERM_DEPOSIT 的 delegate 不能代存款
+    let trader = add_trader(&mut test, &user, 1_000 * USDC, 0).await;
+    test.process(
+        &[user.set_delegate_deposit_limit_ix(&trader.pubkey(), 20 * USDC, 1_000)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let result = test
+        .process(&[user.delegate_deposit_ix(&trader.pubkey(), USDC)], &[&trader])
+        .await;
+    assert_vault_error(result, VaultError::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_owner_cosign_above_delegate_threshold() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let other = test.create_user(0).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let key = api_key.pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[
+            user.upsert_delegate_ix(&key, PERM_WITHDRAW | PERM_TRANSFER, 1_000 * USDC, expiry_slot, 0),
+            user.delegate_owner_ix(
+                &key,
+                VaultInstruction::SetDelegateCosignThreshold {
+                    delegate_pubkey: key,
+                    threshold: 10 * USDC,
+                },
+            ),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 阈值以内 delegate 可以单独提款
+    test.process(&[user.withdraw_ix(&key, Some(&key), 10 * USDC)], &[&api_key])
+        .await
+        .unwrap();
+
+    // 超过阈值的提款和划转都需要 owner 同时签名
+    let result = test
+        .process(&[user.withdraw_ix(&key, Some(&key), 20 * USDC)], &[&api_key])
+        .await;
+    assert_vault_error(result, VaultError::OwnerCosignRequired);
+    let result = test
+        .process(&[user.transfer_to_vault_ix(&other, &key, Some(&key), 20 * USDC)], &[&api_key])
+        .await;
+    assert_vault_error(result, VaultError::OwnerCosignRequired);
+
+    let mut ix = user.withdraw_ix(&key, Some(&key), 20 * USDC);
+    ix.accounts.push(AccountMeta::new_readonly(user.owner_key(), true));
+    test.process(&[ix], &[&api_key, &owner]).await.unwrap();
+    let mut ix = user.transfer_to_vault_ix(&other, &key, Some(&key), 20 * USDC);
+    ix.accounts.push(AccountMeta::new_readonly(user.owner_key(), true));
+    test.process(&[ix], &[&api_key, &owner]).await.unwrap();
+    assert_eq!(test.vault(&user).await.free_collateral, 50 * USDC);
+    assert_eq!(test.vault(&other).await.free_collateral, 20 * USDC);
+}
+
+#[tokio::test]
+async fn test_withdraw_rejects_forged_delegate_account() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let key = api_key.pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[
+            user.upsert_delegate_ix(&key, PERM_WITHDRAW, 1_000 * USDC, expiry_slot, 0),
+            user.delegate_owner_ix(
+                &key,
+                VaultInstruction::SetDelegateCosignThreshold {
+                    delegate_pubkey: key,
+                    threshold: 10 * USDC,
+                },
+            ),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 复制 DelegateAccount 并去掉 co-sign 阈值，放到非 PDA 地址
+    let mut account = test.raw_account(&user.delegate_pda(&key)).await;
+    let mut forged = DelegateAccount::try_from_slice(&account.data).unwrap();
+    forged.cosign_threshold = 0;
+    account.data = forged.try_to_vec().unwrap();
+    let forged_address = Pubkey::new_unique();
+    test.context.set_account(&forged_address, &account.clone().into());
+
+    let mut ix = user.withdraw_ix(&key, Some(&key), 20 * USDC);
+    ix.accounts[6].pubkey = forged_address;
+    let result = test.process(&[ix.clone()], &[&api_key]).await;
+    assert_instruction_error(result, InstructionError::InvalidSeeds);
+
+    // 不属于本程序的账户同样被拒绝
+    account.owner = Pubkey::new_unique();
+    test.context.set_account(&forged_address, &account.into());
+    let result = test.process(&[ix], &[&api_key]).await;
+    assert_instruction_error(result, InstructionError::IllegalOwner);
+    assert_eq!(test.vault(&user).await.free_collateral, 100 * USDC);
+}
+
+fn assert_instruction_error(
+    result: Result<(), solana_program_test::BanksClientError>,
+    expected: InstructionError,
+) {
+    match result.expect_err("transaction should fail").unwrap() {
+        TransactionError::InstructionError(_, err) => assert_eq!(err, expected),
+        other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+}
@@ -0,0 +1,84 @@
+//! Owner 链下签名许可测试
+//!
+//! relayer 提交 ExecuteOwnerPermit 代 owner 管理 delegate 并支付租金，
+//! nonce 必须等于 UserVault.permit_nonce，执行后递增防重放
+
+mod common;
+
+use common::*;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{VaultError, DELEGATE_LABEL_LEN, PERM_TRADE, REGISTRY_STATUS_REVOKED};
+
+#[tokio::test]
+async fn test_relayer_executes_owner_permits() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let payer = test.payer();
+    let api_key = Keypair::new();
+    let key = api_key.pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    let deadline = test.clock().await.unix_timestamp + 60;
+
+    // owner 不签名交易，relayer 提交许可创建 delegate
+    let upsert = user.upsert_delegate_ix(&key, PERM_TRADE, 1_000 * USDC, expiry_slot, 0);
+    let message = user.owner_permit_message(&upsert, 0, deadline);
+    let execute = user.execute_owner_permit_ix(upsert.clone(), &payer, 0, deadline);
+    test.process(&[ed25519_ix(&user.owner, &message), execute.clone()], &[])
+        .await
+        .unwrap();
+    assert_eq!(test.delegate(&user, &key).await.permissions, PERM_TRADE);
+    assert_eq!(test.vault(&user).await.permit_nonce, 1);
+
+    // 同一许可不能重放
+    let result = test.process(&[ed25519_ix(&user.owner, &message), execute], &[]).await;
+    assert_vault_error(result, VaultError::InvalidPermitNonce);
+
+    // 许可撤销
+    let revoke = user.revoke_delegate_ix(&key);
+    let message = user.owner_permit_message(&revoke, 1, deadline);
+    let execute = user.execute_owner_permit_ix(revoke, &payer, 1, deadline);
+    test.process(&[ed25519_ix(&user.owner, &message), execute], &[])
+        .await
+        .unwrap();
+    assert_eq!(test.registry(&user).await.entries[0].status, REGISTRY_STATUS_REVOKED);
+    assert_eq!(test.vault(&user).await.permit_nonce, 2);
+}
+
+#[tokio::test]
+async fn test_owner_permit_requires_owner_signature() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let payer = test.payer();
+    let key = Keypair::new().pubkey();
+    let expiry_slot = test.slot().await + 10_000;
+    let deadline = test.clock().await.unix_timestamp + 60;
+    let upsert = user.upsert_delegate_ix(&key, PERM_TRADE, 1_000 * USDC, expiry_slot, 0);
+    let message = user.owner_permit_message(&upsert, 0, deadline);
+    let execute = user.execute_owner_permit_ix(upsert.clone(), &payer, 0, deadline);
+
+    // 缺少 Ed25519 验签指令
+    let result = test.process(std::slice::from_ref(&execute), &[]).await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+
+    // 非 owner 签名
+    let result = test
+        .process(&[ed25519_ix(&Keypair::new(), &message), execute.clone()], &[])
+        .await;
+    assert_vault_error(result, VaultError::InvalidSignature);
+
+    // 已过截止时间
+    let expired = deadline - 120;
+    let message = user.owner_permit_message(&upsert, 0, expired);
+    let execute = user.execute_owner_permit_ix(upsert.clone(), &payer, 0, expired);
+    let result = test.process(&[ed25519_ix(&user.owner, &message), execute], &[]).await;
+    assert_vault_error(result, VaultError::PermitExpired);
+
+    // 只允许 UpsertDelegate / RevokeDelegate
+    let mut other = upsert;
+    other.data = user.set_delegate_metadata_ix(&key, [0; DELEGATE_LABEL_LEN], 1).data;
+    let message = user.owner_permit_message(&other, 0, deadline);
+    let execute = user.execute_owner_permit_ix(other, &payer, 0, deadline);
+    let result = test.process(&[ed25519_ix(&user.owner, &message), execute], &[]).await;
+    assert_vault_error(result, VaultError::InvalidPermitInstruction);
+    assert_eq!(test.vault(&user).await.permit_nonce, 0);
+}
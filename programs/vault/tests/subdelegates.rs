@@ -0,0 +1,303 @@
+//! 子 Delegate 测试
+//!
+//! 创建规则、敞口和保证金计入父 delegate、父 delegate 的权限与撤销对子 delegate 的约束
+
+mod common;
+
+use common::*;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{
+    VaultError, VaultInstruction, PERM_SUBDELEGATE, PERM_TRADE, PERM_WITHDRAW,
+    REGISTRY_STATUS_REVOKED,
+};
+
+/// 授权（或更新）owner 直接管理的 delegate
+async fn upsert(
+    test: &mut VaultTest,
+    user: &VaultUser,
+    key: &Pubkey,
+    permissions: u64,
+    max_leverage: u64,
+) {
+    let owner = user.owner.insecure_clone();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[user.upsert_delegate_ix(key, permissions, 1_000 * USDC, expiry_slot, max_leverage)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+}
+
+/// 创建父 delegate（分配 50 USDC）及其子 delegate
+async fn setup(test: &mut VaultTest, user: &VaultUser, child_permissions: u64) -> (Keypair, Keypair) {
+    let owner = user.owner.insecure_clone();
+    let parent = Keypair::new();
+    upsert(test, user, &parent.pubkey(), PERM_TRADE | PERM_WITHDRAW | PERM_SUBDELEGATE, 5).await;
+    test.process(
+        &[user.delegate_owner_ix(
+            &parent.pubkey(),
+            VaultInstruction::AllocateCollateral { delegate_pubkey: parent.pubkey(), amount: 50 * USDC },
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 父 delegate 支付子 delegate 的租金
+    test.fund(&parent.pubkey(), 1_000_000_000).await;
+    let child = Keypair::new();
+    let expiry_slot = test.slot().await + 1_000;
+    test.process(
+        &[user.create_subdelegate_ix(
+            &parent.pubkey(),
+            &child.pubkey(),
+            child_permissions,
+            500 * USDC,
+            expiry_slot,
+            5,
+        )],
+        &[&parent],
+    )
+    .await
+    .unwrap();
+    (parent, child)
+}
+
+fn child_lock(user: &VaultUser, parent: &Keypair, child: &Keypair, margin: u64, notional: u64) -> solana_program::instruction::Instruction {
+    let key = child.pubkey();
+    user.with_parent(user.lock_margin_ix(&key, Some(&key), margin, notional), &parent.pubkey())
+}
+
+#[tokio::test]
+async fn test_subdelegate_trades_against_parent_allocation() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let (parent, child) = setup(&mut test, &user, PERM_TRADE).await;
+    let child_key = child.pubkey();
+
+    let created = test.delegate(&user, &child_key).await;
+    assert_eq!(created.parent, user.delegate_pda(&parent.pubkey()));
+    let registry = test.registry(&user).await;
+    assert_eq!(registry.entries[1].parent, parent.pubkey());
+
+    // 不带父账户无法锁定
+    let result = test
+        .process(&[user.lock_margin_ix(&child_key, Some(&child_key), 10 * USDC, 50 * USDC)], &[&child])
+        .await;
+    assert_vault_error(result, VaultError::InvalidDelegate);
+
+    test.process(&[child_lock(&user, &parent, &child, 10 * USDC, 50 * USDC)], &[&child])
+        .await
+        .unwrap();
+    let parent_account = test.delegate(&user, &parent.pubkey()).await;
+    assert_eq!(parent_account.allocated_collateral, 40 * USDC);
+    assert_eq!(parent_account.allocated_locked, 10 * USDC);
+    assert_eq!(parent_account.used_notional, 50 * USDC);
+    assert_eq!(test.delegate(&user, &child_key).await.used_notional, 50 * USDC);
+
+    let unlock = user.unlock_margin_ix(&child_key, Some(&child_key), 10 * USDC, 5 * USDC as i64, -((50 * USDC) as i64));
+    test.process(&[user.with_parent(unlock, &parent.pubkey())], &[&child])
+        .await
+        .unwrap();
+    let parent_account = test.delegate(&user, &parent.pubkey()).await;
+    assert_eq!(parent_account.allocated_collateral, 55 * USDC);
+    assert_eq!(parent_account.allocated_locked, 0);
+    assert_eq!(parent_account.used_notional, 0);
+    assert_eq!(test.vault(&user).await.delegated_collateral, 55 * USDC);
+}
+
+#[tokio::test]
+async fn test_subdelegate_must_be_subset_of_parent() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let (parent, child) = setup(&mut test, &user, PERM_TRADE).await;
+    let parent_expiry = test.delegate(&user, &parent.pubkey()).await.expiry_slot;
+
+    let cases = [
+        (PERM_TRADE | PERM_SUBDELEGATE, 500 * USDC, parent_expiry, 5, VaultError::InvalidPermissions),
+        (PERM_TRADE, 1_001 * USDC, parent_expiry, 5, VaultError::InvalidMaxNotional),
+        (PERM_TRADE, 500 * USDC, parent_expiry + 1, 5, VaultError::InvalidExpirySlot),
+        (PERM_TRADE, 500 * USDC, parent_expiry, 6, VaultError::InvalidMaxLeverage),
+    ];
+    for (permissions, max_notional, expiry_slot, max_leverage, error) in cases {
+        let key = Keypair::new().pubkey();
+        let result = test
+            .process(
+                &[user.create_subdelegate_ix(
+                    &parent.pubkey(),
+                    &key,
+                    permissions,
+                    max_notional,
+                    expiry_slot,
+                    max_leverage,
+                )],
+                &[&parent],
+            )
+            .await;
+        assert_vault_error(result, error);
+    }
+
+    // 子 delegate 不能继续创建子 delegate
+    let expiry_slot = test.slot().await + 100;
+    let result = test
+        .process(
+            &[user.create_subdelegate_ix(&child.pubkey(), &Keypair::new().pubkey(), PERM_TRADE, USDC, expiry_slot, 5)],
+            &[&child],
+        )
+        .await;
+    assert_vault_error(result, VaultError::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_subdelegate_lock_respects_parent_trade_permission_and_leverage() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let (parent, child) = setup(&mut test, &user, PERM_TRADE).await;
+
+    // 父 delegate 失去 PERM_TRADE 后子 delegate 也不能开仓
+    upsert(&mut test, &user, &parent.pubkey(), PERM_WITHDRAW | PERM_SUBDELEGATE, 5).await;
+    let result = test
+        .process(&[child_lock(&user, &parent, &child, 10 * USDC, 50 * USDC)], &[&child])
+        .await;
+    assert_vault_error(result, VaultError::PermissionDenied);
+
+    // 父 delegate 的杠杆上限低于子 delegate 时按父 delegate 限制
+    upsert(&mut test, &user, &parent.pubkey(), PERM_TRADE | PERM_SUBDELEGATE, 2).await;
+    let result = test
+        .process(&[child_lock(&user, &parent, &child, 10 * USDC, 50 * USDC)], &[&child])
+        .await;
+    assert_vault_error(result, VaultError::LeverageExceeded);
+    test.process(&[child_lock(&user, &parent, &child, 10 * USDC, 20 * USDC)], &[&child])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_revoking_parent_invalidates_children() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let (parent, child) = setup(&mut test, &user, PERM_TRADE | PERM_WITHDRAW).await;
+    let child_key = child.pubkey();
+    test.process(&[child_lock(&user, &parent, &child, 10 * USDC, 50 * USDC)], &[&child])
+        .await
+        .unwrap();
+
+    // 子 delegate 提款同样需要父 delegate 有效
+    let withdraw = user.withdraw_ix(&child_key, Some(&child_key), USDC);
+    test.process(&[user.with_parent(withdraw, &parent.pubkey())], &[&child])
+        .await
+        .unwrap();
+
+    // 有子 delegate 时不能轮换父 delegate
+    let result = test
+        .process(&[user.rotate_delegate_ix(&parent.pubkey(), &Keypair::new().pubkey())], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::DelegateHasSubdelegates);
+
+    test.process(&[user.revoke_delegate_ix(&parent.pubkey())], &[&owner])
+        .await
+        .unwrap();
+    let registry = test.registry(&user).await;
+    assert!(registry.entries.iter().all(|entry| entry.status == REGISTRY_STATUS_REVOKED));
+
+    let result = test
+        .process(&[child_lock(&user, &parent, &child, USDC, USDC)], &[&child])
+        .await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+    let withdraw = user.withdraw_ix(&child_key, Some(&child_key), USDC);
+    let result = test
+        .process(&[user.with_parent(withdraw, &parent.pubkey())], &[&child])
+        .await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+
+    // owner 仍可代为结算子 delegate 的仓位
+    let unlock = user.unlock_margin_ix(&owner.pubkey(), Some(&child_key), 10 * USDC, 0, -((50 * USDC) as i64));
+    test.process(&[user.with_parent(unlock, &parent.pubkey())], &[&owner])
+        .await
+        .unwrap();
+    let parent_account = test.delegate(&user, &parent.pubkey()).await;
+    assert_eq!(parent_account.allocated_locked, 0);
+    assert_eq!(parent_account.used_notional, 0);
+}
+
+#[tokio::test]
+async fn test_regranting_subdelegate_detaches_from_parent() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let (parent, child) = setup(&mut test, &user, PERM_TRADE).await;
+    let child_key = child.pubkey();
+    test.process(&[child_lock(&user, &parent, &child, 10 * USDC, 50 * USDC)], &[&child])
+        .await
+        .unwrap();
+
+    // 仓位占用父 delegate 的额度，平仓前不能转为直接授权
+    let expiry_slot = test.slot().await + 1_000;
+    let result = test
+        .process(
+            &[user.upsert_delegate_ix(&child_key, PERM_TRADE, 1_000 * USDC, expiry_slot, 0)],
+            &[&owner],
+        )
+        .await;
+    assert_vault_error(result, VaultError::DelegateHasOpenPositions);
+
+    let unlock = user.unlock_margin_ix(&child_key, Some(&child_key), 10 * USDC, 0, -((50 * USDC) as i64));
+    test.process(&[user.with_parent(unlock, &parent.pubkey())], &[&child])
+        .await
+        .unwrap();
+    upsert(&mut test, &user, &child_key, PERM_TRADE, 0).await;
+    let detached = test.delegate(&user, &child_key).await;
+    assert_eq!(detached.parent, Pubkey::default());
+    assert_eq!(detached.parent_subdelegate_epoch, 0);
+
+    // 撤销原父 delegate 不再影响该 key
+    test.process(&[user.revoke_delegate_ix(&parent.pubkey())], &[&owner])
+        .await
+        .unwrap();
+    test.process(
+        &[user.delegate_owner_ix(
+            &child_key,
+            VaultInstruction::AllocateCollateral { delegate_pubkey: child_key, amount: 10 * USDC },
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    test.process(&[user.lock_margin_ix(&child_key, Some(&child_key), 10 * USDC, 20 * USDC)], &[&child])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_parent_missed_heartbeat_blocks_subdelegate_locks() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let (parent, child) = setup(&mut test, &user, PERM_TRADE).await;
+    let expiry_slot = test.delegate(&user, &parent.pubkey()).await.expiry_slot;
+    test.process(
+        &[user.heartbeat_delegate_ix(
+            &parent.pubkey(),
+            PERM_TRADE | PERM_WITHDRAW | PERM_SUBDELEGATE,
+            expiry_slot,
+            60,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let last_heartbeat_at = test.delegate(&user, &parent.pubkey()).await.last_heartbeat_at;
+
+    let mut clock = test.clock().await;
+    clock.unix_timestamp = last_heartbeat_at + 61;
+    test.context.set_sysvar(&clock);
+    let lock = [child_lock(&user, &parent, &child, 10 * USDC, 50 * USDC)];
+    let result = test.process(&lock, &[&child]).await;
+    assert_vault_error(result, VaultError::HeartbeatMissed);
+
+    test.process(&[user.heartbeat_ix(&parent.pubkey())], &[&parent]).await.unwrap();
+    test.process(&lock, &[&child]).await.unwrap();
+}
@@ -0,0 +1,314 @@
+//! Vault Program Basic Flow Tests
+//!
+//! 测试基本流程：
+//! 1. 初始化 Global Config
+//! 2. 创建 User Vault
+//! 3. 存款
+//! 4. 提款
+//! 5. 创建 Delegate / Delegate 提款 / 撤销 Delegate
+//! 6. 迁移旧版本账户
+//! 7. 一次性撤销全部 Delegate
+//! 8. 第三方代存款
+//! 9. Vault 间划转
+
+mod common;
+
+use borsh::BorshSerialize;
+use common::*;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{
+    GlobalConfig, UserVault, VaultError, VaultInstruction, PERM_TRADE, PERM_TRANSFER,
+    PERM_WITHDRAW,
+};
+
+#[tokio::test]
+async fn test_initialize_and_create_vault() {
+    let mut test = VaultTest::start().await;
+
+    let config: GlobalConfig = test.account(&global_config_pda()).await;
+    assert_eq!(config.admin, test.payer());
+    assert_eq!(config.usdc_mint, test.usdc_mint.pubkey());
+
+    let user = test.create_user(100 * USDC).await;
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.owner, user.owner_key());
+    assert_eq!(vault.usdc_vault, user.vault_usdc);
+    assert_eq!(vault.free_collateral, 0);
+    assert_eq!(test.token_balance(&user.vault_usdc).await, 0);
+}
+
+#[tokio::test]
+async fn test_deposit_and_withdraw() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+
+    test.process(&[user.deposit_ix(60 * USDC)], &[&owner]).await.unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.total_deposit, 60 * USDC);
+    assert_eq!(vault.free_collateral, 60 * USDC);
+    assert_eq!(test.token_balance(&user.vault_usdc).await, 60 * USDC);
+    assert_eq!(test.token_balance(&user.usdc).await, 40 * USDC);
+
+    test.process(&[user.withdraw_ix(&owner.pubkey(), None, 25 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 35 * USDC);
+    assert_eq!(vault.total_withdrawn, 25 * USDC);
+    assert_eq!(test.token_balance(&user.vault_usdc).await, 35 * USDC);
+    assert_eq!(test.token_balance(&user.usdc).await, 65 * USDC);
+
+    // 超过 free_collateral 的提款被拒绝
+    let result = test
+        .process(&[user.withdraw_ix(&owner.pubkey(), None, 36 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InsufficientFreeCollateral);
+}
+
+#[tokio::test]
+async fn test_delegate_withdraw_and_revoke() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let expiry_slot = test.slot().await + 1_000;
+
+    test.process(
+        &[user.upsert_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, 1_000 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let delegate = test.delegate(&user, &api_key.pubkey()).await;
+    assert!(delegate.is_active);
+    assert_eq!(delegate.owner, user.owner_key());
+    assert_eq!(delegate.vault, user.vault);
+
+    // delegate 提款，资金只能进入 owner 的账户
+    test.process(
+        &[user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), 10 * USDC)],
+        &[&api_key],
+    )
+    .await
+    .unwrap();
+    assert_eq!(test.vault(&user).await.free_collateral, 90 * USDC);
+    assert_eq!(test.token_balance(&user.usdc).await, 110 * USDC);
+
+    // 撤销后不能再提款
+    test.process(&[user.revoke_delegate_ix(&api_key.pubkey())], &[&owner])
+        .await
+        .unwrap();
+    assert!(!test.delegate(&user, &api_key.pubkey()).await.is_active);
+    let result = test
+        .process(
+            &[user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), 10 * USDC)],
+            &[&api_key],
+        )
+        .await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+}
+
+#[tokio::test]
+async fn test_delegate_without_withdraw_permission() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let expiry_slot = test.slot().await + 1_000;
+
+    test.process(
+        &[user.upsert_delegate_ix(&api_key.pubkey(), PERM_TRADE, 1_000 * USDC, expiry_slot, 0)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let result = test
+        .process(
+            &[user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), 10 * USDC)],
+            &[&api_key],
+        )
+        .await;
+    assert_vault_error(result, VaultError::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_vault_layout() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+
+    // 旧版本按 208 字节创建 UserVault（末尾 8 字节为 0），无法按当前布局读取
+    let mut account = test.raw_account(&user.vault).await;
+    account.data.extend_from_slice(&[0; 8]);
+    test.context.set_account(&user.vault, &account.into());
+    let result = test.process(&[user.deposit_ix(USDC)], &[&owner]).await;
+    assert!(result.is_err());
+
+    let payer = test.payer();
+    test.process(&[migrate_account_ix(&user.vault, &payer)], &[]).await.unwrap();
+    let account = test.raw_account(&user.vault).await;
+    assert_eq!(account.data.len(), UserVault::SIZE);
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.version, UserVault::VERSION);
+    assert_eq!(vault.owner, user.owner_key());
+
+    // 迁移后可以正常使用，重复迁移不做修改
+    test.process(&[user.deposit_ix(USDC)], &[&owner]).await.unwrap();
+    test.process(&[migrate_account_ix(&user.vault, &payer)], &[]).await.unwrap();
+    assert_eq!(test.vault(&user).await.free_collateral, USDC);
+
+    // 不支持迁移其他类型的账户
+    let result = test
+        .process(&[migrate_account_ix(&global_config_pda(), &payer)], &[])
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_migrate_v1_vault_grows_layout() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+
+    // v1 UserVault 为 200 字节，没有 total_notional 等字段
+    let mut account = test.raw_account(&user.vault).await;
+    account.data.truncate(200);
+    account.data[8] = 1;
+    test.context.set_account(&user.vault, &account.into());
+
+    let payer = test.payer();
+    test.process(&[migrate_account_ix(&user.vault, &payer)], &[]).await.unwrap();
+    let account = test.raw_account(&user.vault).await;
+    assert_eq!(account.data.len(), UserVault::SIZE);
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.version, UserVault::VERSION);
+    assert_eq!(vault.free_collateral, 100 * USDC);
+    assert_eq!(vault.total_notional, 0);
+    assert_eq!(vault.max_total_notional, 0);
+    assert_eq!(vault.rent_payer, owner.pubkey());
+
+    test.process(&[user.withdraw_ix(&owner.pubkey(), None, USDC)], &[&owner])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_revoke_all_delegates() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = Keypair::new();
+    let expiry_slot = test.slot().await + 1_000;
+    let upsert = [user.upsert_delegate_ix(&api_key.pubkey(), PERM_WITHDRAW, 1_000 * USDC, expiry_slot, 0)];
+
+    test.process(&upsert, &[&owner]).await.unwrap();
+    test.process(&[user.revoke_all_delegates_ix()], &[&owner])
+        .await
+        .unwrap();
+    assert_eq!(test.vault(&user).await.delegate_epoch, 1);
+
+    // 旧代数的 delegate 全部失效
+    let result = test
+        .process(
+            &[user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), USDC)],
+            &[&api_key],
+        )
+        .await;
+    assert_vault_error(result, VaultError::DelegateExpired);
+
+    // 重新 UpsertDelegate 后恢复
+    test.process(&upsert, &[&owner]).await.unwrap();
+    assert_eq!(test.delegate(&user, &api_key.pubkey()).await.delegate_epoch, 1);
+    test.process(
+        &[user.withdraw_ix(&api_key.pubkey(), Some(&api_key.pubkey()), USDC)],
+        &[&api_key],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_deposit_for_third_party() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(10 * USDC).await;
+    let sponsor = test.create_user(100 * USDC).await;
+    let sponsor_owner = sponsor.owner.insecure_clone();
+    let sponsor_balance = test.token_balance(&sponsor.usdc).await;
+
+    test.process(&[user.deposit_for_ix(&sponsor, 25 * USDC)], &[&sponsor_owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 35 * USDC);
+    assert_eq!(vault.total_deposit, 35 * USDC);
+    assert_eq!(test.token_balance(&sponsor.usdc).await, sponsor_balance - 25 * USDC);
+    assert_eq!(test.vault(&sponsor).await.free_collateral, 0);
+
+    // owner 参数必须与 vault 一致
+    let mut wrong_owner = user.deposit_for_ix(&sponsor, USDC);
+    wrong_owner.data = VaultInstruction::DepositFor { owner: sponsor.owner_key(), amount: USDC }
+        .try_to_vec()
+        .unwrap();
+    let result = test.process(&[wrong_owner], &[&sponsor_owner]).await;
+    assert_vault_error(result, VaultError::InvalidOwner);
+
+    // vault USDC 账户必须是 vault 记录的账户
+    let mut wrong_usdc = user.deposit_for_ix(&sponsor, USDC);
+    wrong_usdc.accounts[3].pubkey = sponsor.vault_usdc;
+    let result = test.process(&[wrong_usdc], &[&sponsor_owner]).await;
+    assert_vault_error(result, VaultError::InvalidTokenAccount);
+}
+
+#[tokio::test]
+async fn test_transfer_between_vaults() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let other = test.create_funded_user(10 * USDC).await;
+    let owner = user.owner.insecure_clone();
+
+    test.process(&[user.transfer_to_vault_ix(&other, &owner.pubkey(), None, 30 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let from = test.vault(&user).await;
+    let to = test.vault(&other).await;
+    assert_eq!(from.free_collateral, 70 * USDC);
+    assert_eq!(from.total_withdrawn, 30 * USDC);
+    assert_eq!(to.free_collateral, 40 * USDC);
+    assert_eq!(to.total_deposit, 40 * USDC);
+    assert_eq!(test.token_balance(&other.vault_usdc).await, 40 * USDC);
+
+    // 余额不足
+    let result = test
+        .process(&[user.transfer_to_vault_ix(&other, &owner.pubkey(), None, 71 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InsufficientFreeCollateral);
+
+    // delegate 需要 PERM_TRANSFER
+    let expiry_slot = test.slot().await + 1_000;
+    let trader = Keypair::new();
+    let mover = Keypair::new();
+    test.process(
+        &[
+            user.upsert_delegate_ix(&trader.pubkey(), PERM_TRADE, 1_000 * USDC, expiry_slot, 0),
+            user.upsert_delegate_ix(&mover.pubkey(), PERM_TRANSFER, 1_000 * USDC, expiry_slot, 0),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    let ix = user.transfer_to_vault_ix(&other, &trader.pubkey(), Some(&trader.pubkey()), USDC);
+    let result = test.process(&[ix], &[&trader]).await;
+    assert_vault_error(result, VaultError::PermissionDenied);
+
+    let ix = user.transfer_to_vault_ix(&other, &mover.pubkey(), Some(&mover.pubkey()), 5 * USDC);
+    test.process(&[ix], &[&mover]).await.unwrap();
+    assert_eq!(test.vault(&other).await.free_collateral, 45 * USDC);
+
+    // 不能划转给自己
+    let result = test
+        .process(&[user.transfer_to_vault_ix(&user, &owner.pubkey(), None, USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::InvalidVaultAccount);
+}
@@ -0,0 +1,118 @@
+//! 提款请求测试
+//!
+//! free_collateral 不足时排队，解锁和存款释放的资金优先预留，
+//! 任何人可执行支付，owner 可取消
+
+mod common;
+
+use common::*;
+use solana_sdk::signature::{Keypair, Signer};
+use vault_program::{VaultError, VaultInstruction, PERM_TRADE};
+
+/// 创建 PERM_TRADE delegate，分配 `allocation` 并锁定 `locked`
+async fn add_locked_trader(
+    test: &mut VaultTest,
+    user: &VaultUser,
+    allocation: u64,
+    locked: u64,
+) -> Keypair {
+    let api_key = Keypair::new();
+    let key = api_key.pubkey();
+    let owner = user.owner.insecure_clone();
+    let expiry_slot = test.slot().await + 10_000;
+    test.process(
+        &[
+            user.upsert_delegate_ix(&key, PERM_TRADE, 1_000 * USDC, expiry_slot, 0),
+            user.delegate_owner_ix(
+                &key,
+                VaultInstruction::AllocateCollateral { delegate_pubkey: key, amount: allocation },
+            ),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    test.process(&[user.lock_margin_ix(&key, Some(&key), locked, locked)], &[&api_key])
+        .await
+        .unwrap();
+    api_key
+}
+
+#[tokio::test]
+async fn test_withdrawal_request_earmarks_released_margin() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let api_key = add_locked_trader(&mut test, &user, 60 * USDC, 50 * USDC).await;
+    let key = api_key.pubkey();
+
+    // 请求金额不能超过 free + locked，未锁定的分配额度不计入
+    let result = test.process(&[user.request_withdrawal_ix(91 * USDC)], &[&owner]).await;
+    assert_vault_error(result, VaultError::InvalidAmount);
+
+    // free_collateral 立即预留
+    test.process(&[user.request_withdrawal_ix(80 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 0);
+    assert_eq!(vault.earmarked_collateral, 40 * USDC);
+    assert_eq!(vault.withdrawal_requested, 40 * USDC);
+
+    // 同一时间只能有一个请求
+    let result = test.process(&[user.request_withdrawal_ix(USDC)], &[&owner]).await;
+    assert_vault_error(result, VaultError::WithdrawalAlreadyPending);
+
+    // 解锁释放的额度优先预留，delegate 不能再锁定
+    test.process(&[user.unlock_margin_ix(&key, Some(&key), 50 * USDC, 0, 0)], &[&api_key])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.earmarked_collateral, 80 * USDC);
+    assert_eq!(vault.withdrawal_requested, 0);
+    assert_eq!(test.delegate(&user, &key).await.allocated_collateral, 20 * USDC);
+
+    // 任何人都可以执行，支付到 owner 的 USDC 账户并关闭请求
+    let balance = test.token_balance(&user.usdc).await;
+    test.process(&[user.execute_withdrawal_ix()], &[]).await.unwrap();
+    assert_eq!(test.token_balance(&user.usdc).await, balance + 80 * USDC);
+    assert!(!test.account_exists(&withdrawal_request_pda(&user.owner_key())).await);
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.earmarked_collateral, 0);
+    assert_eq!(vault.total_withdrawn, 80 * USDC);
+}
+
+#[tokio::test]
+async fn test_withdrawal_request_earmarks_deposits_and_can_be_cancelled() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    test.process(&[user.deposit_ix(50 * USDC)], &[&owner]).await.unwrap();
+    add_locked_trader(&mut test, &user, 30 * USDC, 30 * USDC).await;
+
+    test.process(&[user.request_withdrawal_ix(40 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    assert_eq!(test.vault(&user).await.earmarked_collateral, 20 * USDC);
+
+    // 存款同样优先预留
+    test.process(&[user.deposit_ix(25 * USDC)], &[&owner]).await.unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.earmarked_collateral, 40 * USDC);
+    assert_eq!(vault.free_collateral, 5 * USDC);
+
+    // 取消后预留资金退回 free_collateral，可以重新请求
+    test.process(&[user.cancel_withdrawal_request_ix()], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.earmarked_collateral, 0);
+    assert_eq!(vault.withdrawal_requested, 0);
+    assert_eq!(vault.free_collateral, 45 * USDC);
+    assert!(!test.account_exists(&withdrawal_request_pda(&user.owner_key())).await);
+
+    test.process(&[user.request_withdrawal_ix(10 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    assert_eq!(test.vault(&user).await.earmarked_collateral, 10 * USDC);
+}
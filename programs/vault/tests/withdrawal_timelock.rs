@@ -0,0 +1,115 @@
+//! 大额提款时间锁测试
+//!
+//! 超过阈值的提款和划转必须排队等待，owner 可在窗口内取消；
+//! 放宽时间锁需等待当前延迟后才生效
+
+mod common;
+
+use common::*;
+use vault_program::{VaultError, VaultInstruction};
+
+#[tokio::test]
+async fn test_timelocked_withdrawal_schedule_execute_and_cancel() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let other = test.create_user(0).await;
+    let owner = user.owner.insecure_clone();
+    test.process(
+        &[user.owner_ix(VaultInstruction::SetWithdrawalTimelock {
+            threshold: 10 * USDC,
+            delay_slots: 100,
+        })],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // 超过阈值的直接提款和划转都被拒绝
+    let result = test
+        .process(&[user.withdraw_ix(&user.owner_key(), None, 20 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::WithdrawalTimelockRequired);
+    let result = test
+        .process(&[user.transfer_to_vault_ix(&other, &user.owner_key(), None, 20 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::WithdrawalTimelockRequired);
+
+    // 阈值以内不受影响
+    test.process(&[user.withdraw_ix(&user.owner_key(), None, 10 * USDC)], &[&owner])
+        .await
+        .unwrap();
+
+    test.process(&[user.schedule_withdrawal_ix(30 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 60 * USDC);
+    assert_eq!(vault.timelocked_collateral, 30 * USDC);
+
+    let result = test.process(&[user.schedule_withdrawal_ix(USDC)], &[&owner]).await;
+    assert_vault_error(result, VaultError::PendingWithdrawalExists);
+    let result = test.process(&[user.execute_pending_withdrawal_ix()], &[]).await;
+    assert_vault_error(result, VaultError::TimelockNotExpired);
+
+    // owner 取消后资金退回 free_collateral
+    test.process(&[user.cancel_pending_withdrawal_ix()], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.free_collateral, 90 * USDC);
+    assert_eq!(vault.timelocked_collateral, 0);
+    assert!(!test.account_exists(&pending_withdrawal_pda(&user.owner_key())).await);
+
+    // 到期后任何人都可以执行
+    test.process(&[user.schedule_withdrawal_ix(30 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let slot = test.slot().await;
+    test.context.warp_to_slot(slot + 101).unwrap();
+    let balance = test.token_balance(&user.usdc).await;
+    test.process(&[user.execute_pending_withdrawal_ix()], &[]).await.unwrap();
+    assert_eq!(test.token_balance(&user.usdc).await, balance + 30 * USDC);
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.timelocked_collateral, 0);
+    assert_eq!(vault.free_collateral, 60 * USDC);
+    assert!(!test.account_exists(&pending_withdrawal_pda(&user.owner_key())).await);
+}
+
+#[tokio::test]
+async fn test_weakening_withdrawal_timelock_is_delayed() {
+    let mut test = VaultTest::start().await;
+    let user = test.create_funded_user(100 * USDC).await;
+    let owner = user.owner.insecure_clone();
+    let set_timelock = |threshold, delay_slots| {
+        user.owner_ix(VaultInstruction::SetWithdrawalTimelock { threshold, delay_slots })
+    };
+    test.process(&[set_timelock(10 * USDC, 100)], &[&owner]).await.unwrap();
+
+    // 关闭时间锁只会排队，当前设置继续生效
+    test.process(&[set_timelock(0, 0)], &[&owner]).await.unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.withdrawal_timelock_threshold, 10 * USDC);
+    assert_eq!(vault.pending_timelock_threshold, 0);
+    assert_ne!(vault.pending_timelock_effective_slot, 0);
+    let result = test
+        .process(&[user.withdraw_ix(&user.owner_key(), None, 20 * USDC)], &[&owner])
+        .await;
+    assert_vault_error(result, VaultError::WithdrawalTimelockRequired);
+
+    // 再次收紧立即生效并取消排队的放宽
+    test.process(&[set_timelock(5 * USDC, 100)], &[&owner]).await.unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.withdrawal_timelock_threshold, 5 * USDC);
+    assert_eq!(vault.pending_timelock_effective_slot, 0);
+
+    // 排队的放宽在当前延迟之后生效
+    test.process(&[set_timelock(50 * USDC, 100)], &[&owner]).await.unwrap();
+    let slot = test.slot().await;
+    test.context.warp_to_slot(slot + 101).unwrap();
+    test.process(&[user.withdraw_ix(&user.owner_key(), None, 20 * USDC)], &[&owner])
+        .await
+        .unwrap();
+    let vault = test.vault(&user).await;
+    assert_eq!(vault.withdrawal_timelock_threshold, 50 * USDC);
+    assert_eq!(vault.pending_timelock_effective_slot, 0);
+}
@@ -71,6 +71,40 @@ const VaultInstruction = {
   RenounceAdmin: 9,
   FreezeVault: 10,
   UnfreezeVault: 11,
+  MigrateAccount: 12,
+  SetLossLimit: 13,
+  ResumeTrading: 14,
+  SetMaxLeverage: 15,
+  AllocateCollateral: 16,
+  ReclaimCollateral: 17,
+  MoveToReserve: 18,
+  ReleaseReserve: 19,
+  SetMaxTotalNotional: 20,
+  RevokeAllDelegates: 21,
+  SetMaxDelegatesPerVault: 22,
+  SetDelegateMetadata: 23,
+  RotateDelegate: 24,
+  SelfRevokeDelegate: 25,
+  CreateSubDelegate: 26,
+  PauseDelegate: 27,
+  ResumeDelegate: 28,
+  Heartbeat: 29,
+  SetDelegatePrograms: 30,
+  LockMarginWithIntent: 31,
+  WithdrawWithIntent: 32,
+  ExecuteOwnerPermit: 33,
+  DepositFor: 34,
+  SetDelegateDepositLimit: 35,
+  DelegateDeposit: 36,
+  TransferBetweenVaults: 37,
+  RequestWithdrawal: 38,
+  ExecuteWithdrawal: 39,
+  CancelWithdrawalRequest: 40,
+  SetWithdrawalTimelock: 41,
+  ScheduleWithdrawal: 42,
+  ExecutePendingWithdrawal: 43,
+  CancelPendingWithdrawal: 44,
+  SetDelegateCosignThreshold: 45,
 } as const;
 
 // 权限定义
@@ -101,17 +135,36 @@ class UpsertDelegateData {
   permissions: bigint;
   max_notional: bigint;
   expiry_slot: bigint;
+  max_leverage: bigint;
+  label: Uint8Array;
+  not_before_slot: bigint;
+  expiry_unix_ts: bigint;
+  heartbeat_interval_secs: bigint;
+  allowed_markets: bigint;
 
   constructor(props: {
     delegate_pubkey: PublicKey;
     permissions: bigint;
     max_notional: bigint;
     expiry_slot: bigint;
+    max_leverage?: bigint;
+    label?: string;
+    not_before_slot?: bigint;
+    expiry_unix_ts?: bigint;
+    heartbeat_interval_secs?: bigint;
+    allowed_markets?: bigint;
   }) {
     this.delegate_pubkey = props.delegate_pubkey.toBytes();
     this.permissions = props.permissions;
     this.max_notional = props.max_notional;
     this.expiry_slot = props.expiry_slot;
+    this.max_leverage = props.max_leverage ?? 0n;
+    this.label = new Uint8Array(32);
+    this.label.set(Buffer.from(props.label ?? '', 'utf8').subarray(0, 32));
+    this.not_before_slot = props.not_before_slot ?? 0n;
+    this.expiry_unix_ts = props.expiry_unix_ts ?? 0n;
+    this.heartbeat_interval_secs = props.heartbeat_interval_secs ?? 0n;
+    this.allowed_markets = props.allowed_markets ?? 0n;
   }
 }
 
@@ -162,6 +215,12 @@ const upsertDelegateSchema = new Map([
         ['permissions', 'u64'],
         ['max_notional', 'u64'],
         ['expiry_slot', 'u64'],
+        ['max_leverage', 'u64'],
+        ['label', [32]],
+        ['not_before_slot', 'u64'],
+        ['expiry_unix_ts', 'u64'], // i64，非负值编码相同
+        ['heartbeat_interval_secs', 'u64'],
+        ['allowed_markets', 'u128'],
       ],
     },
   ],
@@ -389,6 +448,12 @@ async function runTests() {
 
     console.log(`   Delegate PDA: ${delegatePDA.toBase58()}\n`);
 
+    // Delegate Registry PDA
+    const [registryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('registry'), ownerKeypair.publicKey.toBuffer()],
+      PROGRAM_ID
+    );
+
     // 获取当前 slot
     const currentSlot = await connection.getSlot();
     const expirySlot = BigInt(currentSlot) + 100_000n; // ~1天后过期
@@ -410,6 +475,7 @@ async function runTests() {
         { pubkey: ownerKeypair.publicKey, isSigner: true, isWritable: true },
         { pubkey: globalConfigPDA, isSigner: false, isWritable: false },
         { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: registryPDA, isSigner: false, isWritable: true },
       ],
       programId: PROGRAM_ID,
       data: Buffer.from(upsertInstructionData),
@@ -524,6 +590,11 @@ async function runTests() {
       PROGRAM_ID
     );
 
+    const [registryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('registry'), ownerKeypair.publicKey.toBuffer()],
+      PROGRAM_ID
+    );
+
     const revokeData = new RevokeDelegateData(apiKey.publicKey);
     const revokeInstructionData = borsh.serialize(revokeDelegateSchema, revokeData);
 
@@ -533,6 +604,7 @@ async function runTests() {
         { pubkey: vaultPDA, isSigner: false, isWritable: true },
         { pubkey: ownerKeypair.publicKey, isSigner: true, isWritable: false },
         { pubkey: globalConfigPDA, isSigner: false, isWritable: false },
+        { pubkey: registryPDA, isSigner: false, isWritable: true },
       ],
       programId: PROGRAM_ID,
       data: Buffer.from(revokeInstructionData),